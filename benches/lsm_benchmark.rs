@@ -1,8 +1,10 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use mini_lsm::DB;
+use tempfile::TempDir;
 
 pub fn lsm_benchmark_small_values(c: &mut Criterion) {
-    let mut kvstore = DB::new("/tmp/log.txt").expect("Failed to create a new DB");
+    let temp_dir = TempDir::new().unwrap();
+    let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
     let mut group = c.benchmark_group("lsm-benchmarks");
     group.throughput(criterion::Throughput::Elements(1));
     group.bench_function("insert_or_update", |b| {
@@ -17,5 +19,34 @@ pub fn lsm_benchmark_small_values(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, lsm_benchmark_small_values);
+/// Scans over a memtable-only DB. Today every DB is memtable-only, so this is the
+/// baseline the scan path must keep once SSTs and merging iterators are added.
+pub fn lsm_benchmark_scan(c: &mut Criterion) {
+    let temp_dir = TempDir::new().unwrap();
+    let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+    let count: u32 = 100_000;
+    for i in 0..count {
+        kvstore
+            .insert_or_update(&i.to_be_bytes(), &[0u8; 128])
+            .expect("Insert failed");
+    }
+
+    let scan_len: u32 = 100;
+    let mut group = c.benchmark_group("lsm-benchmarks");
+    group.throughput(criterion::Throughput::Elements(scan_len as u64));
+    group.bench_function("scan_memtable_only", |b| {
+        b.iter(|| {
+            let start = rand::random::<u32>() % (count - scan_len);
+            let end = start + scan_len;
+            let scanned = kvstore
+                .scan(&start.to_be_bytes()[..]..&end.to_be_bytes()[..])
+                .expect("Scan failed")
+                .count();
+            assert_eq!(scanned, scan_len as usize);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lsm_benchmark_small_values, lsm_benchmark_scan);
 criterion_main!(benches);
@@ -0,0 +1,146 @@
+//! Measures how long `DB::new` takes to recover WALs of different sizes, and
+//! how much memory recovery needs at its peak.
+//!
+//! Peak memory is deterministic, so it is checked against a fixed budget and
+//! the benchmark fails when recovery starts to need more. Recovery time depends
+//! on the machine, so its threshold is opt-in: set
+//! `MINI_LSM_RECOVERY_MIN_MIB_PER_SEC` to fail when recovery is slower than that.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mini_lsm::{write_batch::WriteBatch, DB};
+use tempfile::TempDir;
+
+/// An allocator that keeps track of the peak number of bytes allocated.
+struct PeakAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
+const MIB: usize = 1024 * 1024;
+
+/// The sizes of the WALs to recover.
+const WAL_SIZES: [usize; 3] = [MIB, 8 * MIB, 32 * MIB];
+
+/// The size of the values written to the WALs.
+const VALUE_SIZE: usize = 1024;
+
+/// The number of entries per write batch.
+const BATCH_SIZE: usize = 16;
+
+/// The peak memory recovery may use, as a multiple of the WAL size. Everything
+/// recovered is kept in the memtable, so this mostly bounds the memtable overhead.
+const MAX_PEAK_MEMORY_RATIO: f64 = 2.0;
+
+/// Fills a DB at `path` until its WAL is about `wal_size` bytes long.
+fn build_wal(path: &Path, wal_size: usize) {
+    let kvstore = DB::new(path).expect("Failed to create a new DB");
+    let mut written = 0u64;
+    while written < wal_size as u64 {
+        let mut wb = WriteBatch::new();
+        for _ in 0..BATCH_SIZE {
+            let key: Vec<u8> = (0..16).map(|_| rand::random::<u8>()).collect();
+            let value: Vec<u8> = (0..VALUE_SIZE).map(|_| rand::random::<u8>()).collect();
+            wb.insert_or_update(&key, &value);
+        }
+        kvstore.write(&wb).expect("Write failed");
+        written += wb.as_bytes().len() as u64;
+    }
+}
+
+/// Recovers the DB at `path` once, and returns the peak memory allocated while doing so.
+fn measure_peak_memory(path: &Path) -> usize {
+    let baseline = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let kvstore = DB::new(path).expect("Failed to recover the DB");
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+    drop(kvstore);
+    peak
+}
+
+pub fn recovery_benchmark(c: &mut Criterion) {
+    let min_mib_per_sec: Option<f64> =
+        std::env::var("MINI_LSM_RECOVERY_MIN_MIB_PER_SEC")
+            .ok()
+            .map(|value| {
+                value
+                    .parse()
+                    .expect("Invalid MINI_LSM_RECOVERY_MIN_MIB_PER_SEC")
+            });
+
+    let mut group = c.benchmark_group("recovery");
+    group.sample_size(10);
+    for wal_size in WAL_SIZES {
+        let temp_dir = TempDir::new().unwrap();
+        build_wal(temp_dir.path(), wal_size);
+
+        let peak = measure_peak_memory(temp_dir.path());
+        println!(
+            "recovery/{} MiB: peak memory {:.1} MiB",
+            wal_size / MIB,
+            peak as f64 / MIB as f64
+        );
+        assert!(
+            peak as f64 <= wal_size as f64 * MAX_PEAK_MEMORY_RATIO,
+            "recovering a {} MiB WAL used {} bytes, more than {}x the WAL size",
+            wal_size / MIB,
+            peak,
+            MAX_PEAK_MEMORY_RATIO
+        );
+
+        if let Some(min_mib_per_sec) = min_mib_per_sec {
+            let start = Instant::now();
+            drop(DB::new(temp_dir.path()).expect("Failed to recover the DB"));
+            let mib_per_sec = (wal_size / MIB) as f64 / start.elapsed().as_secs_f64();
+            assert!(
+                mib_per_sec >= min_mib_per_sec,
+                "recovering a {} MiB WAL ran at {:.1} MiB/s, below {} MiB/s",
+                wal_size / MIB,
+                mib_per_sec,
+                min_mib_per_sec
+            );
+        }
+
+        group.throughput(Throughput::Bytes(wal_size as u64));
+        group.bench_with_input(
+            BenchmarkId::new("db_new", format!("{}MiB", wal_size / MIB)),
+            temp_dir.path(),
+            |b, path| {
+                b.iter(|| {
+                    let kvstore = DB::new(path).expect("Failed to recover the DB");
+                    black_box(kvstore.latest_sequence())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, recovery_benchmark);
+criterion_main!(benches);
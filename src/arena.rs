@@ -0,0 +1,134 @@
+//! This module provides `Arena`, a bump allocator for the values of the memtable.
+//!
+//! Values are copied into large blocks instead of being allocated one by one,
+//! and the blocks are only freed together, when the arena is dropped. Copies
+//! are referred to by `ArenaSlice` handles rather than references, so that the
+//! memtable can hold them next to the arena without any unsafe code.
+
+/// The size of the blocks values are bump-allocated from.
+const BLOCK_SIZE: usize = 4096;
+
+/// A handle to bytes copied into an `Arena`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArenaSlice {
+    block: u32,
+    offset: u32,
+    len: u32,
+}
+
+impl ArenaSlice {
+    /// Returns the number of bytes the handle refers to.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+}
+
+/// A bump allocator handing out `ArenaSlice`s.
+#[derive(Default)]
+pub struct Arena {
+    blocks: Vec<Vec<u8>>,
+    /// The block small allocations are currently bumped from.
+    current: Option<usize>,
+    /// The number of bytes allocated for the blocks.
+    memory_usage: usize,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    /// Copies `bytes` into the arena.
+    ///
+    /// Small allocations share blocks, while allocations of more than a
+    /// quarter of a block get a block of their own, so that they do not waste
+    /// the rest of the current one.
+    pub fn alloc(&mut self, bytes: &[u8]) -> ArenaSlice {
+        if bytes.is_empty() {
+            return ArenaSlice::default();
+        }
+        if bytes.len() > BLOCK_SIZE / 4 {
+            self.memory_usage += bytes.len();
+            self.blocks.push(bytes.to_vec());
+            return self.slice(self.blocks.len() - 1, 0, bytes.len());
+        }
+        let current = match self.current {
+            Some(current)
+                if self.blocks[current].capacity() - self.blocks[current].len() >= bytes.len() =>
+            {
+                current
+            }
+            _ => {
+                let block = Vec::with_capacity(BLOCK_SIZE);
+                self.memory_usage += block.capacity();
+                self.blocks.push(block);
+                self.current = Some(self.blocks.len() - 1);
+                self.blocks.len() - 1
+            }
+        };
+        let block = &mut self.blocks[current];
+        let offset = block.len();
+        // Within the capacity, so the block is never reallocated
+        block.extend_from_slice(bytes);
+        self.slice(current, offset, bytes.len())
+    }
+
+    /// Returns the bytes `slice` refers to, which must have been allocated by this arena.
+    pub fn get(&self, slice: ArenaSlice) -> &[u8] {
+        if slice.len == 0 {
+            return &[];
+        }
+        let offset = slice.offset as usize;
+        &self.blocks[slice.block as usize][offset..offset + slice.len()]
+    }
+
+    /// Returns the number of bytes allocated for the blocks of the arena,
+    /// including the space not handed out yet.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    fn slice(&self, block: usize, offset: usize, len: usize) -> ArenaSlice {
+        ArenaSlice {
+            block: u32::try_from(block).unwrap(),
+            offset: u32::try_from(offset).unwrap(),
+            len: u32::try_from(len).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arena, BLOCK_SIZE};
+
+    #[test]
+    fn alloc() {
+        let mut arena = Arena::new();
+        assert_eq!(arena.memory_usage(), 0);
+        let empty = arena.alloc(b"");
+        assert_eq!(arena.get(empty), b"");
+        assert_eq!(arena.memory_usage(), 0);
+
+        // Small allocations share a block
+        let a = arena.alloc(b"a");
+        let b = arena.alloc(b"bb");
+        assert_eq!((arena.get(a), arena.get(b)), (&b"a"[..], &b"bb"[..]));
+        let block_usage = arena.memory_usage();
+        assert!(block_usage >= BLOCK_SIZE);
+
+        // Large allocations get their own block, and the current one keeps being used
+        let large = vec![7u8; BLOCK_SIZE];
+        let c = arena.alloc(&large);
+        assert_eq!(arena.memory_usage(), block_usage + BLOCK_SIZE);
+        let d = arena.alloc(b"d");
+        assert_eq!(arena.memory_usage(), block_usage + BLOCK_SIZE);
+        assert_eq!((arena.get(c), arena.get(d)), (&large[..], &b"d"[..]));
+
+        // A full block is followed by a new one
+        let filler = vec![1u8; BLOCK_SIZE / 4];
+        let slices: Vec<_> = (0..8).map(|_| arena.alloc(&filler)).collect();
+        assert!(arena.memory_usage() >= 2 * block_usage + BLOCK_SIZE);
+        assert!(slices.iter().all(|&slice| arena.get(slice) == filler));
+        assert_eq!(arena.get(a), b"a");
+    }
+}
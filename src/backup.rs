@@ -0,0 +1,494 @@
+//! This module provides `BackupEngine`, which keeps backups of a DB in a
+//! directory and restores them.
+//!
+//! Every file of a backup is stored once in the `shared` directory, under a
+//! name made of its original name, its CRC32C and its size, so that the files
+//! that did not change between backups are not copied again. The files of each
+//! backup are listed in `meta/<backup id>`, which is written last, so a backup
+//! interrupted by a crash is simply absent.
+//
+// Meta File Format:
+//
+// timestamp <seconds since the Unix epoch>
+// sequence <sequence number of the last write>
+// file <original name> <size> <crc32c>
+// ...
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Error, Result},
+    manifest, DB,
+};
+
+const META_DIR_NAME: &str = "meta";
+const SHARED_DIR_NAME: &str = "shared";
+
+/// The size of the chunks files are copied in.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A file of a backup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BackupFile {
+    /// The name of the file in the DB directory.
+    name: String,
+    size: u64,
+    crc: u32,
+}
+
+impl BackupFile {
+    /// Returns the name the file is stored under in the `shared` directory.
+    fn shared_name(&self) -> String {
+        format!("{}_{:08x}_{}", self.name, self.crc, self.size)
+    }
+}
+
+/// A backup, as recorded by its meta file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Backup {
+    timestamp: u64,
+    sequence: u64,
+    files: Vec<BackupFile>,
+}
+
+impl Backup {
+    fn encode(&self) -> String {
+        let mut contents = format!("timestamp {}\nsequence {}\n", self.timestamp, self.sequence);
+        for file in &self.files {
+            contents.push_str(&format!("file {} {} {}\n", file.name, file.size, file.crc));
+        }
+        contents
+    }
+
+    fn decode(contents: &str) -> Result<Backup> {
+        let corruption = || Error::Corruption(format!("invalid backup meta file: {:?}", contents));
+        let mut backup = Backup {
+            timestamp: 0,
+            sequence: 0,
+            files: Vec::new(),
+        };
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields[..] {
+                ["timestamp", timestamp] => {
+                    backup.timestamp = timestamp.parse().map_err(|_| corruption())?
+                }
+                ["sequence", sequence] => {
+                    backup.sequence = sequence.parse().map_err(|_| corruption())?
+                }
+                ["file", name, size, crc] => backup.files.push(BackupFile {
+                    name: name.to_string(),
+                    size: size.parse().map_err(|_| corruption())?,
+                    crc: crc.parse().map_err(|_| corruption())?,
+                }),
+                _ => return Err(corruption()),
+            }
+        }
+        Ok(backup)
+    }
+}
+
+/// A summary of a backup kept by a `BackupEngine`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// The identifier of the backup, increasing with each new backup.
+    pub backup_id: u32,
+    /// The time the backup was taken, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The sequence number of the last write in the backup.
+    pub sequence: u64,
+    /// The total size of the files of the backup, including the ones shared
+    /// with other backups.
+    pub size: u64,
+    /// The number of files in the backup.
+    pub num_files: usize,
+}
+
+/// Takes backups of DBs into a directory, and restores them.
+///
+/// ```ignore
+/// let mut engine = BackupEngine::open("path/to/backups")?;
+/// engine.create_new_backup(&db)?;
+/// engine.purge_old_backups(3)?;
+/// engine.restore_db_from_latest_backup("path/to/restored")?;
+/// ```
+pub struct BackupEngine {
+    dir: PathBuf,
+    backups: BTreeMap<u32, Backup>,
+}
+
+impl BackupEngine {
+    /// Opens the backups kept in the directory `path`, creating it if needed.
+    ///
+    /// Files left behind by a backup that was interrupted are deleted.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::Corruption)` if a meta file cannot be parsed.
+    pub fn open(path: impl AsRef<Path>) -> Result<BackupEngine> {
+        let dir = path.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join(META_DIR_NAME))?;
+        fs::create_dir_all(dir.join(SHARED_DIR_NAME))?;
+        let mut backups = BTreeMap::new();
+        for entry in fs::read_dir(dir.join(META_DIR_NAME))? {
+            let entry = entry?;
+            let Some(backup_id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+            else {
+                // e.g. the temporary file of a meta file being written
+                continue;
+            };
+            let backup = Backup::decode(&fs::read_to_string(entry.path())?)?;
+            backups.insert(backup_id, backup);
+        }
+        let engine = BackupEngine { dir, backups };
+        engine.collect_garbage()?;
+        Ok(engine)
+    }
+
+    /// Backs up `db`, including the writes that bypassed its WAL.
+    ///
+    /// Writes to `db` wait until its files are copied.
+    ///
+    /// # Returns
+    ///
+    /// The identifier of the new backup.
+    pub fn create_new_backup(&mut self, db: &DB) -> Result<u32> {
+        let shared_dir = self.dir.join(SHARED_DIR_NAME);
+        let mut files = Vec::new();
+        let sequence = db.visit_live_files(|path, size| {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| {
+                    Error::ValueError(format!("invalid file name: {}", path.display()))
+                })?;
+            let tmp_path = shared_dir.join(format!("{}.tmp", name));
+            let crc = copy_file(path, &tmp_path, size)?;
+            let file = BackupFile {
+                name: name.to_string(),
+                size,
+                crc,
+            };
+            let shared_path = shared_dir.join(file.shared_name());
+            if shared_path.exists() {
+                fs::remove_file(&tmp_path)?;
+            } else {
+                fs::rename(&tmp_path, &shared_path)?;
+            }
+            files.push(file);
+            Ok(())
+        })?;
+        manifest::sync_dir(&shared_dir)?;
+
+        let backup_id = self.backups.keys().next_back().map_or(1, |id| id + 1);
+        let backup = Backup {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            sequence,
+            files,
+        };
+        manifest::write_file_atomically(
+            &self.dir.join(META_DIR_NAME).join(backup_id.to_string()),
+            backup.encode().as_bytes(),
+        )?;
+        self.backups.insert(backup_id, backup);
+        Ok(backup_id)
+    }
+
+    /// Returns a summary of every backup, from the oldest to the newest.
+    pub fn get_backup_info(&self) -> Vec<BackupInfo> {
+        self.backups
+            .iter()
+            .map(|(&backup_id, backup)| BackupInfo {
+                backup_id,
+                timestamp: backup.timestamp,
+                sequence: backup.sequence,
+                size: backup.files.iter().map(|file| file.size).sum(),
+                num_files: backup.files.len(),
+            })
+            .collect()
+    }
+
+    /// Checks that every file of a backup is present and matches its checksum.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::ValueError)` if there is no such backup, or
+    /// `Err(Error::Corruption)` if a file is missing or corrupt.
+    pub fn verify_backup(&self, backup_id: u32) -> Result<()> {
+        for file in &self.backup(backup_id)?.files {
+            let path = self.dir.join(SHARED_DIR_NAME).join(file.shared_name());
+            let crc = match File::open(&path) {
+                Ok(mut source) => copy_checksummed(&mut source, &mut io::sink(), file.size)?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Err(Error::Corruption(format!(
+                        "backup {} is missing {}",
+                        backup_id, file.name
+                    )))
+                }
+                Err(err) => return Err(Error::Io(err)),
+            };
+            check_crc(file, crc, fs::metadata(&path)?.len())?;
+        }
+        Ok(())
+    }
+
+    /// Deletes every backup but the `num_to_keep` newest ones.
+    pub fn purge_old_backups(&mut self, num_to_keep: usize) -> Result<()> {
+        let num_to_purge = self.backups.len().saturating_sub(num_to_keep);
+        let purged: Vec<u32> = self.backups.keys().take(num_to_purge).copied().collect();
+        for backup_id in purged {
+            self.delete_backup(backup_id)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes a backup, and the files no other backup shares.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::ValueError)` if there is no such backup.
+    pub fn delete_backup(&mut self, backup_id: u32) -> Result<()> {
+        self.backup(backup_id)?;
+        let meta_dir = self.dir.join(META_DIR_NAME);
+        fs::remove_file(meta_dir.join(backup_id.to_string()))?;
+        manifest::sync_dir(&meta_dir)?;
+        self.backups.remove(&backup_id);
+        self.collect_garbage()
+    }
+
+    /// Restores a backup into `db_dir`, verifying the checksum of every file.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::ValueError)` if there is no such backup or `db_dir`
+    /// is not empty, or `Err(Error::Corruption)` if a file of the backup is
+    /// corrupt. The files restored so far are then left in `db_dir`.
+    pub fn restore_db_from_backup(&self, backup_id: u32, db_dir: impl AsRef<Path>) -> Result<()> {
+        let backup = self.backup(backup_id)?;
+        let db_dir = db_dir.as_ref();
+        fs::create_dir_all(db_dir)?;
+        if fs::read_dir(db_dir)?.next().is_some() {
+            return Err(Error::ValueError(format!(
+                "cannot restore into {}, which is not empty",
+                db_dir.display()
+            )));
+        }
+        for file in &backup.files {
+            let source = self.dir.join(SHARED_DIR_NAME).join(file.shared_name());
+            let crc = copy_file(&source, &db_dir.join(&file.name), file.size)?;
+            check_crc(file, crc, fs::metadata(&source)?.len())?;
+        }
+        manifest::sync_dir(db_dir)
+    }
+
+    /// Restores the newest backup into `db_dir`, like `restore_db_from_backup`.
+    pub fn restore_db_from_latest_backup(&self, db_dir: impl AsRef<Path>) -> Result<()> {
+        let Some(&backup_id) = self.backups.keys().next_back() else {
+            return Err(Error::ValueError(
+                "there is no backup to restore".to_string(),
+            ));
+        };
+        self.restore_db_from_backup(backup_id, db_dir)
+    }
+
+    fn backup(&self, backup_id: u32) -> Result<&Backup> {
+        self.backups
+            .get(&backup_id)
+            .ok_or_else(|| Error::ValueError(format!("no backup with id {}", backup_id)))
+    }
+
+    /// Deletes the shared files that no backup refers to.
+    fn collect_garbage(&self) -> Result<()> {
+        let live: HashSet<String> = self
+            .backups
+            .values()
+            .flat_map(|backup| backup.files.iter().map(BackupFile::shared_name))
+            .collect();
+        let shared_dir = self.dir.join(SHARED_DIR_NAME);
+        for entry in fs::read_dir(&shared_dir)? {
+            let entry = entry?;
+            if !entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| live.contains(name))
+            {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies the first `size` bytes of the file `source` to a new file at
+/// `destination`, which is fsynced.
+///
+/// # Returns
+///
+/// The CRC32C of the bytes copied.
+fn copy_file(source: &Path, destination: &Path, size: u64) -> Result<u32> {
+    let mut source = File::open(source)?;
+    let mut destination = File::create(destination)?;
+    let crc = copy_checksummed(&mut source, &mut destination, size)?;
+    destination.sync_all()?;
+    Ok(crc)
+}
+
+/// Copies up to `size` bytes from `source` to `destination`, and returns their CRC32C.
+fn copy_checksummed(
+    source: &mut impl Read,
+    destination: &mut impl Write,
+    size: u64,
+) -> Result<u32> {
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut source = source.take(size);
+    let mut crc = 0;
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(crc);
+        }
+        crc = crc32c::crc32c_append(crc, &buffer[..read]);
+        destination.write_all(&buffer[..read])?;
+    }
+}
+
+/// Checks a copy of a backup file, of `size` bytes with the given CRC32C, against its meta data.
+fn check_crc(file: &BackupFile, crc: u32, size: u64) -> Result<()> {
+    if crc != file.crc || size != file.size {
+        return Err(Error::Corruption(format!(
+            "backup file {} has CRC {:08x} and size {} instead of {:08x} and {}",
+            file.name, crc, size, file.crc, file.size
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::{BackupEngine, SHARED_DIR_NAME};
+    use crate::{error::Error, options::WriteOptions, write_batch::WriteBatch, DB};
+
+    #[test]
+    fn backup_and_restore() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_dir = temp_dir.path().join("db");
+        let backup_dir = temp_dir.path().join("backups");
+        let db = DB::new(&db_dir).unwrap();
+        let mut engine = BackupEngine::open(&backup_dir).unwrap();
+
+        db.insert_or_update(b"a", b"1").unwrap();
+        let first = engine.create_new_backup(&db).unwrap();
+        db.insert_or_update(b"b", b"2").unwrap();
+        // Writes that bypassed the WAL are backed up too
+        let mut wb = WriteBatch::new();
+        wb.insert_or_update(b"c", b"3");
+        db.write_opt(&wb, &WriteOptions::default().disable_wal(true))
+            .unwrap();
+        let second = engine.create_new_backup(&db).unwrap();
+        assert_eq!((first, second), (1, 2));
+
+        // Only the WAL changed between the backups
+        let info = engine.get_backup_info();
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0].num_files, 5);
+        assert_eq!(info[1].sequence, db.latest_sequence());
+        let shared_files = fs::read_dir(backup_dir.join(SHARED_DIR_NAME))
+            .unwrap()
+            .count();
+        assert_eq!(shared_files, 6);
+
+        // Backups survive reopening the engine, and restore into fresh directories
+        drop(engine);
+        let engine = BackupEngine::open(&backup_dir).unwrap();
+        engine.verify_backup(first).unwrap();
+        engine.verify_backup(second).unwrap();
+        let restored_dir = temp_dir.path().join("restored");
+        engine.restore_db_from_backup(first, &restored_dir).unwrap();
+        let restored = DB::new(&restored_dir).unwrap();
+        assert_eq!(restored.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(restored.get(b"b").unwrap(), None);
+        assert_eq!(restored.get_db_identity(), db.get_db_identity());
+        drop(restored);
+        assert!(matches!(
+            engine.restore_db_from_latest_backup(&restored_dir),
+            Err(Error::ValueError(_))
+        ));
+        let latest_dir = temp_dir.path().join("latest");
+        engine.restore_db_from_latest_backup(&latest_dir).unwrap();
+        let restored = DB::new(&latest_dir).unwrap();
+        assert_eq!(restored.scan(..).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn purge_old_backups() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = DB::new(temp_dir.path().join("db")).unwrap();
+        let backup_dir = temp_dir.path().join("backups");
+        let mut engine = BackupEngine::open(&backup_dir).unwrap();
+        for i in 0..3u8 {
+            db.insert_or_update(&[i], &[i + 1]).unwrap();
+            engine.create_new_backup(&db).unwrap();
+        }
+
+        engine.purge_old_backups(1).unwrap();
+        let info = engine.get_backup_info();
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].backup_id, 3);
+        // Only the files of the remaining backup are kept
+        let shared_files = fs::read_dir(backup_dir.join(SHARED_DIR_NAME))
+            .unwrap()
+            .count();
+        assert_eq!(shared_files, 5);
+        assert!(matches!(engine.delete_backup(1), Err(Error::ValueError(_))));
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 4);
+    }
+
+    #[test]
+    fn corrupt_backup() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = DB::new(temp_dir.path().join("db")).unwrap();
+        db.insert_or_update(b"a", b"1").unwrap();
+        let backup_dir = temp_dir.path().join("backups");
+        let mut engine = BackupEngine::open(&backup_dir).unwrap();
+        let backup_id = engine.create_new_backup(&db).unwrap();
+
+        let wal = fs::read_dir(backup_dir.join(SHARED_DIR_NAME))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_str().unwrap().contains(".log"))
+            .unwrap();
+        let mut contents = fs::read(&wal).unwrap();
+        *contents.last_mut().unwrap() ^= 1;
+        fs::write(&wal, contents).unwrap();
+
+        assert!(matches!(
+            engine.verify_backup(backup_id),
+            Err(Error::Corruption(_))
+        ));
+        assert!(matches!(
+            engine.restore_db_from_backup(backup_id, temp_dir.path().join("restored")),
+            Err(Error::Corruption(_))
+        ));
+        fs::remove_file(&wal).unwrap();
+        assert!(matches!(
+            engine.verify_backup(backup_id),
+            Err(Error::Corruption(_))
+        ));
+        assert!(matches!(engine.verify_backup(2), Err(Error::ValueError(_))));
+    }
+}
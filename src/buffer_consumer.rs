@@ -17,7 +17,7 @@ impl<'a> BufferConsumer<'a> {
     /// # Returns
     ///
     /// A new `BufferConsumer` instance.
-    pub fn new(buf: &[u8]) -> BufferConsumer {
+    pub fn new(buf: &[u8]) -> BufferConsumer<'_> {
         BufferConsumer {
             buf,
             pos: RefCell::<usize>::new(0),
@@ -0,0 +1,377 @@
+//! This module provides `DBIterator`, a cursor over the keys of a DB that can
+//! be positioned anywhere and moved in both directions.
+//!
+//! ```ignore
+//! let mut it = db.iterator(..)?;
+//! it.seek(b"key");
+//! while it.valid() {
+//!     println!("{:?} => {:?}", it.key(), it.value());
+//!     it.next();
+//! }
+//! it.status()?;
+//! ```
+
+use std::{collections::VecDeque, ops::Bound, sync::RwLock};
+
+use crate::{error::Result, iterator_tracker::IteratorGuard, keys, memtable::Memtable};
+
+/// The number of entries the iterator copies out of the memtable at a time.
+const READ_AHEAD: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// A cursor over the keys of a DB within a range, in order.
+///
+/// The iterator starts unpositioned: one of the `seek` methods must be called
+/// before reading from it. It is valid while it is positioned on an entry, and
+/// becomes invalid once it moves past either end of its range.
+///
+/// Entries are copied out of the memtable a few at a time, like with `Iter`, so
+/// the iterator does not hold up writers. It still reads the DB as of when it
+/// was created, like a snapshot: the writes made while it is open are not seen,
+/// and the versions it reads are kept until it is dropped or refreshed.
+pub struct DBIterator<'a> {
+    memtable: &'a RwLock<Memtable>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    /// The sequence number the values are read as of.
+    sequence: u64,
+    /// Whether reading the latest data pins it at the newest write applied.
+    pin_latest: bool,
+    /// Whether `sequence` is registered as a snapshot of the memtable, to
+    /// release when the iterator is dropped.
+    pinned: bool,
+    direction: Direction,
+    current: Option<(Vec<u8>, Vec<u8>)>,
+    /// The entries following the current one in `direction`.
+    buffered: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// Whether `buffered` holds every entry left in `direction`.
+    exhausted: bool,
+    _guard: IteratorGuard<'a>,
+}
+
+impl<'a> DBIterator<'a> {
+    /// Creates an iterator over the keys of `memtable` within `start` and
+    /// `end`, with their values as of `sequence`, or of the latest data if it
+    /// is `u64::MAX`.
+    ///
+    /// With `pin_latest`, the latest data is read as of the newest write
+    /// applied when the iterator is created or refreshed, so that a write
+    /// batch applied meanwhile is seen either in full or not at all.
+    pub(crate) fn new(
+        memtable: &'a RwLock<Memtable>,
+        guard: IteratorGuard<'a>,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        sequence: u64,
+        pin_latest: bool,
+    ) -> DBIterator<'a> {
+        let mut it = DBIterator {
+            memtable,
+            start: start.map(<[u8]>::to_vec),
+            end: end.map(<[u8]>::to_vec),
+            sequence,
+            pin_latest,
+            pinned: false,
+            direction: Direction::Forward,
+            current: None,
+            buffered: VecDeque::new(),
+            exhausted: true,
+            _guard: guard,
+        };
+        if sequence == u64::MAX {
+            it.pin();
+        }
+        it
+    }
+
+    /// Returns whether the iterator is positioned on an entry.
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Returns the key of the current entry, or `None` if the iterator is not valid.
+    pub fn key(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|(key, _)| key.as_slice())
+    }
+
+    /// Returns the value of the current entry, or `None` if the iterator is not valid.
+    pub fn value(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|(_, value)| value.as_slice())
+    }
+
+    /// Returns the error that made the iterator invalid, if any.
+    ///
+    /// An iterator that becomes invalid without an error has simply reached
+    /// the end of its range. Reads from the memtable cannot fail, so for now
+    /// the status is always `Ok`.
+    pub fn status(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Positions the iterator on the first key of its range.
+    pub fn seek_to_first(&mut self) {
+        self.read_ahead(Direction::Forward, Bound::Unbounded);
+        self.step();
+    }
+
+    /// Positions the iterator on the last key of its range.
+    pub fn seek_to_last(&mut self) {
+        self.read_ahead(Direction::Reverse, Bound::Unbounded);
+        self.step();
+    }
+
+    /// Positions the iterator on the first key at or after `target`.
+    pub fn seek(&mut self, target: &[u8]) {
+        self.read_ahead(Direction::Forward, Bound::Included(target));
+        self.step();
+    }
+
+    /// Positions the iterator on the last key at or before `target`.
+    pub fn seek_for_prev(&mut self, target: &[u8]) {
+        self.read_ahead(Direction::Reverse, Bound::Included(target));
+        self.step();
+    }
+
+    /// Makes the iterator read the latest data of the DB, including the writes
+    /// made since it was created, even if it was created from a snapshot.
+    ///
+    /// This is cheaper than creating a new iterator, and lets a long-lived one
+    /// stop holding on to old data. The iterator is left unpositioned, so one of
+    /// the `seek` methods must be called again before reading from it.
+    pub fn refresh(&mut self) {
+        self.unpin();
+        self.sequence = u64::MAX;
+        self.pin();
+        self.current = None;
+        self.buffered.clear();
+        self.exhausted = true;
+    }
+
+    /// Pins the latest data at the newest write applied, if reading it is pinned.
+    fn pin(&mut self) {
+        if !self.pin_latest {
+            return;
+        }
+        let mut memtable = self.memtable.write().unwrap();
+        self.sequence = memtable.last_sequence();
+        memtable.add_snapshot(self.sequence);
+        self.pinned = true;
+    }
+
+    /// Releases the data pinned by `pin`, if any.
+    fn unpin(&mut self) {
+        if std::mem::take(&mut self.pinned) {
+            self.memtable
+                .write()
+                .unwrap()
+                .release_snapshot(self.sequence);
+        }
+    }
+
+    /// Moves the iterator to the next key. Does nothing if it is not valid.
+    pub fn next(&mut self) {
+        self.move_to(Direction::Forward);
+    }
+
+    /// Moves the iterator to the previous key. Does nothing if it is not valid.
+    pub fn prev(&mut self) {
+        self.move_to(Direction::Reverse);
+    }
+
+    /// Moves the iterator to the next key in `direction`, reading from the
+    /// memtable again if it changes direction.
+    fn move_to(&mut self, direction: Direction) {
+        if direction != self.direction {
+            let Some((key, _)) = self.current.take() else {
+                return;
+            };
+            self.read_ahead(direction, Bound::Excluded(&key));
+        }
+        self.step();
+    }
+
+    /// Moves the iterator to the next buffered entry, reading ahead from the
+    /// current key if the buffer is empty.
+    fn step(&mut self) {
+        if self.buffered.is_empty() && !self.exhausted {
+            if let Some((key, _)) = self.current.take() {
+                self.read_ahead(self.direction, Bound::Excluded(&key));
+            }
+        }
+        self.current = self.buffered.pop_front();
+    }
+
+    /// Buffers the entries of the range from `from` on, in `direction`.
+    fn read_ahead(&mut self, direction: Direction, from: Bound<&[u8]>) {
+        let (start, end) = match direction {
+            Direction::Forward => (keys::max_start(from, self.start_bound()), self.end_bound()),
+            Direction::Reverse => (self.start_bound(), keys::min_end(from, self.end_bound())),
+        };
+        let mut entries: VecDeque<(Vec<u8>, Vec<u8>)> = VecDeque::new();
+        // `BTreeMap::range` panics on such bounds
+        if !keys::is_empty_range(start, end) {
+            let memtable = self.memtable.read().unwrap();
+            let copy = |(key, value): (&[u8], &[u8])| (key.to_vec(), value.to_vec());
+            entries = match direction {
+                Direction::Forward => memtable
+                    .scan_at(start, end, self.sequence)
+                    .take(READ_AHEAD + 1)
+                    .map(copy)
+                    .collect(),
+                Direction::Reverse => memtable
+                    .scan_rev_at(start, end, self.sequence)
+                    .take(READ_AHEAD + 1)
+                    .map(copy)
+                    .collect(),
+            };
+        }
+        self.exhausted = entries.len() <= READ_AHEAD;
+        entries.truncate(READ_AHEAD);
+        self.direction = direction;
+        self.buffered = entries;
+    }
+
+    fn start_bound(&self) -> Bound<&[u8]> {
+        self.start.as_ref().map(Vec::as_slice)
+    }
+
+    fn end_bound(&self) -> Bound<&[u8]> {
+        self.end.as_ref().map(Vec::as_slice)
+    }
+}
+
+impl Drop for DBIterator<'_> {
+    fn drop(&mut self) {
+        self.unpin();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::DBIterator;
+    use crate::DB;
+
+    fn entry(it: &DBIterator) -> Option<(Vec<u8>, Vec<u8>)> {
+        Some((it.key()?.to_vec(), it.value()?.to_vec()))
+    }
+
+    #[test]
+    fn seek_and_move() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        // Enough keys to read ahead several times in both directions
+        for i in 0..200u32 {
+            db.insert_or_update(&(i * 2).to_be_bytes(), &i.to_be_bytes())
+                .unwrap();
+        }
+
+        let mut it = db.iterator(..).unwrap();
+        assert!(!it.valid());
+        it.seek_to_first();
+        assert_eq!(
+            entry(&it),
+            Some((0u32.to_be_bytes().to_vec(), 0u32.to_be_bytes().to_vec()))
+        );
+        let mut count = 1;
+        while {
+            it.next();
+            it.valid()
+        } {
+            count += 1;
+        }
+        assert_eq!(count, 200);
+        it.status().unwrap();
+
+        it.seek_to_last();
+        assert_eq!(it.key(), Some(&398u32.to_be_bytes()[..]));
+        let mut count = 1;
+        while {
+            it.prev();
+            it.valid()
+        } {
+            count += 1;
+        }
+        assert_eq!(count, 200);
+
+        // Seeks land on the nearest key in their direction
+        it.seek(&101u32.to_be_bytes());
+        assert_eq!(it.key(), Some(&102u32.to_be_bytes()[..]));
+        it.seek_for_prev(&101u32.to_be_bytes());
+        assert_eq!(it.key(), Some(&100u32.to_be_bytes()[..]));
+        it.seek_for_prev(&100u32.to_be_bytes());
+        assert_eq!(it.key(), Some(&100u32.to_be_bytes()[..]));
+
+        // Changing direction steps over the current key
+        it.next();
+        it.next();
+        assert_eq!(it.key(), Some(&104u32.to_be_bytes()[..]));
+        it.prev();
+        assert_eq!(it.key(), Some(&102u32.to_be_bytes()[..]));
+
+        // Moving past either end invalidates the iterator for good
+        it.seek(&1000u32.to_be_bytes());
+        assert!(!it.valid());
+        it.prev();
+        assert!(!it.valid());
+    }
+
+    #[test]
+    fn range_and_snapshot() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        for key in [b"a", b"b", b"c", b"d"] {
+            db.insert_or_update(key, b"1").unwrap();
+        }
+        let snapshot = db.snapshot().unwrap();
+        db.insert_or_update(b"b", b"2").unwrap();
+        db.delete(b"c").unwrap();
+
+        let mut it = db.iterator(&b"b"[..]..&b"d"[..]).unwrap();
+        it.seek_to_first();
+        assert_eq!(entry(&it), Some((b"b".to_vec(), b"2".to_vec())));
+        it.next();
+        assert!(!it.valid());
+        it.seek_to_last();
+        assert_eq!(it.key(), Some(&b"b"[..]));
+        it.seek(b"a");
+        assert_eq!(it.key(), Some(&b"b"[..]));
+        it.seek_for_prev(b"z");
+        assert_eq!(it.key(), Some(&b"b"[..]));
+
+        let mut it = snapshot.iterator(..).unwrap();
+        it.seek_to_last();
+        assert_eq!(it.key(), Some(&b"d"[..]));
+        it.prev();
+        assert_eq!(entry(&it), Some((b"c".to_vec(), b"1".to_vec())));
+        it.prev();
+        assert_eq!(entry(&it), Some((b"b".to_vec(), b"1".to_vec())));
+    }
+
+    #[test]
+    fn refresh() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        db.insert_or_update(b"a", b"1").unwrap();
+        let snapshot = db.snapshot().unwrap();
+        let mut it = snapshot.iterator(..).unwrap();
+        it.seek_to_first();
+        db.insert_or_update(b"a", b"2").unwrap();
+        db.insert_or_update(b"b", b"2").unwrap();
+        it.next();
+        assert!(!it.valid());
+
+        it.refresh();
+        assert!(!it.valid());
+        it.seek_to_first();
+        assert_eq!(entry(&it), Some((b"a".to_vec(), b"2".to_vec())));
+        it.next();
+        assert_eq!(entry(&it), Some((b"b".to_vec(), b"2".to_vec())));
+    }
+}
@@ -3,6 +3,7 @@ use std::{array, io, result};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("Value Error: `{0}`")]
     ValueError(String),
@@ -24,6 +25,36 @@ pub enum Error {
 
     #[error("TryFromSliceError error: {0}")]
     TryFromSlice(#[from] array::TryFromSliceError),
+
+    #[error("Corruption: {0}")]
+    Corruption(String),
+
+    #[error("Found format version `{found}` but only versions up to `{supported}` are supported")]
+    IncompatibleVersion { found: u8, supported: u8 },
+
+    #[error("The database at `{0}` is already open in this process")]
+    AlreadyOpen(String),
+
+    #[error("The database at `{0}` is locked by another process")]
+    Locked(String),
+
+    #[error("The database is closed")]
+    Closed,
+
+    #[error("The database is poisoned: {0}")]
+    Poisoned(String),
+
+    #[error("The operation timed out")]
+    TimedOut,
+
+    #[error("Incompatible options: {0}")]
+    IncompatibleOptions(String),
+
+    #[error("Too many open iterators (limit {0})")]
+    TooManyIterators(usize),
+
+    #[error("Snappy error: {0}")]
+    Snappy(#[from] snap::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,157 @@
+//! This module provides the `EventListener` hook.
+//!
+//! Listeners are registered through `Options::listeners` and notified when the
+//! DB flushes, syncs its WAL or hits a fatal error, so applications can log
+//! these events, export metrics or trigger downstream work.
+
+use std::{fmt, time::Duration};
+
+/// The details of a completed `DB::flush`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlushJobInfo {
+    /// The number of entries written with `WriteOptions::disable_wal` that
+    /// the flush appended to the WAL.
+    pub num_entries: u32,
+    /// The sequence number of the last write persisted by the flush.
+    pub last_sequence: u64,
+}
+
+/// The details of an fsync of the WAL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalSyncInfo {
+    /// The number of the WAL segment synced.
+    pub log_number: u64,
+    /// How long the fsync took.
+    pub duration: Duration,
+    /// Whether the fsync was done by the background thread set up with
+    /// `Options::wal_sync_interval`, rather than for a write or a flush.
+    pub background: bool,
+}
+
+/// A trait for being notified of the events of a DB.
+///
+/// Every method does nothing by default. Methods are called on the thread that
+/// did the work, possibly with internal locks held, so they should return
+/// quickly and must not call back into the DB.
+pub trait EventListener: Send + Sync {
+    /// Called after `DB::flush` persisted the writes done so far. Closing the
+    /// DB and backing it up flush it as well.
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// Called once when an error poisons the DB, with the reason reported by
+    /// `Error::Poisoned`.
+    fn on_background_error(&self, _reason: &str) {}
+
+    /// Called after each successful fsync of the WAL.
+    fn on_wal_sync(&self, _info: &WalSyncInfo) {}
+}
+
+// Listeners have no state worth printing or comparing, so `Options` can keep
+// deriving `Debug` and `PartialEq`: two listeners are equal if they are the same object.
+impl fmt::Debug for dyn EventListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EventListener({:p})", self)
+    }
+}
+
+impl PartialEq for dyn EventListener {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+impl Eq for dyn EventListener {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tempfile::TempDir;
+
+    use super::{EventListener, FlushJobInfo, WalSyncInfo};
+    use crate::{
+        options::{Options, WriteOptions},
+        write_batch::WriteBatch,
+        DB,
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        flushes: Mutex<Vec<FlushJobInfo>>,
+        syncs: Mutex<Vec<WalSyncInfo>>,
+        errors: Mutex<Vec<String>>,
+    }
+
+    impl EventListener for Recorder {
+        fn on_flush_completed(&self, info: &FlushJobInfo) {
+            self.flushes.lock().unwrap().push(info.clone());
+        }
+
+        fn on_background_error(&self, reason: &str) {
+            self.errors.lock().unwrap().push(reason.to_string());
+        }
+
+        fn on_wal_sync(&self, info: &WalSyncInfo) {
+            self.syncs.lock().unwrap().push(info.clone());
+        }
+    }
+
+    #[test]
+    fn listener_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let options = Options::default().listener(recorder.clone());
+        assert_eq!(options, options.clone());
+        let db = DB::open(temp_dir.path(), options).unwrap();
+
+        // Synced writes report the fsync of the WAL
+        db.write_opt(
+            &{
+                let mut wb = WriteBatch::new();
+                wb.insert_or_update(b"a", b"1");
+                wb
+            },
+            &WriteOptions::default().sync(true),
+        )
+        .unwrap();
+        let syncs = recorder.syncs.lock().unwrap().clone();
+        assert_eq!(syncs.len(), 1);
+        assert!(!syncs[0].background);
+
+        // Flushes report the unlogged writes they persisted
+        let mut wb = WriteBatch::new();
+        wb.insert_or_update(b"b", b"2");
+        wb.insert_or_update(b"c", b"3");
+        db.write_opt(&wb, &WriteOptions::default().disable_wal(true))
+            .unwrap();
+        db.flush().unwrap();
+        assert_eq!(
+            *recorder.flushes.lock().unwrap(),
+            [FlushJobInfo {
+                num_entries: 2,
+                last_sequence: db.latest_sequence(),
+            }]
+        );
+        assert_eq!(recorder.syncs.lock().unwrap().len(), 2);
+
+        // The error poisoning the DB is reported once
+        db.poison("injected failure".to_string());
+        db.poison("another failure".to_string());
+        assert_eq!(*recorder.errors.lock().unwrap(), ["injected failure"]);
+    }
+
+    #[test]
+    fn background_wal_sync() {
+        let temp_dir = TempDir::new().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let options = Options::default()
+            .wal_sync_interval(Some(std::time::Duration::from_millis(1)))
+            .listener(recorder.clone());
+        let db = DB::open(temp_dir.path(), options).unwrap();
+        db.insert_or_update(b"a", b"1").unwrap();
+        while recorder.syncs.lock().unwrap().is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(recorder.syncs.lock().unwrap()[0].background);
+    }
+}
@@ -1,11 +1,41 @@
 use crate::error::{Error, Result};
 use crate::log_record::DEFAULT_BUFFER_CAPACITY;
+use crate::metrics::Metrics;
+use crate::options::WalPreallocation;
 use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use std::time::Instant;
 
 /// A struct representing a file writer.
 pub struct FileWriter {
     writer: BufWriter<File>,
+    offset: u64,
+    metrics: Option<Arc<Metrics>>,
+    preallocation: Option<WalPreallocation>,
+    /// The end of the space reserved for the file.
+    preallocated: u64,
+}
+
+/// Reserves disk space for the `len` bytes of `file` starting at `offset`,
+/// without changing the size of the file.
+#[cfg(target_os = "linux")]
+fn preallocate(file: &File, offset: u64, len: u64, zero_fill: bool) -> io::Result<()> {
+    use rustix::{fs::FallocateFlags, io::Errno};
+
+    if zero_fill {
+        let flags = FallocateFlags::KEEP_SIZE | FallocateFlags::ZERO_RANGE;
+        match rustix::fs::fallocate(file, flags, offset, len) {
+            Err(Errno::OPNOTSUPP) => {}
+            result => return result.map_err(io::Error::from),
+        }
+    }
+    rustix::fs::fallocate(file, FallocateFlags::KEEP_SIZE, offset, len).map_err(io::Error::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate(_file: &File, _offset: u64, _len: u64, _zero_fill: bool) -> io::Result<()> {
+    Ok(())
 }
 
 impl FileWriter {
@@ -30,11 +60,53 @@ impl FileWriter {
         }
 
         let file = options.open(file_path).map_err(Error::Io)?;
+        let offset = file.metadata().map_err(Error::Io)?.len();
         Ok(FileWriter {
             writer: BufWriter::with_capacity(DEFAULT_BUFFER_CAPACITY, file),
+            offset,
+            metrics: None,
+            preallocation: None,
+            preallocated: offset,
         })
     }
 
+    /// Sets how the writer reserves disk space ahead of appends, or disables
+    /// preallocation if `preallocation` is `None`.
+    pub fn set_preallocation(&mut self, preallocation: Option<WalPreallocation>) {
+        self.preallocation = preallocation;
+    }
+
+    /// Reserves disk space for an append of `len` bytes if it would go past the
+    /// space reserved so far.
+    ///
+    /// Preallocation is only an optimization, so failures are logged and ignored.
+    fn reserve(&mut self, len: u64) {
+        let Some(preallocation) = self.preallocation else {
+            return;
+        };
+        if self.offset + len <= self.preallocated {
+            return;
+        }
+        let size = preallocation.size.max(len);
+        match preallocate(
+            self.writer.get_ref(),
+            self.offset,
+            size,
+            preallocation.zero_fill,
+        ) {
+            Ok(()) => self.preallocated = self.offset + size,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to preallocate the WAL");
+                self.preallocation = None;
+            }
+        }
+    }
+
+    /// Sets the metrics the writer reports its fsyncs to.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
     /// Appends data to the file.
     ///
     /// # Arguments
@@ -48,7 +120,15 @@ impl FileWriter {
         if data.is_empty() {
             return Ok(());
         }
-        self.writer.write_all(data).map_err(Error::Io)
+        self.reserve(data.len() as u64);
+        self.writer.write_all(data).map_err(Error::Io)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the size of the file, including the data that is still buffered.
+    pub fn offset(&self) -> u64 {
+        self.offset
     }
 
     /// Flushes any buffered data to the file.
@@ -62,15 +142,24 @@ impl FileWriter {
         self.writer.flush().map_err(Error::Io)
     }
 
+    /// Returns a new handle to the underlying file, e.g. to sync it from another thread.
+    pub fn try_clone_file(&self) -> Result<File> {
+        self.writer.get_ref().try_clone().map_err(Error::Io)
+    }
+
     /// Flushes any buffered data to the OS and fsyncs the file to disk.
     ///
     /// # Returns
     ///
     /// Returns a `Result` indicating success or an `Error` if an error occurs.
-    #[allow(dead_code)]
     pub fn sync(&mut self) -> Result<()> {
-        self.flush()
-            .and_then(|_| self.writer.get_mut().sync_all().map_err(Error::Io))
+        self.flush()?;
+        let start = Instant::now();
+        self.writer.get_mut().sync_all().map_err(Error::Io)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_fsync(start.elapsed());
+        }
+        Ok(())
     }
 }
 
@@ -103,10 +192,15 @@ mod tests {
         }
         fw.flush().unwrap();
         fw.sync().unwrap();
+        assert_eq!(fw.offset(), random_bytes.len() as u64);
 
         // read file and validate the contents
         let actual = fs::read(file_path).unwrap();
         assert_eq!(actual, random_bytes);
+
+        // Reopening the file in append mode continues at its end
+        let fw = FileWriter::new(file_path, false).expect("failed opening a file handle");
+        assert_eq!(fw.offset(), random_bytes.len() as u64);
     }
     #[test]
     fn append_empty_data() {
@@ -166,4 +260,53 @@ mod tests {
         let expected = random_bytes.repeat(5);
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn sync_metrics() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+
+        let metrics = Arc::new(Metrics::default());
+        let mut fw = FileWriter::new(file_path, true).expect("failed opening a file handle");
+        fw.set_metrics(Arc::clone(&metrics));
+
+        for _ in 0..3 {
+            fw.append(b"data").unwrap();
+            fw.sync().unwrap();
+        }
+        // Flushes don't reach the disk and are not counted
+        fw.flush().unwrap();
+
+        assert_eq!(metrics.fsync_count(), 3);
+        assert_eq!(metrics.fsync_latency().count(), 3);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preallocation() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+        let size = 1024 * 1024;
+
+        for zero_fill in [false, true] {
+            let mut fw = FileWriter::new(file_path, true).expect("failed opening a file handle");
+            fw.set_preallocation(Some(WalPreallocation { size, zero_fill }));
+            fw.append(b"data").unwrap();
+            fw.flush().unwrap();
+
+            // The space is reserved without changing the size of the file
+            let metadata = fs::metadata(file_path).unwrap();
+            assert_eq!(metadata.len(), 4);
+            assert!(metadata.blocks() * 512 >= size);
+            assert_eq!(fw.preallocated, size);
+
+            // Appends within the reserved space don't reserve more
+            fw.append(&vec![0; size as usize - 4]).unwrap();
+            assert_eq!(fw.preallocated, size);
+            fw.append(b"more").unwrap();
+            assert_eq!(fw.preallocated, 2 * size);
+        }
+    }
 }
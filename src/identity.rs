@@ -0,0 +1,59 @@
+//! This module manages the IDENTITY file of a DB.
+//!
+//! The IDENTITY file holds a UUID generated when the DB is created. It never
+//! changes afterwards, so replication and backup tooling can use it to tell
+//! stores apart even when they are moved or copied.
+
+use std::{fs, io, path::Path};
+
+use uuid::Uuid;
+
+use crate::{
+    error::{Error, Result},
+    manifest::write_file_atomically,
+};
+
+/// The name of the IDENTITY file in the DB directory.
+pub const IDENTITY_FILE_NAME: &str = "IDENTITY";
+
+/// Returns the identity stored in the IDENTITY file at `path`, generating and
+/// persisting a new one if the file does not exist.
+pub fn load_or_create(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let identity = contents.trim_end();
+            Uuid::parse_str(identity)
+                .map_err(|_| Error::Corruption(format!("invalid IDENTITY file: {:?}", contents)))?;
+            Ok(identity.to_string())
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let identity = Uuid::new_v4().to_string();
+            write_file_atomically(path, format!("{}\n", identity).as_bytes())?;
+            Ok(identity)
+        }
+        Err(err) => Err(Error::Io(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn load_or_create() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("IDENTITY");
+
+        let identity = super::load_or_create(&path).unwrap();
+        assert!(Uuid::parse_str(&identity).is_ok());
+        assert_eq!(super::load_or_create(&path).unwrap(), identity);
+
+        fs::write(&path, "not a uuid").unwrap();
+        assert!(matches!(
+            super::load_or_create(&path),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+//! This module tracks the iterators that are open on a DB.
+//!
+//! Open iterators pin the state they read from, so leaking them holds memory
+//! (and, once SSTs exist, files) alive indefinitely. The tracker counts open
+//! iterators, enforces an optional limit, and in debug builds remembers where
+//! each iterator was created so leaks can be traced back to their origin.
+
+use std::{
+    backtrace::Backtrace,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::error::{Error, Result};
+
+#[derive(Default)]
+pub struct IteratorTracker {
+    open: AtomicUsize,
+    next_id: AtomicU64,
+    max_open: Option<usize>,
+    backtraces: Mutex<HashMap<u64, Backtrace>>,
+}
+
+/// Unregisters an iterator from its tracker when dropped.
+pub struct IteratorGuard<'a> {
+    tracker: &'a IteratorTracker,
+    id: u64,
+}
+
+impl Drop for IteratorGuard<'_> {
+    fn drop(&mut self) {
+        self.tracker.open.fetch_sub(1, Ordering::SeqCst);
+        if cfg!(debug_assertions) {
+            self.tracker.backtraces.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+impl IteratorTracker {
+    /// Sets the maximum number of iterators that may be open at once, or `None` for no limit.
+    pub fn set_max_open(&mut self, max_open: Option<usize>) {
+        self.max_open = max_open;
+    }
+
+    /// Returns the number of open iterators.
+    pub fn open(&self) -> usize {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    /// Registers a new iterator.
+    ///
+    /// # Returns
+    ///
+    /// Returns a guard that must live as long as the iterator, or
+    /// `Err(Error::TooManyIterators)` if the limit of open iterators is reached.
+    pub fn register(&self) -> Result<IteratorGuard<'_>> {
+        let open = self.open.fetch_add(1, Ordering::SeqCst) + 1;
+        let guard = IteratorGuard {
+            tracker: self,
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+        };
+        if let Some(max_open) = self.max_open {
+            if open > max_open {
+                return Err(Error::TooManyIterators(max_open));
+            }
+        }
+        if cfg!(debug_assertions) {
+            self.backtraces
+                .lock()
+                .unwrap()
+                .insert(guard.id, Backtrace::force_capture());
+        }
+        Ok(guard)
+    }
+
+    /// Returns the creation backtraces of the open iterators.
+    ///
+    /// Backtraces are only recorded in debug builds; release builds return an empty list.
+    pub fn open_backtraces(&self) -> Vec<String> {
+        self.backtraces
+            .lock()
+            .unwrap()
+            .values()
+            .map(|backtrace| backtrace.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register() {
+        let mut tracker = IteratorTracker::default();
+        tracker.set_max_open(Some(2));
+
+        let first = tracker.register().unwrap();
+        let second = tracker.register().unwrap();
+        assert_eq!(tracker.open(), 2);
+        match tracker.register() {
+            Err(Error::TooManyIterators(2)) => {}
+            _ => panic!("Expected TooManyIterators error"),
+        }
+        assert_eq!(tracker.open(), 2);
+        assert_eq!(
+            tracker.open_backtraces().len(),
+            if cfg!(debug_assertions) { 2 } else { 0 }
+        );
+
+        drop(first);
+        drop(second);
+        assert_eq!(tracker.open(), 0);
+        assert!(tracker.open_backtraces().is_empty());
+    }
+}
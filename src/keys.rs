@@ -0,0 +1,442 @@
+//! Helpers for computing key ranges, and the internal keys that tag each
+//! version of a user key with its sequence number and value type.
+
+use std::{borrow::Borrow, cmp::Ordering, ops::Bound};
+
+use tinyvec::TinyVec;
+
+use crate::error::{Error, Result};
+
+/// The largest sequence number, which leaves the low byte of the trailer of an
+/// internal key for its value type.
+pub const MAX_SEQUENCE: u64 = (1 << 56) - 1;
+
+/// The length of the trailer appended to the user key of an encoded internal key.
+#[allow(dead_code)]
+const TRAILER_LEN: usize = 8;
+
+/// The type of a version of a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum ValueType {
+    /// A tombstone, hiding the older versions of the key.
+    Deletion = 0,
+    /// A value.
+    Value = 1,
+    /// A tombstone of a key written at most once, which compactions drop
+    /// together with the value it deletes.
+    SingleDeletion = 2,
+}
+
+impl ValueType {
+    /// The value type that sorts first among the versions with the same
+    /// sequence number, used to seek to the newest version at a sequence number.
+    pub const FOR_SEEK: ValueType = ValueType::SingleDeletion;
+}
+
+/// A version of a user key: the user key, the sequence number of the write
+/// that produced it, and its value type.
+///
+/// Internal keys are ordered by user key, and then from the newest version to
+/// the oldest, so that the first internal key at or after
+/// `InternalKey::new(key, sequence, ValueType::FOR_SEEK)` is the version of
+/// `key` visible at `sequence`.
+///
+/// An internal key is encoded as the user key followed by its trailer,
+/// `sequence << 8 | value_type`, as a little-endian fixed64. Encoded internal
+/// keys do not sort bytewise and must be compared with `compare_encoded`. The
+/// encoding is what SST files will store, which the DB does not write yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InternalKey {
+    user_key: TinyVec<[u8; 16]>,
+    trailer: u64,
+}
+
+impl InternalKey {
+    /// Creates the internal key of the version of `user_key` written at `sequence`.
+    ///
+    /// Sequence numbers above `MAX_SEQUENCE` are clamped to it.
+    pub fn new(user_key: &[u8], sequence: u64, value_type: ValueType) -> InternalKey {
+        InternalKey {
+            user_key: TinyVec::from(user_key),
+            trailer: trailer(sequence, value_type),
+        }
+    }
+
+    /// Returns the user key.
+    pub fn user_key(&self) -> &[u8] {
+        &self.user_key
+    }
+
+    /// Returns the sequence number of the write that produced this version.
+    pub fn sequence(&self) -> u64 {
+        self.trailer >> 8
+    }
+
+    /// Returns the value type of this version.
+    pub fn value_type(&self) -> ValueType {
+        match self.trailer as u8 {
+            0 => ValueType::Deletion,
+            1 => ValueType::Value,
+            _ => ValueType::SingleDeletion,
+        }
+    }
+
+    /// Returns the encoding of the internal key.
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(self.user_key.len() + TRAILER_LEN);
+        encoded.extend_from_slice(&self.user_key);
+        encoded.extend_from_slice(&self.trailer.to_le_bytes());
+        encoded
+    }
+
+    /// Decodes an internal key encoded by `InternalKey::encode`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::Corruption)` if `encoded` is too short or has an unknown value type.
+    #[allow(dead_code)]
+    pub fn decode(encoded: &[u8]) -> Result<InternalKey> {
+        let (user_key, trailer) = split_encoded(encoded)?;
+        if trailer as u8 > ValueType::SingleDeletion as u8 {
+            return Err(Error::Corruption(format!(
+                "unknown value type {} in an internal key",
+                trailer as u8
+            )));
+        }
+        Ok(InternalKey {
+            user_key: TinyVec::from(user_key),
+            trailer,
+        })
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.user_key, self.trailer, &other.user_key, other.trailer)
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Returns the trailer of the version written at `sequence`, clamped to `MAX_SEQUENCE`.
+fn trailer(sequence: u64, value_type: ValueType) -> u64 {
+    sequence.min(MAX_SEQUENCE) << 8 | value_type as u64
+}
+
+/// Compares two internal keys given by their user keys and trailers.
+fn compare(a_user_key: &[u8], a_trailer: u64, b_user_key: &[u8], b_trailer: u64) -> Ordering {
+    a_user_key
+        .cmp(b_user_key)
+        .then_with(|| b_trailer.cmp(&a_trailer))
+}
+
+/// The parts of an internal key, whether owned by an `InternalKey` or borrowed
+/// by a `LookupKey`.
+///
+/// Ordered maps keyed by `InternalKey` can be searched with a `LookupKey`
+/// through `&dyn InternalKeyRef`, which both borrow as, so that lookups do not
+/// copy the user key.
+pub trait InternalKeyRef {
+    fn user_key(&self) -> &[u8];
+    fn trailer(&self) -> u64;
+}
+
+impl InternalKeyRef for InternalKey {
+    fn user_key(&self) -> &[u8] {
+        &self.user_key
+    }
+
+    fn trailer(&self) -> u64 {
+        self.trailer
+    }
+}
+
+impl Ord for dyn InternalKeyRef + '_ {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(
+            self.user_key(),
+            self.trailer(),
+            other.user_key(),
+            other.trailer(),
+        )
+    }
+}
+
+impl PartialOrd for dyn InternalKeyRef + '_ {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for dyn InternalKeyRef + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for dyn InternalKeyRef + '_ {}
+
+impl<'a> Borrow<dyn InternalKeyRef + 'a> for InternalKey {
+    fn borrow(&self) -> &(dyn InternalKeyRef + 'a) {
+        self
+    }
+}
+
+/// An internal key borrowing its user key, to search for versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LookupKey<'a> {
+    user_key: &'a [u8],
+    trailer: u64,
+}
+
+impl<'a> LookupKey<'a> {
+    /// Creates the key of the version of `user_key` written at `sequence`,
+    /// like `InternalKey::new`.
+    pub fn new(user_key: &'a [u8], sequence: u64, value_type: ValueType) -> LookupKey<'a> {
+        LookupKey {
+            user_key,
+            trailer: trailer(sequence, value_type),
+        }
+    }
+}
+
+impl InternalKeyRef for LookupKey<'_> {
+    fn user_key(&self) -> &[u8] {
+        self.user_key
+    }
+
+    fn trailer(&self) -> u64 {
+        self.trailer
+    }
+}
+
+/// Splits an encoded internal key into its user key and trailer.
+#[allow(dead_code)]
+fn split_encoded(encoded: &[u8]) -> Result<(&[u8], u64)> {
+    let Some(split) = encoded.len().checked_sub(TRAILER_LEN) else {
+        return Err(Error::Corruption(format!(
+            "internal key of {} bytes is shorter than its trailer",
+            encoded.len()
+        )));
+    };
+    let (user_key, trailer) = encoded.split_at(split);
+    Ok((user_key, u64::from_le_bytes(trailer.try_into().unwrap())))
+}
+
+/// Compares two encoded internal keys in the order of `InternalKey`, without
+/// decoding them.
+///
+/// # Returns
+///
+/// Returns `Err(Error::Corruption)` if either key is shorter than its trailer.
+#[allow(dead_code)]
+pub fn compare_encoded(a: &[u8], b: &[u8]) -> Result<Ordering> {
+    let (a_user_key, a_trailer) = split_encoded(a)?;
+    let (b_user_key, b_trailer) = split_encoded(b)?;
+    Ok(a_user_key
+        .cmp(b_user_key)
+        .then_with(|| b_trailer.cmp(&a_trailer)))
+}
+
+/// Returns the smallest key that is greater than every key starting with `prefix`,
+/// or `None` if no such key exists (the prefix is empty or consists only of `0xff` bytes).
+///
+/// The successor is computed by dropping trailing `0xff` bytes and incrementing the
+/// last remaining byte, e.g. `[0x01, 0xff]` becomes `[0x02]`.
+pub fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&b| b != 0xff)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
+/// Returns whether no key can lie within the bounds `start` and `end`, e.g.
+/// because `start` is greater than `end`.
+pub fn is_empty_range(start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start >= end,
+        _ => false,
+    }
+}
+
+/// Returns the tighter of two start bounds.
+pub fn max_start<'k>(a: Bound<&'k [u8]>, b: Bound<&'k [u8]>) -> Bound<&'k [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y))
+            if x != y =>
+        {
+            if x > y {
+                a
+            } else {
+                b
+            }
+        }
+        (Bound::Excluded(_), _) => a,
+        _ => b,
+    }
+}
+
+/// Returns the tighter of two end bounds.
+pub fn min_end<'k>(a: Bound<&'k [u8]>, b: Bound<&'k [u8]>) -> Bound<&'k [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y))
+            if x != y =>
+        {
+            if x < y {
+                a
+            } else {
+                b
+            }
+        }
+        (Bound::Excluded(_), _) => a,
+        _ => b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use super::{
+        compare_encoded, is_empty_range, max_start, min_end, prefix_successor, InternalKey,
+        ValueType, MAX_SEQUENCE,
+    };
+
+    #[test]
+    fn successor() {
+        assert_eq!(prefix_successor(b"abc"), Some(b"abd".to_vec()));
+        assert_eq!(prefix_successor(&[0x01, 0xff, 0xff]), Some(vec![0x02]));
+        assert_eq!(prefix_successor(&[0xff, 0xff]), None);
+        assert_eq!(prefix_successor(&[]), None);
+    }
+
+    #[test]
+    fn empty_range() {
+        let (a, b) = (&b"a"[..], &b"b"[..]);
+        assert!(!is_empty_range(Bound::Included(a), Bound::Included(a)));
+        assert!(is_empty_range(Bound::Included(a), Bound::Excluded(a)));
+        assert!(is_empty_range(Bound::Excluded(a), Bound::Included(a)));
+        assert!(!is_empty_range(Bound::Included(a), Bound::Excluded(b)));
+        assert!(is_empty_range(Bound::Included(b), Bound::Included(a)));
+        assert!(!is_empty_range(Bound::Excluded(b), Bound::Unbounded));
+        assert!(!is_empty_range(Bound::Unbounded, Bound::Excluded(a)));
+    }
+
+    #[test]
+    fn lookup_key() {
+        use std::collections::BTreeMap;
+
+        use super::{InternalKeyRef, LookupKey};
+
+        let map: BTreeMap<InternalKey, u64> = [
+            (InternalKey::new(b"a", 2, ValueType::Value), 2),
+            (InternalKey::new(b"a", 1, ValueType::Value), 1),
+            (InternalKey::new(b"b", 3, ValueType::Deletion), 3),
+        ]
+        .into_iter()
+        .collect();
+        let lookup = LookupKey::new(b"a", 1, ValueType::Value);
+        assert_eq!(map.get(&lookup as &dyn InternalKeyRef), Some(&1));
+        let (start, end) = (
+            LookupKey::new(b"a", 5, ValueType::FOR_SEEK),
+            LookupKey::new(b"a", 0, ValueType::Deletion),
+        );
+        let versions: Vec<u64> = map
+            .range::<dyn InternalKeyRef, _>((
+                Bound::Included(&start as &dyn InternalKeyRef),
+                Bound::Included(&end as &dyn InternalKeyRef),
+            ))
+            .map(|(_, &value)| value)
+            .collect();
+        assert_eq!(versions, [2, 1]);
+    }
+
+    #[test]
+    fn internal_key_order() {
+        let keys = [
+            InternalKey::new(b"a", 7, ValueType::SingleDeletion),
+            InternalKey::new(b"a", 7, ValueType::Value),
+            InternalKey::new(b"a", 7, ValueType::Deletion),
+            InternalKey::new(b"a", 3, ValueType::Value),
+            InternalKey::new(b"ab", MAX_SEQUENCE, ValueType::Value),
+            InternalKey::new(b"b", 1, ValueType::Deletion),
+        ];
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert_eq!(
+                compare_encoded(&pair[0].encode(), &pair[1].encode()).unwrap(),
+                std::cmp::Ordering::Less
+            );
+        }
+        // The seek key of a sequence number sorts right before its version
+        assert!(InternalKey::new(b"a", 5, ValueType::FOR_SEEK) > keys[2]);
+        assert!(InternalKey::new(b"a", 5, ValueType::FOR_SEEK) < keys[3]);
+    }
+
+    #[test]
+    fn internal_key_encoding() {
+        let key = InternalKey::new(b"key", 42, ValueType::Deletion);
+        let encoded = key.encode();
+        assert_eq!(encoded.len(), 11);
+        let decoded = InternalKey::decode(&encoded).unwrap();
+        assert_eq!(decoded, key);
+        assert_eq!(decoded.user_key(), b"key");
+        assert_eq!(decoded.sequence(), 42);
+        assert_eq!(decoded.value_type(), ValueType::Deletion);
+        let single_deletion = InternalKey::new(b"key", 42, ValueType::SingleDeletion);
+        assert_eq!(
+            InternalKey::decode(&single_deletion.encode()).unwrap(),
+            single_deletion
+        );
+
+        assert_eq!(
+            InternalKey::new(b"", u64::MAX, ValueType::Value).sequence(),
+            MAX_SEQUENCE
+        );
+        assert!(InternalKey::decode(b"short").is_err());
+        let mut unknown_type = encoded;
+        unknown_type[3] = 9;
+        assert!(InternalKey::decode(&unknown_type).is_err());
+    }
+
+    #[test]
+    fn bounds() {
+        let (a, b) = (&b"a"[..], &b"b"[..]);
+        assert_eq!(
+            max_start(Bound::Included(a), Bound::Excluded(b)),
+            Bound::Excluded(b)
+        );
+        assert_eq!(
+            max_start(Bound::Included(a), Bound::Excluded(a)),
+            Bound::Excluded(a)
+        );
+        assert_eq!(
+            max_start(Bound::Unbounded, Bound::Included(a)),
+            Bound::Included(a)
+        );
+        assert_eq!(
+            min_end(Bound::Included(a), Bound::Excluded(b)),
+            Bound::Included(a)
+        );
+        assert_eq!(
+            min_end(Bound::Included(b), Bound::Excluded(b)),
+            Bound::Excluded(b)
+        );
+        assert_eq!(
+            min_end(Bound::Excluded(a), Bound::Unbounded),
+            Bound::Excluded(a)
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! This module implements the `kvdb::KeyValueDB` trait on top of a `DB`, so
+//! mini-lsm can be used behind code written against that abstraction.
+//!
+//! `KeyValueDB` is organized in numbered columns. Each column is stored under a
+//! 4-byte big-endian prefix of the column number, so columns are isolated the
+//! same way `ScopedDB` isolates scopes. `KeyValueDB` also distinguishes empty
+//! values from missing keys, while mini-lsm treats an empty value as a delete,
+//! so every value is stored behind a 1-byte tag.
+
+use std::{io, sync::Mutex};
+
+use kvdb::{DBKey, DBKeyValue, DBOp, DBTransaction, DBValue, KeyValueDB};
+
+use crate::{write_batch::WriteBatch, DB};
+
+/// The tag stored in front of every value.
+const VALUE_TAG: u8 = 0;
+
+/// A `DB` exposed through the `kvdb::KeyValueDB` trait.
+///
+/// Reads go straight to the `DB`, which is synchronized internally. Transactions
+/// are serialized by a mutex, so that a prefix delete covers every key written
+/// before it. Iterators collect their results up front.
+pub struct KvdbAdapter {
+    db: DB,
+    write_lock: Mutex<()>,
+    num_columns: u32,
+}
+
+impl KvdbAdapter {
+    /// Wraps `db` in an adapter exposing `num_columns` columns.
+    pub fn new(db: DB, num_columns: u32) -> KvdbAdapter {
+        KvdbAdapter {
+            db,
+            write_lock: Mutex::new(()),
+            num_columns,
+        }
+    }
+
+    /// Returns the wrapped `DB`.
+    pub fn into_inner(self) -> DB {
+        self.db
+    }
+
+    fn column_prefix(&self, col: u32) -> io::Result<[u8; 4]> {
+        if col >= self.num_columns {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no such column: {}", col),
+            ));
+        }
+        Ok(col.to_be_bytes())
+    }
+
+    fn column_key(&self, col: u32, key: &[u8]) -> io::Result<Vec<u8>> {
+        Ok([&self.column_prefix(col)?[..], key].concat())
+    }
+
+    /// Returns the entries of column `col` whose keys start with `prefix`.
+    fn collect_prefix(&self, col: u32, prefix: &[u8]) -> io::Result<Vec<DBKeyValue>> {
+        let prefix = self.column_key(col, prefix)?;
+        let entries = self
+            .db
+            .scan_prefix(&prefix)
+            .map_err(io::Error::other)?
+            .map(|(key, value)| (DBKey::from_slice(&key[4..]), value[1..].to_vec()))
+            .collect();
+        Ok(entries)
+    }
+}
+
+impl KeyValueDB for KvdbAdapter {
+    fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
+        let key = self.column_key(col, key)?;
+        let value = self.db.get(&key).map_err(io::Error::other)?;
+        Ok(value.map(|mut value| {
+            value.remove(0);
+            value
+        }))
+    }
+
+    fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> io::Result<Option<DBValue>> {
+        let prefix = self.column_key(col, prefix)?;
+        let mut iter = self.db.scan_prefix(&prefix).map_err(io::Error::other)?;
+        Ok(iter.next().map(|(_, value)| value[1..].to_vec()))
+    }
+
+    fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+        let _write_lock = self.write_lock.lock().unwrap();
+        let mut wb = WriteBatch::new();
+        for op in &transaction.ops {
+            let key = self.column_key(op.col(), op.key())?;
+            match op {
+                DBOp::Insert { value, .. } => {
+                    wb.insert_or_update(&key, &[&[VALUE_TAG][..], value].concat())
+                }
+                DBOp::Delete { .. } => wb.delete(&key),
+                DBOp::DeletePrefix { .. } => {
+                    // Delete the matching keys in the DB as well as those
+                    // inserted earlier in this transaction.
+                    let mut doomed: Vec<Vec<u8>> = self
+                        .db
+                        .scan_prefix(&key)
+                        .map_err(io::Error::other)?
+                        .map(|(k, _)| k)
+                        .collect();
+                    doomed.extend(
+                        wb.iter()
+                            .filter(|(k, _)| k.starts_with(&key))
+                            .map(|(k, _)| k.to_vec()),
+                    );
+                    for k in doomed {
+                        wb.delete(&k);
+                    }
+                }
+            }
+        }
+        if wb.is_empty() {
+            return Ok(());
+        }
+        self.db.write(&wb).map_err(io::Error::other)
+    }
+
+    fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = io::Result<DBKeyValue>> + 'a> {
+        self.iter_with_prefix(col, &[])
+    }
+
+    fn iter_with_prefix<'a>(
+        &'a self,
+        col: u32,
+        prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = io::Result<DBKeyValue>> + 'a> {
+        match self.collect_prefix(col, prefix) {
+            Ok(entries) => Box::new(entries.into_iter().map(Ok)),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn key_value_db() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        let kv = KvdbAdapter::new(db, 2);
+
+        let mut tx = kv.transaction();
+        tx.put(0, b"a", b"1");
+        tx.put(0, b"b", b"");
+        tx.put(1, b"a", b"col1");
+        tx.put(0, b"pre/1", b"x");
+        tx.put(0, b"pre/2", b"y");
+        kv.write(tx).unwrap();
+
+        // Empty values are distinct from missing keys, and columns are isolated
+        assert_eq!(kv.get(0, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kv.get(0, b"b").unwrap(), Some(Vec::new()));
+        assert_eq!(kv.get(0, b"c").unwrap(), None);
+        assert_eq!(kv.get(1, b"a").unwrap(), Some(b"col1".to_vec()));
+        assert!(kv.get(2, b"a").is_err());
+        assert_eq!(kv.get_by_prefix(0, b"pre/").unwrap(), Some(b"x".to_vec()));
+
+        let keys: Vec<_> = kv.iter(0).map(|kv| kv.unwrap().0.to_vec()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                b"a".to_vec(),
+                b"b".to_vec(),
+                b"pre/1".to_vec(),
+                b"pre/2".to_vec()
+            ]
+        );
+
+        // Prefix deletes cover keys written earlier in the same transaction
+        let mut tx = kv.transaction();
+        tx.put(0, b"pre/3", b"z");
+        tx.delete_prefix(0, b"pre/");
+        tx.delete(0, b"a");
+        kv.write(tx).unwrap();
+        assert!(!kv.has_prefix(0, b"pre/").unwrap());
+        assert!(!kv.has_key(0, b"a").unwrap());
+        assert_eq!(kv.iter_with_prefix(1, b"").count(), 1);
+
+        // Everything is persisted in the underlying DB
+        drop(kv.into_inner());
+        let kv = KvdbAdapter::new(DB::new(temp_dir.path()).unwrap(), 2);
+        assert_eq!(kv.get(0, b"b").unwrap(), Some(Vec::new()));
+        assert_eq!(kv.get(1, b"a").unwrap(), Some(b"col1".to_vec()));
+    }
+}
@@ -1,80 +1,1371 @@
+#![forbid(unsafe_code)]
+
+mod arena;
+pub mod backup;
 mod buffer_consumer;
+pub mod db_iterator;
 mod error;
+pub mod event_listener;
 mod file_writer;
+mod identity;
+mod iterator_tracker;
+mod keys;
+#[cfg(feature = "kvdb")]
+pub mod kvdb_adapter;
 mod lending_iterator;
+mod log_header;
 mod log_reader;
 mod log_record;
 mod log_writer;
+mod manifest;
 mod memtable;
+pub mod metrics;
+pub mod options;
+mod options_file;
+pub mod prelude;
+pub mod properties;
+mod registry;
+pub mod scoped;
+pub mod secondary;
+pub mod snapshot;
+mod version;
 mod wal_recovery;
+mod wal_sync;
+pub mod wal_tail;
 pub mod write_batch;
-use std::{fs, os::unix::fs::MetadataExt, path::Path};
+use std::{
+    collections::BTreeSet,
+    fs::{self, File, TryLockError},
+    io,
+    ops::{Bound, ControlFlow, Range, RangeBounds},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
 
+use db_iterator::DBIterator;
+pub use error::{Error, Result};
+use event_listener::{EventListener, FlushJobInfo, WalSyncInfo};
+use iterator_tracker::{IteratorGuard, IteratorTracker};
 use log_writer::LogWriter;
+use manifest::{Manifest, VersionEdit, VersionState};
 use memtable::Memtable;
+use metrics::{Metrics, WalStats};
+use options::{Options, ReadOptions, WalRecoveryMode, WriteOptions};
+use scoped::ScopedDB;
+use secondary::SecondaryDB;
+use snapshot::Snapshot;
+use version::VersionSet;
+use wal_sync::WalSyncer;
+use write_batch::CompressionType;
+use write_batch::WriteOp;
 
-pub struct DB {
-    memtable: Memtable,
+/// The name of the file locked by the process that has the DB open.
+const LOCK_FILE_NAME: &str = "LOCK";
+
+/// Takes the LOCK file of the DB in `dir` and registers it as open.
+///
+/// # Returns
+///
+/// Returns `Err(Error::AlreadyOpen)` if the DB is already open in this process,
+/// or `Err(Error::Locked)` if another process has it open.
+fn lock_dir(dir: &Path) -> error::Result<(File, registry::Registration)> {
+    let registration = registry::register(dir)?;
+    let lock = File::create(dir.join(LOCK_FILE_NAME))?;
+    match lock.try_lock() {
+        Ok(()) => Ok((lock, registration)),
+        Err(TryLockError::WouldBlock) => Err(Error::Locked(dir.display().to_string())),
+        Err(TryLockError::Error(err)) => Err(Error::Io(err)),
+    }
+}
+
+/// Returns whether `name` is the name of a file written by the DB, including
+/// the temporary files replacing some of them.
+fn is_db_file(name: &str) -> bool {
+    let name = name.strip_suffix(".tmp").unwrap_or(name);
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match name {
+        LOCK_FILE_NAME
+        | identity::IDENTITY_FILE_NAME
+        | options_file::OPTIONS_FILE_NAME
+        | manifest::CURRENT_FILE_NAME => true,
+        _ => {
+            name.strip_prefix("MANIFEST-").is_some_and(is_number)
+                || name.strip_suffix(".log").is_some_and(is_number)
+        }
+    }
+}
+
+/// The lifecycle state of a `DB`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DbState {
+    /// The DB serves reads and writes.
+    Opened,
+    /// A fatal error left the WAL in an unknown state. The DB still serves
+    /// reads, but rejects writes until it is reopened and recovered.
+    Poisoned,
+    /// The DB was closed and rejects all operations.
+    Closed,
+}
+
+/// The lifecycle state of the DB, with the reason it was poisoned.
+struct Status {
+    state: DbState,
+    poison_reason: Option<String>,
+}
+
+/// The state of the write path. Writers hold it for the whole write, which
+/// orders the batches in the WAL the same way they are applied to the memtable.
+struct Writer {
     log_writer: LogWriter,
+    wal_state: wal_recovery::WalState,
+    /// The keys written with `WriteOptions::disable_wal` since the last `flush`.
+    unlogged_keys: BTreeSet<Vec<u8>>,
+    /// The ranges deleted with `WriteOptions::disable_wal` since the last `flush`.
+    unlogged_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// A DB can be shared across threads, e.g. in an `Arc`. Reads only contend
+/// with the writers applying a batch to the memtable, while writes are
+/// serialized by the WAL.
+pub struct DB {
+    dir: PathBuf,
+    status: RwLock<Status>,
+    versions: VersionSet,
+    memtable: RwLock<Memtable>,
+    writer: Mutex<Writer>,
+    /// The byte ranges of the WAL that recovery skipped because they were corrupt.
+    wal_corrupted: Vec<Range<u64>>,
+    wal_compression: CompressionType,
+    dedup_write_batches: bool,
+    /// How long values live after they are written, see `Options::ttl`.
+    ttl: Option<Duration>,
+    checkpoint_interval: u64,
+    iterators: IteratorTracker,
+    identity: String,
+    metrics: Arc<Metrics>,
+    wal_syncer: Option<WalSyncer>,
+    listeners: Vec<Arc<dyn EventListener>>,
+    /// The locked LOCK file and the registration of the directory, released by `close`.
+    lock: Option<(File, registry::Registration)>,
+}
+
+/// A page of the entries of a range, see `DB::scan_limited`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanPage {
+    /// The entries of the page, in order.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The start of the next page, or `None` if the range has no entries left.
+    pub continuation: Option<Vec<u8>>,
+}
+
+/// The error of a `DB::compare_and_swap` that found another value than the expected one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompareAndSwapError {
+    /// The current value of the key, or `None` if it does not exist.
+    pub current: Option<Vec<u8>>,
 }
 
+/// An iterator over a range of the keys of a DB, in order.
+///
+/// It walks a `DBIterator` from the first key of the range to the last, and
+/// reads the memtable the same way: the writes made while it is open are not
+/// returned.
 pub struct Iter<'a> {
-    it: memtable::Iter<'a>,
+    inner: DBIterator<'a>,
+    started: bool,
+}
+
+impl<'a> Iter<'a> {
+    /// Creates an iterator over the keys of `memtable` within `start` and
+    /// `end`, with their values as of `sequence`, see `DBIterator::new`.
+    fn new(
+        memtable: &'a RwLock<Memtable>,
+        guard: IteratorGuard<'a>,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        sequence: u64,
+        pin_latest: bool,
+    ) -> Iter<'a> {
+        Iter {
+            inner: DBIterator::new(memtable, guard, start, end, sequence, pin_latest),
+            started: false,
+        }
+    }
 }
 
-impl<'a> Iterator for Iter<'a> {
-    type Item = (&'a [u8], &'a [u8]);
+impl Iterator for Iter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.it.next()
+        match self.started {
+            true => self.inner.next(),
+            false => self.inner.seek_to_first(),
+        }
+        self.started = true;
+        Some((self.inner.key()?.to_vec(), self.inner.value()?.to_vec()))
     }
 }
 
 impl DB {
-    pub fn new(log_file: &str) -> error::Result<DB> {
-        let mut memtable = Memtable::new();
+    /// Opens the DB stored in the directory `path` with the default options,
+    /// creating it if needed.
+    ///
+    /// The directory holds all the files of the DB:
+    ///
+    /// * `LOCK` - Locked for as long as the DB is open, so that a single process uses it at a time.
+    /// * `IDENTITY` - The UUID of the DB.
+    /// * `OPTIONS` - The options the DB was created with.
+    /// * `CURRENT` - The name of the live MANIFEST.
+    /// * `MANIFEST-NNNNNN` - The log of the changes to the set of files of the DB.
+    /// * `NNNNNN.log` - The WAL segment the MANIFEST designates as live.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::AlreadyOpen)` if the DB is already open in this process,
+    /// or `Err(Error::Locked)` if another process has it open.
+    pub fn new(path: impl AsRef<Path>) -> error::Result<DB> {
+        DB::open(path, Options::default())
+    }
+
+    /// Opens the DB stored in the directory `path` like `DB::new`, handling
+    /// corruption found in the WAL according to `mode`.
+    ///
+    /// Unless `mode` is `WalRecoveryMode::AbsoluteConsistency`, the WAL is
+    /// truncated right after the last batch recovered, so that new writes are
+    /// not appended after a torn tail or other discarded bytes.
+    pub fn new_with_recovery_mode(
+        path: impl AsRef<Path>,
+        mode: WalRecoveryMode,
+    ) -> error::Result<DB> {
+        DB::open(path, Options::default().wal_recovery_mode(mode))
+    }
+
+    /// Opens the DB stored in the directory `path` like `DB::new`, with values
+    /// living for `ttl` after they are written, see `Options::ttl`.
+    pub fn open_with_ttl(path: impl AsRef<Path>, ttl: Duration) -> error::Result<DB> {
+        DB::open(path, Options::default().ttl(Some(ttl)))
+    }
 
-        if Path::new(log_file).exists() {
-            let metadata = fs::metadata(log_file)?;
+    /// Opens the DB stored in the directory `path` like `DB::new`, configured by `options`.
+    ///
+    /// See `DB::new_with_recovery_mode` for how `options.wal_recovery_mode` is applied.
+    /// `options.wal_checksum` only applies to a WAL created by this call: an
+    /// existing WAL keeps the checksum recorded in its header.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::ValueError)` if `options.block_size` is out of range, or
+    /// `Err(Error::IncompatibleOptions)` if it differs from the block size the DB
+    /// was created with.
+    pub fn open(path: impl AsRef<Path>, options: Options) -> error::Result<DB> {
+        if !log_header::is_valid_block_size(options.block_size) {
+            return Err(Error::ValueError(format!(
+                "invalid block size: {}",
+                options.block_size
+            )));
+        }
+        let mode = options.wal_recovery_mode;
+        let dir = path.as_ref();
+        fs::create_dir_all(dir)?;
+        let (lock, registration) = lock_dir(dir)?;
+
+        // The options are checked before replaying the WAL, which they describe.
+        options_file::check_and_persist(
+            &dir.join(options_file::OPTIONS_FILE_NAME),
+            &options_file::PersistedOptions {
+                block_size: options.block_size,
+                ttl: options.ttl,
+                ..options_file::PersistedOptions::default()
+            },
+        )?;
+        let identity = identity::load_or_create(&dir.join(identity::IDENTITY_FILE_NAME))?;
+
+        let manifest = match Manifest::recover(dir, manifest::DEFAULT_MAX_MANIFEST_SIZE)? {
+            Some(manifest) => manifest,
+            None => {
+                let state = VersionState {
+                    next_file_number: 1,
+                    ..VersionState::default()
+                };
+                Manifest::create(dir, state, manifest::DEFAULT_MAX_MANIFEST_SIZE)?
+            }
+        };
+        let mut versions = VersionSet::new(manifest);
+        if versions.manifest().state().log_number == 0 {
+            let log_number = versions.manifest().state().next_file_number;
+            let edit = VersionEdit {
+                log_number: Some(log_number),
+                next_file_number: Some(log_number + 1),
+                ..VersionEdit::default()
+            };
+            versions.log_and_apply(&edit)?;
+        }
+
+        let wal_path = wal_recovery::wal_file_path(dir, versions.manifest().state().log_number);
+        let wal_path = manifest::path_str(&wal_path)?;
+        let mut memtable = Memtable::new();
+        let mut wal_state = wal_recovery::WalState::default();
+        if Path::new(wal_path).exists() {
+            let metadata = fs::metadata(wal_path)?;
             if metadata.size() > 0 {
-                wal_recovery::load(log_file, &mut memtable)?;
+                wal_state = wal_recovery::load(wal_path, &mut memtable, mode, options.ttl)?;
+                let truncate = mode != WalRecoveryMode::AbsoluteConsistency;
+                if truncate && wal_state.replayed_len < metadata.size() {
+                    tracing::warn!(
+                        offset = wal_state.replayed_len,
+                        discarded = metadata.size() - wal_state.replayed_len,
+                        "truncating the WAL after the last recovered batch"
+                    );
+                    let wal = fs::OpenOptions::new().write(true).open(wal_path)?;
+                    wal.set_len(wal_state.replayed_len)?;
+                    wal.sync_all()?;
+                }
             }
         }
 
-        let log_writer = LogWriter::new(log_file, false)?;
-        Ok(DB {
-            memtable,
-            log_writer,
-        })
+        let metrics = Arc::new(Metrics::default());
+        let header = log_header::LogHeader {
+            checksum: options.wal_checksum.into(),
+            block_size: options.block_size,
+            ..log_header::LogHeader::default()
+        };
+        let mut log_writer = LogWriter::with_header(wal_path, false, header)?;
+        log_writer.set_metrics(Arc::clone(&metrics));
+        log_writer.set_preallocation(options.wal_preallocation);
+        let mut iterators = IteratorTracker::default();
+        iterators.set_max_open(options.max_open_iterators);
+        let wal_corrupted = std::mem::take(&mut wal_state.corrupted);
+        let mut db = DB {
+            dir: dir.to_path_buf(),
+            status: RwLock::new(Status {
+                state: DbState::Opened,
+                poison_reason: None,
+            }),
+            versions,
+            memtable: RwLock::new(memtable),
+            writer: Mutex::new(Writer {
+                log_writer,
+                wal_state,
+                unlogged_keys: BTreeSet::new(),
+                unlogged_ranges: Vec::new(),
+            }),
+            wal_corrupted,
+            wal_compression: options.wal_compression,
+            dedup_write_batches: options.dedup_write_batches,
+            ttl: options.ttl,
+            checkpoint_interval: wal_recovery::DEFAULT_CHECKPOINT_INTERVAL,
+            iterators,
+            identity,
+            metrics,
+            wal_syncer: None,
+            listeners: options.listeners,
+            lock: Some((lock, registration)),
+        };
+        db.set_wal_sync_interval(options.wal_sync_interval)?;
+        Ok(db)
+    }
+
+    /// Opens a read-only view of the DB stored in the directory `primary_path`,
+    /// which may be open in another process. The view starts from the
+    /// primary's current state and is updated with
+    /// `SecondaryDB::try_catch_up_with_primary`.
+    ///
+    /// # Arguments
+    ///
+    /// * `primary_path` - The directory of the DB to follow.
+    /// * `secondary_path` - A directory of its own for the secondary, locked
+    ///   for as long as it is open.
+    pub fn open_as_secondary(
+        primary_path: impl AsRef<Path>,
+        secondary_path: impl AsRef<Path>,
+    ) -> error::Result<SecondaryDB> {
+        SecondaryDB::open(primary_path.as_ref(), secondary_path.as_ref())
+    }
+
+    /// Deletes the DB stored in the directory `path`.
+    ///
+    /// Only the files written by the DB are deleted, and the directory itself
+    /// once it is empty. Destroying a directory that does not exist is a no-op.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory of the DB.
+    /// * `options` - The options the DB is opened with. All its files live in
+    ///   its directory for now, so none of them changes what is deleted.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::AlreadyOpen)` if the DB is open in this process, or
+    /// `Err(Error::Locked)` if another process has it open.
+    pub fn destroy(path: impl AsRef<Path>, _options: &Options) -> error::Result<()> {
+        let dir = path.as_ref();
+        if !dir.exists() {
+            return Ok(());
+        }
+        let (lock, _registration) = lock_dir(dir)?;
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            match name.to_str() {
+                Some(LOCK_FILE_NAME) => {}
+                Some(name) if is_db_file(name) => fs::remove_file(dir.join(name))?,
+                _ => {}
+            }
+        }
+        // The LOCK file goes last, so the DB cannot be opened while it is half deleted.
+        fs::remove_file(dir.join(LOCK_FILE_NAME))?;
+        drop(lock);
+        match fs::remove_dir(dir) {
+            Err(err) if err.kind() != io::ErrorKind::DirectoryNotEmpty => Err(Error::Io(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the lifecycle state of the DB.
+    pub fn state(&self) -> DbState {
+        self.status.read().unwrap().state
+    }
+
+    /// Closes the DB, stopping background work, and releases its directory so
+    /// it can be opened again.
+    ///
+    /// The writes done with `WriteOptions::disable_wal` are appended to the WAL
+    /// and the WAL is synced first, like `DB::flush` does. A poisoned DB is
+    /// closed without touching the WAL.
+    ///
+    /// Every operation on a closed DB fails with `Error::Closed`. Closing a
+    /// closed DB is a no-op. Dropping a DB closes it, but can only log errors.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the WAL cannot be written or synced. The DB is closed
+    /// regardless, and the writes that were not synced may be lost.
+    pub fn close(&mut self) -> error::Result<()> {
+        if self.state() == DbState::Closed {
+            return Ok(());
+        }
+        self.wal_syncer = None;
+        let result = match self.state() {
+            DbState::Opened => self.flush(),
+            _ => Ok(()),
+        };
+        self.status.get_mut().unwrap().state = DbState::Closed;
+        self.lock = None;
+        result
+    }
+
+    /// Moves the DB to the `Poisoned` state, recording `reason`.
+    fn poison(&self, reason: String) {
+        let mut status = self.status.write().unwrap();
+        if status.state == DbState::Opened {
+            tracing::error!(reason, "poisoning the DB");
+            status.state = DbState::Poisoned;
+            status.poison_reason = Some(reason.clone());
+            drop(status);
+            for listener in &self.listeners {
+                listener.on_background_error(&reason);
+            }
+        }
+    }
+
+    /// Notifies the listeners of an fsync of the WAL that took `duration`.
+    fn notify_wal_sync(&self, duration: Duration) {
+        let info = WalSyncInfo {
+            log_number: self.versions.manifest().state().log_number,
+            duration,
+            background: false,
+        };
+        for listener in &self.listeners {
+            listener.on_wal_sync(&info);
+        }
+    }
+
+    /// Returns an error unless the DB can serve reads.
+    fn check_readable(&self) -> error::Result<()> {
+        match self.state() {
+            DbState::Closed => Err(Error::Closed),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error unless the DB can serve writes.
+    ///
+    /// Writers poison the DB while holding the writer lock, so the result
+    /// only holds for as long as the caller holds it.
+    fn check_writable(&self) -> error::Result<()> {
+        let status = self.status.read().unwrap();
+        match status.state {
+            DbState::Opened => Ok(()),
+            DbState::Poisoned => Err(Error::Poisoned(
+                status.poison_reason.clone().unwrap_or_default(),
+            )),
+            DbState::Closed => Err(Error::Closed),
+        }
+    }
+
+    /// Returns the directory of the DB.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the path of the live WAL segment.
+    fn wal_path(&self) -> PathBuf {
+        wal_recovery::wal_file_path(&self.dir, self.versions.manifest().state().log_number)
+    }
+
+    /// Returns the byte ranges of the WAL that were skipped during recovery
+    /// because they were corrupt. Only `WalRecoveryMode::SkipAnyCorruption` and
+    /// `WalRecoveryMode::PointInTime` open a DB with corruption in the middle of the WAL.
+    pub fn wal_corrupted_ranges(&self) -> &[Range<u64>] {
+        &self.wal_corrupted
+    }
+
+    /// Returns a `WalTail` that follows the WAL of the DB, e.g. to replicate it.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_interval` - How long to wait before checking the WAL for new batches again.
+    pub fn tail_wal(&self, poll_interval: Duration) -> error::Result<wal_tail::WalTail> {
+        self.tail_wal_from(0, poll_interval)
+    }
+
+    /// Returns a `WalTail` that follows the WAL of the DB from `offset`, so a
+    /// consumer can resume where it left off without re-reading the whole WAL.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset to resume at, as reported by `WalTail::offset`.
+    /// * `poll_interval` - How long to wait before checking the WAL for new batches again.
+    pub fn tail_wal_from(
+        &self,
+        offset: u64,
+        poll_interval: Duration,
+    ) -> error::Result<wal_tail::WalTail> {
+        wal_tail::WalTail::new(manifest::path_str(&self.wal_path())?, offset, poll_interval)
+    }
+
+    /// Returns the UUID generated when the DB was created.
+    ///
+    /// The identity is persisted in the IDENTITY file and stays the same across
+    /// restarts, which lets replication and backup tooling distinguish stores.
+    pub fn get_db_identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// Returns the metrics collected by the DB.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns the counters of the WAL writer.
+    pub fn wal_stats(&self) -> WalStats {
+        let writer = self.writer.lock().unwrap();
+        let stats = writer.log_writer.stats();
+        WalStats {
+            records_written: stats.record_count(),
+            bytes_written: stats.bytes_written(),
+            padding_bytes: stats.padding_bytes(),
+            blocks_used: writer.log_writer.block_count(),
+            fsync_count: self.metrics.fsync_count(),
+        }
+    }
+
+    /// Returns the value of the property `name`, or `None` if there is no such
+    /// property or it is not available yet. See the `properties` module for the
+    /// available properties.
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        let value = match name {
+            properties::ESTIMATE_NUM_KEYS => self.memtable.read().unwrap().len() as u64,
+            properties::CUR_SIZE_ALL_MEM_TABLES => self.memtable.read().unwrap().size() as u64,
+            properties::SIZE_ALL_MEM_TABLES => {
+                self.memtable.read().unwrap().approximate_memory_usage() as u64
+            }
+            properties::TOTAL_WAL_SIZE => self.writer.lock().unwrap().log_writer.offset(),
+            // Nothing is flushed to SST files yet, so their properties are not available
+            _ => return None,
+        };
+        Some(value.to_string())
+    }
+
+    /// Returns the sequence number of the most recent write, or 0 if nothing
+    /// has been written yet.
+    ///
+    /// Every entry of a write batch is assigned the next sequence number, so
+    /// the value is strictly increasing with each write and is restored on
+    /// recovery. Layered systems can rely on it as a logical clock.
+    pub fn latest_sequence(&self) -> u64 {
+        self.writer.lock().unwrap().wal_state.last_sequence
+    }
+
+    /// Fsyncs the WAL in the background every `interval`, or stops doing so if
+    /// `None`, see `Options::wal_sync_interval`.
+    fn set_wal_sync_interval(&mut self, interval: Option<Duration>) -> error::Result<()> {
+        self.wal_syncer = None;
+        if let Some(interval) = interval {
+            let file = self.writer.get_mut().unwrap().log_writer.try_clone_file()?;
+            self.wal_syncer = Some(WalSyncer::start(
+                file,
+                interval,
+                Arc::clone(&self.metrics),
+                self.versions.manifest().state().log_number,
+                self.listeners.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of iterators that are currently open.
+    pub fn open_iterators(&self) -> usize {
+        self.iterators.open()
+    }
+
+    /// Returns the backtraces captured when each open iterator was created.
+    ///
+    /// Backtraces are only captured in debug builds, to help track down leaked
+    /// iterators; release builds always return an empty list.
+    pub fn open_iterator_backtraces(&self) -> Vec<String> {
+        self.iterators.open_backtraces()
     }
 
-    pub fn insert_or_update(&mut self, key: &[u8], value: &[u8]) -> error::Result<()> {
+    pub fn insert_or_update(&self, key: &[u8], value: &[u8]) -> error::Result<()> {
         let mut wb = write_batch::WriteBatch::new();
         wb.insert_or_update(key, value);
         self.write(&wb)
     }
 
-    pub fn write(&mut self, wb: &write_batch::WriteBatch) -> error::Result<()> {
-        self.log_writer.append(wb.as_bytes())?;
-        wal_recovery::consume_write_batch(&mut self.memtable, wb);
+    /// Inserts or updates `key` with a value that expires after `ttl`, after
+    /// which reads skip it as if it was deleted. It takes precedence over the
+    /// TTL of the DB.
+    pub fn insert_or_update_with_ttl(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> error::Result<()> {
+        let ttl_micros = u64::try_from(ttl.as_micros()).unwrap_or(u64::MAX);
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update_with_expiry(
+            key,
+            value,
+            write_batch::now_micros().saturating_add(ttl_micros),
+        );
+        self.write(&wb)
+    }
+
+    pub fn write(&self, wb: &write_batch::WriteBatch) -> error::Result<()> {
+        self.write_opt(wb, &WriteOptions::default())
+    }
+
+    /// Applies a write batch with the given options.
+    ///
+    /// Concurrent writes are applied one at a time, in the order they are
+    /// appended to the WAL.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::TimedOut)` if the write could not be committed
+    /// before the timeout in `options` elapsed, in which case none of the
+    /// batch is applied.
+    pub fn write_opt(
+        &self,
+        wb: &write_batch::WriteBatch,
+        options: &WriteOptions,
+    ) -> error::Result<()> {
+        let deadline = options.deadline(Instant::now());
+        if options.disable_wal && options.sync {
+            return Err(Error::ValueError(
+                "a write cannot both bypass and sync the WAL".to_string(),
+            ));
+        }
+        let wb = self.prepare_batch(wb);
+        let mut writer = self.writer.lock().unwrap();
+        self.write_locked(&mut writer, wb, options, deadline)
+    }
+
+    /// Applies several write batches as a single atomic unit: they are
+    /// committed to the WAL as one record, so either all of them survive a
+    /// crash or none does, and readers never see some of them without the others.
+    ///
+    /// The batches are applied in order, so a later batch wins over an earlier
+    /// one for the same key.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::ValueError)` if the batches carry different metadata,
+    /// which a single WAL record cannot hold, and otherwise the same errors as `write`.
+    pub fn write_multi(&self, batches: &[write_batch::WriteBatch]) -> error::Result<()> {
+        let Some((first, rest)) = batches.split_first() else {
+            return Ok(());
+        };
+        if rest.iter().any(|wb| wb.metadata() != first.metadata()) {
+            return Err(Error::ValueError(
+                "batches with different metadata cannot be written together".to_string(),
+            ));
+        }
+        let mut combined = first.clone();
+        for wb in rest {
+            combined.append(wb);
+        }
+        self.write(&combined)
+    }
+
+    /// Writes `new` as the value of `key` if its current value is `expected`,
+    /// atomically with respect to other writers. A `None` value stands for a
+    /// missing key, so `expected: None` inserts a key only if it does not
+    /// exist and `new: None` deletes it. An empty `new` value deletes the key
+    /// as well.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Err(CompareAndSwapError))` with the current value if it is not
+    /// `expected`, in which case nothing is written.
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> error::Result<std::result::Result<(), CompareAndSwapError>> {
+        let mut writer = self.writer.lock().unwrap();
+        self.check_writable()?;
+        let current = self.memtable.read().unwrap().get(key).map(<[u8]>::to_vec);
+        if current.as_deref() != expected {
+            return Ok(Err(CompareAndSwapError { current }));
+        }
+        self.put_locked(&mut writer, key, new)?;
+        Ok(Ok(()))
+    }
+
+    /// Replaces the value of `key` with the one `f` computes from it, atomically
+    /// with respect to other writers. `f` is passed `None` if the key does not
+    /// exist, and returns `None`, or an empty value, to delete it.
+    ///
+    /// `f` runs with the writer lock held, so it must not write to the DB.
+    ///
+    /// # Returns
+    ///
+    /// The new value of `key`, or `None` if it was deleted.
+    pub fn update(
+        &self,
+        key: &[u8],
+        f: impl FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> error::Result<Option<Vec<u8>>> {
+        let mut writer = self.writer.lock().unwrap();
+        self.check_writable()?;
+        let current = self.memtable.read().unwrap().get(key).map(<[u8]>::to_vec);
+        let new = f(current.as_deref()).filter(|value| !value.is_empty());
+        self.put_locked(&mut writer, key, new.as_deref())?;
+        Ok(new)
+    }
+
+    /// Writes `value` as the value of `key`, or deletes it if `value` is `None`,
+    /// with the writer lock held.
+    fn put_locked(
+        &self,
+        writer: &mut Writer,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> error::Result<()> {
+        let mut wb = write_batch::WriteBatch::new();
+        match value {
+            Some(value) => wb.insert_or_update(key, value),
+            None => wb.delete(key),
+        }
+        let wb = self.prepare_batch(&wb);
+        self.write_locked(writer, wb, &WriteOptions::default(), None)
+    }
+
+    /// Returns the batch to commit for `wb`, deduplicated if configured and
+    /// stamped with its write time if it can expire.
+    fn prepare_batch(&self, wb: &write_batch::WriteBatch) -> write_batch::WriteBatch {
+        let mut wb = wb.clone();
+        if self.dedup_write_batches && wb.duplicate_count() > 0 {
+            wb.dedup();
+        }
+        // TTLs count from the write time, which recovery reads from the metadata.
+        let mut metadata = wb.metadata().unwrap_or_default();
+        if (self.ttl.is_some() || metadata.ttl_secs != 0) && metadata.write_time_micros == 0 {
+            metadata.write_time_micros = write_batch::now_micros();
+            wb.set_metadata(metadata);
+        }
+        wb
+    }
+
+    /// Assigns the next sequence numbers to `wb` and commits it, with the
+    /// writer lock held.
+    fn write_locked(
+        &self,
+        writer: &mut Writer,
+        mut wb: write_batch::WriteBatch,
+        options: &WriteOptions,
+        deadline: Option<Instant>,
+    ) -> error::Result<()> {
+        self.check_writable()?;
+        if let Some(err) = self.wal_syncer.as_ref().and_then(WalSyncer::take_error) {
+            self.poison(format!("background WAL sync failed: {}", err));
+            return Err(Error::Io(err));
+        }
+        wb.set_sequence(writer.wal_state.last_sequence + 1);
+        self.write_before(writer, &wb, options, deadline)
+    }
+
+    fn write_before(
+        &self,
+        writer: &mut Writer,
+        wb: &write_batch::WriteBatch,
+        options: &WriteOptions,
+        deadline: Option<Instant>,
+    ) -> error::Result<()> {
+        let sequence = wb.sequence();
+        let _span = tracing::info_span!(
+            "write",
+            correlation_id = options.correlation_id.unwrap_or(sequence),
+            sequence,
+            count = wb.count(),
+            wal_offset = writer.log_writer.offset(),
+        )
+        .entered();
+
+        if options.disable_wal {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::TimedOut);
+            }
+            tracing::debug_span!("memtable_apply").in_scope(|| {
+                wal_recovery::consume_write_batch(
+                    &mut self.memtable.write().unwrap(),
+                    wb,
+                    wb.sequence(),
+                    self.ttl,
+                );
+                writer.wal_state.record_unlogged_batch(wb);
+            });
+            for op in wb.ops() {
+                match op {
+                    WriteOp::Put(key, _)
+                    | WriteOp::PutWithExpiry(key, ..)
+                    | WriteOp::Delete(key)
+                    | WriteOp::SingleDelete(key) => {
+                        writer.unlogged_keys.insert(key.to_vec());
+                    }
+                    WriteOp::DeleteRange(start, end) => {
+                        writer.unlogged_ranges.push((start.to_vec(), end.to_vec()));
+                    }
+                }
+            }
+            tracing::debug!("ack");
+            return Ok(());
+        }
+
+        let payload = wb.to_wal_payload(
+            self.wal_compression,
+            write_batch::DEFAULT_COMPRESSION_THRESHOLD,
+        )?;
+        // Appending to the WAL commits the batch, so this is the last point it can be abandoned.
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Error::TimedOut);
+        }
+        let appended = tracing::debug_span!("wal_append", bytes = payload.len())
+            .in_scope(|| writer.log_writer.append(&payload));
+        if let Err(err) = appended {
+            // Part of the batch may have reached the WAL.
+            self.poison(format!("WAL append failed: {}", err));
+            return Err(err);
+        }
+        tracing::debug_span!("memtable_apply").in_scope(|| {
+            wal_recovery::consume_write_batch(
+                &mut self.memtable.write().unwrap(),
+                wb,
+                wb.sequence(),
+                self.ttl,
+            );
+            writer.wal_state.record_batch(wb);
+        });
+
+        // Checkpoints count the keys in the memtable, which would include keys
+        // that recovery cannot restore until they are flushed.
+        if writer.wal_state.batches_since_checkpoint >= self.checkpoint_interval
+            && writer.unlogged_keys.is_empty()
+            && writer.unlogged_ranges.is_empty()
+        {
+            let checkpoint = writer.wal_state.checkpoint(&self.memtable.read().unwrap());
+            let checkpoint_wb = write_batch::WriteBatch::new_checkpoint(checkpoint);
+            let payload = checkpoint_wb.to_wal_payload(CompressionType::None, 0)?;
+            if let Err(err) = writer.log_writer.append(&payload) {
+                self.poison(format!("WAL checkpoint append failed: {}", err));
+                return Err(err);
+            }
+        }
+
+        // The batch is already visible, but after a failed fsync the state of
+        // the data in the page cache is unknown, so no further writes are accepted.
+        if options.sync {
+            let start = Instant::now();
+            let synced = tracing::debug_span!("wal_sync").in_scope(|| writer.log_writer.sync());
+            if let Err(err) = synced {
+                self.poison(format!("WAL sync failed: {}", err));
+                return Err(err);
+            }
+            self.notify_wal_sync(start.elapsed());
+        }
+        tracing::debug!("ack");
+        Ok(())
+    }
+
+    /// Makes the writes done with `WriteOptions::disable_wal` durable, by
+    /// appending their keys' current values to the WAL and syncing it.
+    /// Unlogged range deletions are appended again, followed by the current
+    /// values of the keys written in their ranges since. Values keep the
+    /// time they expire at.
+    ///
+    /// The DB does not write SST files yet, so the WAL is where the memtable
+    /// is persisted.
+    pub fn flush(&self) -> error::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        self.check_writable()?;
+        self.flush_locked(&mut writer)
+    }
+
+    /// Flushes the unlogged writes to the WAL of the locked `writer`, and fsyncs it.
+    fn flush_locked(&self, writer: &mut Writer) -> error::Result<()> {
+        let mut num_entries = 0;
+        if !writer.unlogged_keys.is_empty() || !writer.unlogged_ranges.is_empty() {
+            let mut wb = write_batch::WriteBatch::new();
+            let memtable = self.memtable.read().unwrap();
+            let mut keys: Vec<&[u8]> = Vec::new();
+            for (start, end) in &writer.unlogged_ranges {
+                wb.delete_range(start, end);
+                keys.extend(
+                    memtable
+                        .scan_with_expired(Bound::Included(start), Bound::Excluded(end))
+                        .map(|(key, _)| key),
+                );
+            }
+            keys.extend(writer.unlogged_keys.iter().map(Vec::as_slice));
+            for key in keys {
+                match memtable.get_with_expiry(key) {
+                    Some((value, 0)) => wb.insert_or_update(key, value),
+                    Some((value, expires_at)) => {
+                        wb.insert_or_update_with_expiry(key, value, expires_at)
+                    }
+                    None => wb.delete(key),
+                }
+            }
+            drop(memtable);
+            wb.set_sequence(writer.wal_state.last_sequence + 1);
+            self.write_before(writer, &wb, &WriteOptions::default(), None)?;
+            writer.unlogged_keys.clear();
+            writer.unlogged_ranges.clear();
+            num_entries = wb.count();
+        }
+        self.flush_log(writer, true)?;
+        let info = FlushJobInfo {
+            num_entries,
+            last_sequence: writer.wal_state.last_sequence,
+        };
+        for listener in &self.listeners {
+            listener.on_flush_completed(&info);
+        }
         Ok(())
     }
 
-    pub fn get(&self, key: &[u8]) -> error::Result<Option<&[u8]>> {
-        Ok(self.memtable.get(key))
+    /// Flushes every write like `flush`, then passes the path of each live
+    /// file of the DB to `visit`, with the number of bytes of it that belong
+    /// to the DB. Writes are held off until `visit` has seen every file.
+    ///
+    /// # Returns
+    ///
+    /// The sequence number of the last write in the files.
+    pub(crate) fn visit_live_files(
+        &self,
+        mut visit: impl FnMut(&Path, u64) -> error::Result<()>,
+    ) -> error::Result<u64> {
+        let mut writer = self.writer.lock().unwrap();
+        self.check_writable()?;
+        self.flush_locked(&mut writer)?;
+        let manifest = self.versions.manifest();
+        // The WAL may extend past its last batch, into preallocated space.
+        visit(
+            &wal_recovery::wal_file_path(&self.dir, manifest.state().log_number),
+            writer.log_writer.offset(),
+        )?;
+        for path in [
+            manifest::manifest_file_path(&self.dir, manifest.number()),
+            self.dir.join(manifest::CURRENT_FILE_NAME),
+            self.dir.join(options_file::OPTIONS_FILE_NAME),
+            self.dir.join(identity::IDENTITY_FILE_NAME),
+        ] {
+            visit(&path, fs::metadata(&path)?.len())?;
+        }
+        Ok(writer.wal_state.last_sequence)
+    }
+
+    /// Establishes a durability point for the writes done so far, e.g. after
+    /// many unsynced writes.
+    ///
+    /// # Arguments
+    ///
+    /// * `sync` - Whether to also fsync the WAL. Without it, the writes are
+    ///   handed to the OS and survive a crash of the process, but not of the machine.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the WAL cannot be flushed or synced, after which
+    /// the DB is poisoned.
+    pub fn flush_wal(&self, sync: bool) -> error::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        self.check_writable()?;
+        self.flush_log(&mut writer, sync)
+    }
+
+    /// Flushes, and if `sync` is set fsyncs, the WAL of the locked `writer`.
+    fn flush_log(&self, writer: &mut Writer, sync: bool) -> error::Result<()> {
+        let start = Instant::now();
+        let flushed = match sync {
+            true => writer.log_writer.sync(),
+            false => writer.log_writer.flush(),
+        };
+        match &flushed {
+            Err(err) => self.poison(format!("WAL flush failed: {}", err)),
+            Ok(()) if sync => self.notify_wal_sync(start.elapsed()),
+            Ok(()) => {}
+        }
+        flushed
+    }
+
+    /// Returns the value of `key`, or `None` if it does not exist.
+    ///
+    /// The value is copied out of the memtable, so holding on to it neither
+    /// blocks writes nor pins any internal state.
+    pub fn get(&self, key: &[u8]) -> error::Result<Option<Vec<u8>>> {
+        self.get_at(key, u64::MAX)
+    }
+
+    /// Returns the value of `key` like `get`, as of `options.snapshot` if set.
+    ///
+    /// The iterate bounds of `options` only apply to scans.
+    pub fn get_opt(&self, key: &[u8], options: &ReadOptions) -> error::Result<Option<Vec<u8>>> {
+        self.get_at(key, self.read_sequence(options)?)
+    }
+
+    /// Returns the sequence number to read as of with `options`.
+    fn read_sequence(&self, options: &ReadOptions) -> error::Result<u64> {
+        match options.snapshot {
+            Some(snapshot) if !snapshot.is_of(self) => Err(Error::ValueError(
+                "the snapshot was taken of another DB".to_string(),
+            )),
+            Some(snapshot) => Ok(snapshot.sequence()),
+            None => Ok(u64::MAX),
+        }
+    }
+
+    /// Returns whether `key` exists, like `get(key)?.is_some()` but without
+    /// copying its value.
+    pub fn contains_key(&self, key: &[u8]) -> error::Result<bool> {
+        self.check_readable()?;
+        Ok(self.memtable.read().unwrap().contains_key(key))
+    }
+
+    /// Returns the value of `key` as of `sequence`.
+    fn get_at(&self, key: &[u8], sequence: u64) -> error::Result<Option<Vec<u8>>> {
+        self.check_readable()?;
+        let memtable = self.memtable.read().unwrap();
+        Ok(memtable.get_at(key, sequence).map(<[u8]>::to_vec))
+    }
+
+    /// Returns a consistent view of the DB as of now: reads through the
+    /// snapshot do not observe the writes made after it was taken.
+    ///
+    /// The memtable keeps the versions of the keys overwritten or deleted
+    /// since then for as long as the snapshot is alive.
+    pub fn snapshot(&self) -> error::Result<Snapshot<'_>> {
+        self.check_readable()?;
+        // Holding the writer lock keeps the versions at the sequence number
+        // from being pruned before the snapshot is registered.
+        let writer = self.writer.lock().unwrap();
+        let sequence = writer.wal_state.last_sequence;
+        self.memtable.write().unwrap().add_snapshot(sequence);
+        Ok(Snapshot::new(self, sequence))
+    }
+
+    /// Returns an estimate of the number of bytes taken by the keys within
+    /// `range` and their values, e.g. to decide where to split a shard.
+    ///
+    /// All the data of the DB is in the memtable until it writes SSTs, so for
+    /// now this is the exact size of the keys and values, without any overhead.
+    pub fn approximate_size<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> error::Result<u64> {
+        self.check_readable()?;
+        let (start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+        if keys::is_empty_range(start, end) {
+            return Ok(0);
+        }
+        Ok(self.memtable.read().unwrap().range_size(start, end))
+    }
+
+    /// Returns whether `key` may exist, without copying its value. `false`
+    /// means the key definitely does not exist, so callers can skip work for it.
+    ///
+    /// Only in-memory state is consulted. All the keys of the DB are in the
+    /// memtable until it writes SSTs, so for now the answer is exact.
+    pub fn key_may_exist(&self, key: &[u8]) -> error::Result<bool> {
+        self.check_readable()?;
+        Ok(self.memtable.read().unwrap().get(key).is_some())
     }
 
     /// Deletes a key from the KVStore.
     /// Performs a logical delete by inserting an empty value for the key.
-    pub fn delete(&mut self, key: &[u8]) -> error::Result<()> {
+    ///
+    /// Deletes obey the following visibility rules, which every storage
+    /// component must preserve (see the `test_semantics` suite):
+    ///
+    /// * A deleted key is not returned by `get` and is skipped by `scan`.
+    /// * Deleting a key that does not exist succeeds and has no effect.
+    /// * Writing a key after deleting it makes the new value visible again.
+    /// * Operations on the same key are ordered by the WAL, so the last
+    ///   write wins, both within a `WriteBatch` and across batches.
+    /// * Inserting an empty value is equivalent to deleting the key.
+    /// * All of the above holds after recovery.
+    pub fn delete(&self, key: &[u8]) -> error::Result<()> {
         let mut wb = write_batch::WriteBatch::new();
         wb.delete(key);
         self.write(&wb)
     }
 
-    pub fn scan(&self, start: &[u8], end: &[u8]) -> error::Result<Iter> {
-        let iter = self.memtable.scan(start, end);
-        Ok(Iter { it: iter })
+    /// Deletes `key`, which must have been written at most once since it was
+    /// last deleted, e.g. a key that is inserted once and never updated.
+    ///
+    /// Reads see it as a `delete`, but compactions can drop the tombstone as
+    /// soon as it meets the value it deletes. Single deleting a key that was
+    /// overwritten may make its older values reappear.
+    pub fn single_delete(&self, key: &[u8]) -> error::Result<()> {
+        let mut wb = write_batch::WriteBatch::new();
+        wb.single_delete(key);
+        self.write(&wb)
+    }
+
+    /// Deletes every key from `start`, inclusive, to `end`, exclusive.
+    ///
+    /// The deletion is logged as a single range tombstone, whose size does not
+    /// depend on how many keys it covers. Applying it to the memtable still
+    /// visits every covered key, to count the live ones and drop the versions
+    /// no snapshot needs, so it takes time proportional to the keys deleted.
+    /// Deleted keys obey the same visibility rules as with `delete`, and an
+    /// inverted range deletes nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The first key to delete.
+    /// * `end` - The key right after the last one to delete.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> error::Result<()> {
+        let mut wb = write_batch::WriteBatch::new();
+        wb.delete_range(start, end);
+        self.write(&wb)
+    }
+
+    /// Returns an iterator over the keys within `start` and `end`, with their
+    /// values as of `sequence`.
+    fn iter(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        sequence: u64,
+    ) -> error::Result<Iter<'_>> {
+        self.check_readable()?;
+        let guard = self.iterators.register()?;
+        Ok(Iter::new(&self.memtable, guard, start, end, sequence, true))
+    }
+
+    /// Returns a cursor over the keys within `range`, which can be positioned
+    /// anywhere in it and moved in both directions, see `DBIterator`.
+    pub fn iterator<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> error::Result<DBIterator<'_>> {
+        self.check_readable()?;
+        let guard = self.iterators.register()?;
+        Ok(DBIterator::new(
+            &self.memtable,
+            guard,
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            u64::MAX,
+            true,
+        ))
+    }
+
+    /// Returns an iterator over the keys within `range`, e.g.
+    /// `kvstore.scan(&b"a"[..]..=&b"c"[..])`, or `kvstore.scan(..)` for all of them.
+    ///
+    /// A range whose start lies after its end is empty.
+    pub fn scan<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> error::Result<Iter<'_>> {
+        self.iter(
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            u64::MAX,
+        )
+    }
+
+    /// Returns an iterator over the keys within `range` like `scan`, as of
+    /// `options.snapshot` if set.
+    ///
+    /// The keys are also limited to the iterate bounds of `options`, e.g. to
+    /// restrict a scan passed in by a caller to the keys of a tenant.
+    ///
+    /// The iterator borrows the snapshot of `options`, like `Snapshot::scan`:
+    ///
+    /// ```compile_fail
+    /// # use mini_lsm::options::ReadOptions;
+    /// # let temp_dir = tempfile::TempDir::new().unwrap();
+    /// # let db = mini_lsm::DB::new(temp_dir.path()).unwrap();
+    /// let snapshot = db.snapshot().unwrap();
+    /// let iter = db.scan_opt(.., &ReadOptions::default().snapshot(&snapshot)).unwrap();
+    /// drop(snapshot);
+    /// iter.count();
+    /// ```
+    pub fn scan_opt<'s, 'k>(
+        &'s self,
+        range: impl RangeBounds<&'k [u8]>,
+        options: &ReadOptions<'s>,
+    ) -> error::Result<Iter<'s>> {
+        let start = match &options.iterate_lower_bound {
+            Some(bound) => keys::max_start(range.start_bound().cloned(), Bound::Included(bound)),
+            None => range.start_bound().cloned(),
+        };
+        let end = match &options.iterate_upper_bound {
+            Some(bound) => keys::min_end(range.end_bound().cloned(), Bound::Excluded(bound)),
+            None => range.end_bound().cloned(),
+        };
+        self.iter(start, end, self.read_sequence(options)?)
+    }
+
+    /// Returns the entries within `range`, in order, like `scan(range)?.collect()`.
+    ///
+    /// The entries do not borrow the DB, so they can outlive it or be sent to
+    /// another thread. They are all held in memory, so large ranges are better
+    /// read with `scan` or `scan_limited`.
+    pub fn scan_owned<'k>(
+        &self,
+        range: impl RangeBounds<&'k [u8]>,
+    ) -> error::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.scan(range)?.collect())
+    }
+
+    /// Calls `f` with each key within `range` and its value, in order, until
+    /// it returns `ControlFlow::Break`.
+    ///
+    /// Unlike with `scan`, nothing borrowed from the DB outlives the call, so
+    /// the scan is free to change how it reads and locks the DB. `f` must not
+    /// write to the DB, as the scan may hold internal locks while calling it.
+    ///
+    /// # Returns
+    ///
+    /// Returns the value `f` broke with, or `None` if it went through the whole range.
+    pub fn for_each_in_range<'k, B>(
+        &self,
+        range: impl RangeBounds<&'k [u8]>,
+        mut f: impl FnMut(&[u8], &[u8]) -> ControlFlow<B>,
+    ) -> error::Result<Option<B>> {
+        for (key, value) in self.scan(range)? {
+            if let ControlFlow::Break(value) = f(&key, &value) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns up to `limit` entries of `range`, e.g. to serve a page of a
+    /// paginated API without holding an iterator between requests.
+    ///
+    /// The next page is read by passing the continuation of the page as the
+    /// inclusive start of the range, with the same end. Each page is read as of
+    /// the latest data, so pages may reflect writes made between them.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The keys to read.
+    /// * `limit` - The maximum number of entries to return, which must not be 0.
+    pub fn scan_limited<'k>(
+        &self,
+        range: impl RangeBounds<&'k [u8]>,
+        limit: usize,
+    ) -> error::Result<ScanPage> {
+        if limit == 0 {
+            return Err(Error::ValueError("the limit must not be 0".to_string()));
+        }
+        let mut entries: Vec<_> = self.scan(range)?.take(limit + 1).collect();
+        if entries.len() <= limit {
+            return Ok(ScanPage {
+                entries,
+                continuation: None,
+            });
+        }
+        // The first key that did not fit, so the next page starts right there
+        let (continuation, _) = entries.pop().unwrap();
+        Ok(ScanPage {
+            entries,
+            continuation: Some(continuation),
+        })
+    }
+
+    /// Returns a handle that transparently namespaces all operations under the scope `name`.
+    pub fn scoped(&self, name: &[u8]) -> ScopedDB<'_> {
+        ScopedDB::new(self, name)
+    }
+
+    /// Returns an iterator over all the keys starting with `prefix`.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> error::Result<Iter<'_>> {
+        let successor = keys::prefix_successor(prefix);
+        let end = match &successor {
+            Some(successor) => Bound::Excluded(successor.as_slice()),
+            None => Bound::Unbounded,
+        };
+        self.iter(Bound::Included(prefix), end, u64::MAX)
+    }
+
+    /// Returns an iterator over the keys `prefix + suffix` where `suffix` is
+    /// within `range`, e.g. `kvstore.scan_prefix_range(b"t1", &b"a"[..]..)`.
+    /// An unbounded end scans to the last key starting with `prefix`.
+    ///
+    /// This is a convenience for keys composed of a fixed prefix (e.g. a table or
+    /// tenant id) followed by a sortable component.
+    pub fn scan_prefix_range<'k>(
+        &self,
+        prefix: &[u8],
+        range: impl RangeBounds<&'k [u8]>,
+    ) -> error::Result<Iter<'_>> {
+        let prefixed = |suffix: &[u8]| [prefix, suffix].concat();
+        let start = match range.start_bound() {
+            Bound::Included(suffix) => Bound::Included(prefixed(suffix)),
+            Bound::Excluded(suffix) => Bound::Excluded(prefixed(suffix)),
+            Bound::Unbounded => Bound::Included(prefix.to_vec()),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(suffix) => Bound::Included(prefixed(suffix)),
+            Bound::Excluded(suffix) => Bound::Excluded(prefixed(suffix)),
+            Bound::Unbounded => match keys::prefix_successor(prefix) {
+                Some(successor) => Bound::Excluded(successor),
+                None => Bound::Unbounded,
+            },
+        };
+        self.iter(
+            start.as_ref().map(Vec::as_slice),
+            end.as_ref().map(Vec::as_slice),
+            u64::MAX,
+        )
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        // An iterator can only outlive the DB if it was leaked (e.g. with
+        // `mem::forget`); fail loudly in debug builds so tests catch it.
+        let open = self.iterators.open();
+        if cfg!(debug_assertions) && open > 0 && !std::thread::panicking() {
+            panic!(
+                "DB dropped with {} open iterator(s), created at:\n{}",
+                open,
+                self.iterators.open_backtraces().join("\n")
+            );
+        }
+        if let Err(err) = self.close() {
+            tracing::error!(error = %err, "failed to close the DB");
+        }
     }
 }
 
@@ -86,7 +1377,7 @@ mod test_utils {
 
     use super::*;
 
-    pub fn populate(count: i32, kvstore: &mut DB) -> Vec<(i32, i32)> {
+    pub fn populate(count: i32, kvstore: &DB) -> Vec<(i32, i32)> {
         let mut data: Vec<(i32, i32)> = vec![];
         for i in 0..count {
             data.push((i, i));
@@ -101,9 +1392,9 @@ mod test_utils {
         data
     }
 
-    pub fn update(data: &mut Vec<(i32, i32)>, kvstore: &mut DB) {
+    pub fn update(data: &mut [(i32, i32)], kvstore: &DB) {
         for (_, value) in data.iter_mut() {
-            *value = 2 * (*value);
+            *value *= 2;
         }
 
         for (key, value) in data.iter() {
@@ -133,14 +1424,14 @@ mod test_utils {
                     kvstore
                         .get(key.to_be_bytes().as_ref())
                         .expect("Get failed")
-                        .expect(&format!("Expected a non-empty value for key {}", key)),
+                        .unwrap_or_else(|| panic!("Expected a non-empty value for key {}", key)),
                     value.to_be_bytes()
                 );
             }
         }
     }
 
-    pub fn delete_keys(keys: &HashSet<i32>, kvstore: &mut DB) {
+    pub fn delete_keys(keys: &HashSet<i32>, kvstore: &DB) {
         for key in keys {
             kvstore
                 .delete(key.to_be_bytes().as_ref())
@@ -151,7 +1442,9 @@ mod test_utils {
 
 #[cfg(test)]
 mod test_basic_operations {
-    use tempfile::NamedTempFile;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
 
     use self::test_utils::{delete_keys, validate_key_values};
 
@@ -159,53 +1452,170 @@ mod test_basic_operations {
 
     #[test]
     fn insert_or_update() {
-        let temp_file: NamedTempFile = NamedTempFile::new().unwrap();
-        let log_file_path = temp_file.path().to_str().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
 
-        let mut kvstore = DB::new(log_file_path).expect("Failed to create a new DB");
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
         let count = 1000;
 
         // Test inserts
-        let mut data: Vec<(i32, i32)> = test_utils::populate(count, &mut kvstore);
+        let mut data: Vec<(i32, i32)> = test_utils::populate(count, &kvstore);
 
         // Test updates
-        test_utils::update(&mut data, &mut kvstore)
+        test_utils::update(&mut data, &kvstore)
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+
+        // `None` stands for a missing key, both as expected and as new value
+        assert_eq!(
+            kvstore.compare_and_swap(b"a", None, Some(b"1")).unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            kvstore.compare_and_swap(b"a", None, Some(b"2")).unwrap(),
+            Err(CompareAndSwapError {
+                current: Some(b"1".to_vec())
+            })
+        );
+        assert_eq!(kvstore.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(
+            kvstore.compare_and_swap(b"a", Some(b"1"), None).unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            kvstore
+                .compare_and_swap(b"a", Some(b"1"), Some(b"2"))
+                .unwrap(),
+            Err(CompareAndSwapError { current: None })
+        );
+        assert_eq!(kvstore.latest_sequence(), 2);
+
+        // Concurrent increments are not lost
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        let mut current = kvstore.get(b"counter").unwrap();
+                        loop {
+                            let count = current
+                                .as_deref()
+                                .map_or(0, |count| u32::from_be_bytes(count.try_into().unwrap()));
+                            match kvstore
+                                .compare_and_swap(
+                                    b"counter",
+                                    current.as_deref(),
+                                    Some(&(count + 1).to_be_bytes()),
+                                )
+                                .unwrap()
+                            {
+                                Ok(()) => break,
+                                Err(err) => current = err.current,
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(
+            kvstore.get(b"counter").unwrap(),
+            Some(200u32.to_be_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn contains_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        kvstore.insert_or_update(b"b", b"2").unwrap();
+        kvstore.delete(b"b").unwrap();
+        kvstore
+            .insert_or_update_with_ttl(b"c", b"3", Duration::ZERO)
+            .unwrap();
+
+        assert!(kvstore.contains_key(b"a").unwrap());
+        assert!(!kvstore.contains_key(b"b").unwrap());
+        assert!(!kvstore.contains_key(b"c").unwrap());
+        assert!(!kvstore.contains_key(b"d").unwrap());
+        kvstore.close().unwrap();
+        assert!(matches!(kvstore.contains_key(b"a"), Err(Error::Closed)));
+    }
+
+    #[test]
+    fn update() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        let append = |old: Option<&[u8]>| Some([old.unwrap_or_default(), b"x"].concat());
+
+        assert_eq!(kvstore.update(b"a", append).unwrap(), Some(b"x".to_vec()));
+        assert_eq!(kvstore.update(b"a", append).unwrap(), Some(b"xx".to_vec()));
+        assert_eq!(kvstore.get(b"a").unwrap(), Some(b"xx".to_vec()));
+        // Returning `None` or an empty value deletes the key
+        assert_eq!(kvstore.update(b"a", |_| None).unwrap(), None);
+        assert_eq!(kvstore.get(b"a").unwrap(), None);
+        kvstore.insert_or_update(b"b", b"1").unwrap();
+        assert_eq!(kvstore.update(b"b", |_| Some(Vec::new())).unwrap(), None);
+        assert_eq!(kvstore.get(b"b").unwrap(), None);
+
+        // Concurrent updates are not lost
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        kvstore.update(b"c", append).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(kvstore.get(b"c").unwrap(), Some(vec![b'x'; 200]));
     }
 
     #[test]
     fn get() {
-        let temp_file: NamedTempFile = NamedTempFile::new().unwrap();
-        let log_file_path = temp_file.path().to_str().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
 
-        let mut kvstore = DB::new(&log_file_path).expect("Failed to create a new DB");
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
         let count = 1000;
 
         // Check that a non-exisitent key returns an empty value
         assert!(kvstore
-            .get((1 as i32).to_be_bytes().as_ref())
+            .get(1_i32.to_be_bytes().as_ref())
             .expect("Get failed")
             .is_none());
 
         // Populate the KVStore and validate the data
-        let mut data = test_utils::populate(count, &mut kvstore);
-        test_utils::validate_key_values(&data, None, &mut kvstore);
+        let mut data = test_utils::populate(count, &kvstore);
+        test_utils::validate_key_values(&data, None, &kvstore);
 
         // Update all the values and validate the data
-        test_utils::update(&mut data, &mut kvstore);
-        test_utils::validate_key_values(&data, None, &mut kvstore);
+        test_utils::update(&mut data, &kvstore);
+        test_utils::validate_key_values(&data, None, &kvstore);
+
+        // A value read earlier outlives later writes to its key
+        let key = data[0].0.to_be_bytes();
+        let value = kvstore.get(&key).unwrap();
+        assert!(kvstore.key_may_exist(&key).unwrap());
+        kvstore.delete(&key).unwrap();
+        assert_eq!(value, Some(data[0].1.to_be_bytes().to_vec()));
+        assert!(!kvstore.key_may_exist(&key).unwrap());
     }
 
     #[test]
     fn delete() {
-        let temp_file: NamedTempFile = NamedTempFile::new().unwrap();
-        let log_file_path = temp_file.path().to_str().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
 
-        let mut kvstore = DB::new(&log_file_path).expect("Failed to create a new DB");
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
         let count = 1000;
 
         // Populate the KVStore and validate the data
-        let data = test_utils::populate(count, &mut kvstore);
-        test_utils::validate_key_values(&data, None, &mut kvstore);
+        let data = test_utils::populate(count, &kvstore);
+        test_utils::validate_key_values(&data, None, &kvstore);
 
         // Delete half the keys and validate that they are deleted
         let mut keys_to_delete = std::collections::HashSet::new();
@@ -214,67 +1624,849 @@ mod test_basic_operations {
                 keys_to_delete.insert(*key);
             }
         }
-        delete_keys(&keys_to_delete, &mut kvstore);
+        delete_keys(&keys_to_delete, &kvstore);
         validate_key_values(&data, Some(&keys_to_delete), &kvstore);
     }
 
     #[test]
-    fn scan() {
-        let temp_file: NamedTempFile = NamedTempFile::new().unwrap();
-        let log_file_path = temp_file.path().to_str().unwrap();
-
-        let mut kvstore = DB::new(&log_file_path).expect("Failed to create a new DB");
-        let count = 1000;
+    fn read_options() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        for key in [b"a", b"b", b"c", b"d"] {
+            kvstore.insert_or_update(key, b"1").unwrap();
+        }
+        let snapshot = kvstore.snapshot().unwrap();
+        kvstore.insert_or_update(b"b", b"2").unwrap();
+        kvstore.delete(b"c").unwrap();
 
-        let mut data = test_utils::populate(count, &mut kvstore);
-        data.sort();
+        // The iterate bounds narrow the range of the scan
+        let options = ReadOptions::default()
+            .iterate_lower_bound(b"b")
+            .iterate_upper_bound(b"d");
+        let keys = |iter: Iter| iter.map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(keys(kvstore.scan_opt(.., &options).unwrap()), [b"b"]);
+        assert_eq!(
+            keys(kvstore.scan_opt(&b"a"[..]..=&b"b"[..], &options).unwrap()),
+            [b"b"]
+        );
+        assert_eq!(
+            kvstore.get_opt(b"a", &options).unwrap(),
+            Some(b"1".to_vec())
+        );
 
-        let start_idx = data.len() / 2;
-        let end_idx = data.len() - 1;
+        // Reads as of a snapshot
+        let options = options.snapshot(&snapshot);
+        assert_eq!(
+            kvstore.scan_opt(.., &options).unwrap().collect::<Vec<_>>(),
+            [
+                (b"b".to_vec(), b"1".to_vec()),
+                (b"c".to_vec(), b"1".to_vec())
+            ]
+        );
+        assert_eq!(
+            kvstore.get_opt(b"b", &options).unwrap(),
+            Some(b"1".to_vec())
+        );
 
-        let mut result = Vec::new();
-        for (key, value) in kvstore
-            .scan(
-                data[start_idx].0.to_be_bytes().as_ref(),
-                data[end_idx].0.to_be_bytes().as_ref(),
-            )
-            .expect("range query returned an error")
-        {
-            let key = i32::from_be_bytes(key.try_into().unwrap());
-            let value = i32::from_be_bytes(value.try_into().unwrap());
-            result.push((key, value));
-        }
-        assert_eq!(result, &data[start_idx..end_idx]);
+        // A snapshot of another DB is rejected
+        let other_dir = TempDir::new().unwrap();
+        let other = DB::new(other_dir.path()).expect("Failed to create a new DB");
+        assert!(matches!(
+            other.get_opt(b"b", &options),
+            Err(Error::ValueError(_))
+        ));
     }
-}
 
-#[cfg(test)]
-/// Module for testing recovery functionality.
-mod test_recovery {
-    use tempfile::NamedTempFile;
+    #[test]
+    fn write_multi() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        let batch = |ops: &[(&[u8], &[u8])]| {
+            let mut wb = write_batch::WriteBatch::new();
+            for (key, value) in ops {
+                wb.insert_or_update(key, value);
+            }
+            wb
+        };
+        kvstore.write_multi(&[]).unwrap();
+        let sequence = kvstore.latest_sequence();
+        kvstore
+            .write_multi(&[
+                batch(&[(b"a", b"1"), (b"b", b"1")]),
+                batch(&[(b"b", b"2"), (b"c", b"2")]),
+            ])
+            .unwrap();
+        // Committed as a single WAL record
+        assert_eq!(kvstore.latest_sequence(), sequence + 4);
+        assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
 
-    use super::*;
-    use crate::test_utils::{delete_keys, validate_key_values};
+        let mut tagged = batch(&[(b"d", b"3")]);
+        tagged.set_metadata(write_batch::BatchMetadata {
+            origin_id: 1,
+            ..Default::default()
+        });
+        assert!(matches!(
+            kvstore.write_multi(&[batch(&[(b"d", b"3")]), tagged]),
+            Err(Error::ValueError(_))
+        ));
+        assert_eq!(kvstore.get(b"d").unwrap(), None);
+
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Failed to reopen the DB");
+        assert_eq!(
+            kvstore.scan_owned(..).unwrap(),
+            [
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"2".to_vec()),
+                (b"c".to_vec(), b"2".to_vec())
+            ]
+        );
+    }
 
     #[test]
-    fn recovery() {
-        let temp_file: NamedTempFile = NamedTempFile::new().unwrap();
-        let log_file_path = temp_file.path().to_str().unwrap();
+    fn write_multi_during_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        let key = |i: u32| i.to_be_bytes().to_vec();
+        // Enough keys for a scan to read ahead several times
+        for i in 0..200 {
+            kvstore.insert_or_update(&key(i), b"old").unwrap();
+        }
+        let update = |keys: &[u32]| {
+            let batches: Vec<_> = keys
+                .iter()
+                .map(|&i| {
+                    let mut wb = write_batch::WriteBatch::new();
+                    wb.insert_or_update(&key(i), b"new");
+                    wb
+                })
+                .collect();
+            kvstore.write_multi(&batches).unwrap();
+        };
 
-        let mut kvstore = DB::new(&log_file_path).expect("Failed to create a new DB");
-        let count = 1000;
+        let mut scan = kvstore.scan(..).unwrap();
+        let mut it = kvstore.iterator(..).unwrap();
+        it.seek_to_first();
+        assert_eq!(scan.next(), Some((key(0), b"old".to_vec())));
+        update(&[10, 150]);
+        // Neither half of the batches is seen, even once read ahead past the first
+        let rest: Vec<_> = scan.collect();
+        assert_eq!(rest.len(), 199);
+        assert!(rest.iter().all(|(_, value)| value == b"old"));
+        it.seek(&key(150));
+        assert_eq!(it.value(), Some(&b"old"[..]));
 
-        /**********************************/
-        /*           STAGE 1              */
-        /* Basic CRUD Operations Testing  */
-        /**********************************/
+        it.refresh();
+        it.seek(&key(10));
+        assert_eq!(it.value(), Some(&b"new"[..]));
+        it.seek(&key(150));
+        assert_eq!(it.value(), Some(&b"new"[..]));
+        drop(it);
+        assert_eq!(kvstore.get(&key(150)).unwrap(), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn scan_owned() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        for i in 0..5u8 {
+            kvstore.insert_or_update(&[i], &[i]).unwrap();
+        }
+        let entries = kvstore.scan_owned(&[1u8][..]..=&[3u8][..]).unwrap();
+        assert_eq!(kvstore.iterators.open(), 0);
+        drop(kvstore);
+
+        // The entries outlive the DB and can be moved to another thread
+        let keys = std::thread::spawn(move || {
+            entries
+                .into_iter()
+                .map(|(key, _)| key[0])
+                .collect::<Vec<_>>()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(keys, [1, 2, 3]);
+    }
+
+    #[test]
+    fn for_each_in_range() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        for i in 0..10u8 {
+            kvstore.insert_or_update(&[i], &[i * 2]).unwrap();
+        }
+
+        let mut sum = 0;
+        let result = kvstore
+            .for_each_in_range(&[2u8][..]..&[5u8][..], |_, value| {
+                sum += value[0];
+                ControlFlow::<()>::Continue(())
+            })
+            .unwrap();
+        assert_eq!((result, sum), (None, 4 + 6 + 8));
+
+        // Breaking stops the scan and returns the value broken with
+        let mut visited = 0;
+        let found = kvstore
+            .for_each_in_range(.., |key, value| {
+                visited += 1;
+                match value[0] > 10 {
+                    true => ControlFlow::Break(key.to_vec()),
+                    false => ControlFlow::Continue(()),
+                }
+            })
+            .unwrap();
+        assert_eq!((found, visited), (Some(vec![6]), 7));
+        assert_eq!(kvstore.iterators.open(), 0);
+    }
+
+    #[test]
+    fn scan_limited() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        for i in 0..10u8 {
+            kvstore.insert_or_update(&[i], &[i]).unwrap();
+        }
+        assert!(matches!(
+            kvstore.scan_limited(.., 0),
+            Err(Error::ValueError(_))
+        ));
+
+        // Pages resume where the previous one stopped, up to the end of the range
+        let end = [9u8];
+        let mut start = vec![1u8];
+        let mut pages = Vec::new();
+        loop {
+            let page = kvstore.scan_limited(&start[..]..&end[..], 3).unwrap();
+            pages.push(
+                page.entries
+                    .iter()
+                    .map(|(key, _)| key[0])
+                    .collect::<Vec<_>>(),
+            );
+            match page.continuation {
+                Some(continuation) => start = continuation,
+                None => break,
+            }
+        }
+        assert_eq!(pages, [vec![1, 2, 3], vec![4, 5, 6], vec![7, 8]]);
+
+        // A page that holds the rest of the range exactly has no continuation
+        let page = kvstore.scan_limited(&[7u8][..]..&end[..], 2).unwrap();
+        assert_eq!(page.entries.len(), 2);
+        assert_eq!(page.continuation, None);
+    }
+
+    #[test]
+    fn scan() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        let count = 1000;
+
+        let mut data = test_utils::populate(count, &kvstore);
+        data.sort();
+
+        let start_idx = data.len() / 2;
+        let end_idx = data.len() - 1;
+
+        let start = data[start_idx].0.to_be_bytes();
+        let end = data[end_idx].0.to_be_bytes();
+        let scan = |range: (Bound<&[u8]>, Bound<&[u8]>)| -> Vec<(i32, i32)> {
+            kvstore
+                .scan(range)
+                .expect("range query returned an error")
+                .map(|(key, value)| {
+                    let key = i32::from_be_bytes(key.try_into().unwrap());
+                    let value = i32::from_be_bytes(value.try_into().unwrap());
+                    (key, value)
+                })
+                .collect()
+        };
+        let result = scan((Bound::Included(&start), Bound::Excluded(&end)));
+        assert_eq!(result, &data[start_idx..end_idx]);
+
+        // Both ends can be inclusive, exclusive or unbounded
+        let result = scan((Bound::Excluded(&start), Bound::Included(&end)));
+        assert_eq!(result, &data[start_idx + 1..=end_idx]);
+        let result = scan((Bound::Included(&start), Bound::Unbounded));
+        assert_eq!(result, &data[start_idx..]);
+        let result = scan((Bound::Unbounded, Bound::Excluded(&end)));
+        assert_eq!(result, &data[..end_idx]);
+        assert_eq!(kvstore.scan(..).unwrap().count(), data.len());
+        assert_eq!(kvstore.scan(&end[..]..=&end[..]).unwrap().count(), 1);
+
+        // Inverted ranges are empty
+        assert_eq!(kvstore.scan(&end[..]..&start[..]).unwrap().count(), 0);
+        assert_eq!(kvstore.scan(&end[..]..&end[..]).unwrap().count(), 0);
+
+        // Every entry takes a 4-byte key and a 4-byte value
+        let size = kvstore.approximate_size(&start[..]..&end[..]).unwrap();
+        assert_eq!(size, 8 * (end_idx - start_idx) as u64);
+        assert_eq!(kvstore.approximate_size(..).unwrap(), 8 * data.len() as u64);
+        assert_eq!(kvstore.approximate_size(&end[..]..&start[..]).unwrap(), 0);
+
+        // An open iterator does not hold up writes, and does not return them
+        let mut iter = kvstore.scan_prefix(b"").unwrap();
+        assert!(iter.next().is_some());
+        kvstore.insert_or_update(&[0], b"before").unwrap();
+        kvstore.insert_or_update(&[0xff; 8], b"after").unwrap();
+        assert_eq!(iter.count(), data.len() - 1);
+    }
+
+    #[test]
+    fn concurrent_reads_and_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let kvstore = Arc::new(DB::new(db_path).expect("Failed to create a new DB"));
+        let threads = 4;
+        let count: i32 = 500;
+
+        let writers: Vec<_> = (0..threads)
+            .map(|thread| {
+                let kvstore = Arc::clone(&kvstore);
+                std::thread::spawn(move || {
+                    for i in 0..count {
+                        let key = (thread * count + i).to_be_bytes();
+                        kvstore.insert_or_update(&key, &i.to_be_bytes()).unwrap();
+                        assert_eq!(kvstore.get(&key).unwrap(), Some(i.to_be_bytes().to_vec()));
+                    }
+                })
+            })
+            .collect();
+        // Scans see every write in full while the writers are running
+        while !writers.iter().all(|writer| writer.is_finished()) {
+            for (key, value) in kvstore.scan_prefix(b"").unwrap() {
+                let key = i32::from_be_bytes(key.try_into().unwrap());
+                assert_eq!(value, (key % count).to_be_bytes());
+            }
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let total = (threads * count) as usize;
+        assert_eq!(kvstore.scan_prefix(b"").unwrap().count(), total);
+        assert_eq!(kvstore.latest_sequence(), total as u64);
+
+        // The writes were serialized in the WAL
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Recovery failed");
+        assert_eq!(kvstore.scan_prefix(b"").unwrap().count(), total);
+        assert_eq!(kvstore.latest_sequence(), total as u64);
+    }
+
+    #[test]
+    fn write_timeout() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"a", b"1");
+        wb.insert_or_update(b"b", b"2");
+
+        // A write that times out leaves no trace
+        let expired = WriteOptions::default().timeout(Duration::ZERO);
+        assert!(matches!(
+            kvstore.write_opt(&wb, &expired),
+            Err(Error::TimedOut)
+        ));
+        assert_eq!(kvstore.latest_sequence(), 0);
+        assert_eq!(kvstore.get(b"a").unwrap(), None);
+
+        let options = WriteOptions::default().timeout(Duration::from_secs(60));
+        kvstore.write_opt(&wb, &options).expect("Write failed");
+        assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
+
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        assert_eq!(kvstore.latest_sequence(), 2);
+    }
+
+    #[test]
+    fn write_durability() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        assert_eq!(kvstore.metrics().fsync_count(), 0);
+
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"b", b"2");
+        kvstore
+            .write_opt(&wb, &WriteOptions::default().sync(true))
+            .expect("Write failed");
+        assert_eq!(kvstore.metrics().fsync_count(), 1);
+
+        // Only a synced WAL flush fsyncs
+        kvstore.flush_wal(false).unwrap();
+        assert_eq!(kvstore.metrics().fsync_count(), 1);
+        kvstore.flush_wal(true).unwrap();
+        assert_eq!(kvstore.metrics().fsync_count(), 2);
+
+        drop(kvstore);
+        let options = Options::default().wal_sync_interval(Some(Duration::from_millis(1)));
+        let mut kvstore = DB::open(db_path, options).expect("Failed to reopen the DB");
+        let fsync_count = kvstore.metrics().fsync_count();
+        let start = Instant::now();
+        while kvstore.metrics().fsync_count() < fsync_count + 2 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // Closing the DB stops the background sync and joins the thread
+        kvstore.close().unwrap();
+        let fsync_count = kvstore.metrics().fsync_count();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(kvstore.metrics().fsync_count(), fsync_count);
+    }
+
+    #[test]
+    fn open_with_options() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let options = Options::default()
+            .block_size(4096)
+            .wal_compression(CompressionType::Snappy)
+            .dedup_write_batches(true)
+            .max_open_iterators(Some(1))
+            .wal_preallocation(None);
+
+        let kvstore = DB::open(db_path, options.clone()).unwrap();
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"a", &[1; 10_000]);
+        wb.insert_or_update(b"a", &[2; 10_000]);
+        kvstore.write(&wb).unwrap();
+        let _iter = kvstore.scan(&b"a"[..]..b"z").unwrap();
+        assert!(matches!(
+            kvstore.scan(&b"a"[..]..b"z"),
+            Err(Error::TooManyIterators(1))
+        ));
+        drop(_iter);
+
+        // The batch was deduplicated and compressed into a single small record
+        assert_eq!(kvstore.wal_stats().records_written, 1);
+        assert_eq!(kvstore.wal_stats().blocks_used, 1);
+        let wal_path = kvstore.wal_path();
+        drop(kvstore);
+        let header = log_header::LogHeader::read(File::open(wal_path).unwrap()).unwrap();
+        assert_eq!(header.block_size, 4096);
+
+        let kvstore = DB::open(db_path, options).unwrap();
+        assert_eq!(kvstore.get(b"a").unwrap(), Some([2; 10_000].to_vec()));
+        drop(kvstore);
+
+        // The block size cannot change over the lifetime of the DB
+        assert!(matches!(
+            DB::new(db_path),
+            Err(Error::IncompatibleOptions(_))
+        ));
+        assert!(matches!(
+            DB::open(
+                TempDir::new().unwrap().path(),
+                Options::default().block_size(1)
+            ),
+            Err(Error::ValueError(_))
+        ));
+    }
+
+    #[test]
+    fn wal_stats() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        // The header of the WAL takes the start of the first block
+        let stats = kvstore.wal_stats();
+        assert_eq!(stats.records_written, 0);
+        assert_eq!(stats.blocks_used, 1);
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        kvstore.insert_or_update(b"b", &[2; 40_000]).unwrap();
+        kvstore.flush_wal(true).unwrap();
+
+        // The large value spans two blocks
+        let stats = kvstore.wal_stats();
+        assert_eq!(stats.records_written, 3);
+        assert_eq!(stats.padding_bytes, 0);
+        assert_eq!(stats.blocks_used, 2);
+        assert_eq!(stats.fsync_count, 1);
+        assert_eq!(
+            stats.bytes_written,
+            fs::metadata(kvstore.wal_path()).unwrap().len() - log_header::LOG_HEADER_SIZE as u64
+        );
+    }
+
+    #[test]
+    fn get_property() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        kvstore.insert_or_update(b"b", b"22").unwrap();
+        kvstore.insert_or_update(b"b", b"333").unwrap();
+        kvstore.insert_or_update(b"c", b"4").unwrap();
+        kvstore.delete(b"c").unwrap();
+
+        let property = |name: &str| kvstore.get_property(name);
+        assert_eq!(property(properties::ESTIMATE_NUM_KEYS).unwrap(), "2");
+        // The tombstone of `c` counts its key
+        assert_eq!(property(properties::CUR_SIZE_ALL_MEM_TABLES).unwrap(), "7");
+        let memory_usage: usize = property(properties::SIZE_ALL_MEM_TABLES)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(memory_usage > 6);
+        assert_eq!(property(properties::TOTAL_SST_FILES_SIZE), None);
+        assert_eq!(
+            property(properties::TOTAL_WAL_SIZE).unwrap(),
+            fs::metadata(kvstore.wal_path()).unwrap().len().to_string()
+        );
+        assert_eq!(property("mini-lsm.num-files-at-level0"), None);
+        assert_eq!(property("mini-lsm.num-files-at-levelx"), None);
+        assert_eq!(property("mini-lsm.unknown"), None);
+    }
+
+    #[test]
+    fn tail_wal() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        let mut tail = kvstore.tail_wal(Duration::from_millis(1)).unwrap();
+        tail.set_timeout(Some(Duration::from_millis(10)));
+
+        let wb = tail.next_batch().unwrap().unwrap();
+        assert_eq!(
+            wb.iter().collect::<Vec<_>>(),
+            vec![(&b"a"[..], Some(&b"1"[..]))]
+        );
+        assert!(tail.next_batch().is_none());
+
+        // Batches written later are picked up
+        kvstore.delete(b"a").unwrap();
+        let wb = tail.next_batch().unwrap().unwrap();
+        assert_eq!(wb.iter().collect::<Vec<_>>(), vec![(&b"a"[..], None)]);
+        assert!(tail.next_batch().is_none());
+
+        // A new tail resumes at the offset reported by the previous one
+        let offset = tail.offset();
+        drop(tail);
+        kvstore.insert_or_update(b"b", b"2").unwrap();
+        let mut tail = kvstore
+            .tail_wal_from(offset, Duration::from_millis(1))
+            .unwrap();
+        tail.set_timeout(Some(Duration::from_millis(10)));
+        let wb = tail.next_batch().unwrap().unwrap();
+        assert_eq!(
+            wb.iter().collect::<Vec<_>>(),
+            vec![(&b"b"[..], Some(&b"2"[..]))]
+        );
+        assert!(tail.next_batch().is_none());
+    }
+
+    #[test]
+    fn tail_wal_offset_skips_partial_batches() {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path().join("db")).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        kvstore.insert_or_update(b"b", &[2; 100_000]).unwrap();
+        let wal = fs::read(kvstore.wal_path()).unwrap();
+        let mut tail = wal_tail::WalTail::new(
+            kvstore.wal_path().to_str().unwrap(),
+            0,
+            Duration::from_millis(1),
+        )
+        .unwrap();
+        tail.set_timeout(Some(Duration::from_millis(10)));
+        tail.next_batch().unwrap().unwrap();
+        let first_batch_end = tail.offset();
+
+        // A copy of the WAL whose second batch is only partially appended
+        let copy_path = temp_dir.path().join("wal");
+        let copy_path = copy_path.to_str().unwrap();
+        fs::write(copy_path, &wal[..first_batch_end as usize + 50_000]).unwrap();
+
+        let mut tail = wal_tail::WalTail::new(copy_path, 0, Duration::from_millis(1)).unwrap();
+        tail.set_timeout(Some(Duration::from_millis(10)));
+        tail.next_batch().unwrap().unwrap();
+        assert!(tail.next_batch().is_none());
+        assert_eq!(tail.offset(), first_batch_end);
+
+        // Resuming at the offset reads the batch in full once it is appended
+        fs::write(copy_path, &wal).unwrap();
+        let mut tail =
+            wal_tail::WalTail::new(copy_path, first_batch_end, Duration::from_millis(1)).unwrap();
+        tail.set_timeout(Some(Duration::from_millis(10)));
+        let wb = tail.next_batch().unwrap().unwrap();
+        assert_eq!(
+            wb.iter().collect::<Vec<_>>(),
+            vec![(&b"b"[..], Some(&[2; 100_000][..]))]
+        );
+        assert!(tail.offset() > first_batch_end + 100_000);
+    }
+
+    #[test]
+    fn state_transitions() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let mut kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        assert_eq!(kvstore.state(), DbState::Opened);
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+
+        // A poisoned DB serves reads but rejects writes
+        kvstore.poison("injected failure".to_string());
+        assert_eq!(kvstore.state(), DbState::Poisoned);
+        assert_eq!(kvstore.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kvstore.scan(&b"a"[..]..b"z").unwrap().count(), 1);
+        match kvstore.insert_or_update(b"b", b"2") {
+            Err(Error::Poisoned(reason)) => assert_eq!(reason, "injected failure"),
+            _ => panic!("Expected a Poisoned error"),
+        }
+
+        // A closed DB rejects everything
+        kvstore.close().unwrap();
+        kvstore.close().unwrap();
+        assert_eq!(kvstore.state(), DbState::Closed);
+        assert!(matches!(kvstore.get(b"a"), Err(Error::Closed)));
+        assert!(matches!(kvstore.key_may_exist(b"a"), Err(Error::Closed)));
+        assert!(matches!(kvstore.scan_prefix(b"a"), Err(Error::Closed)));
+        assert!(matches!(kvstore.delete(b"a"), Err(Error::Closed)));
+
+        // Closing releases the directory, and reopening recovers from the WAL
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        assert_eq!(kvstore.state(), DbState::Opened);
+        assert_eq!(kvstore.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kvstore.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn dedup_write_batches() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let options = Options::default().dedup_write_batches(true);
+        let kvstore = DB::open(db_path, options).expect("Failed to create a new DB");
+
+        let mut wb = write_batch::WriteBatch::new();
+        for i in 0..100i32 {
+            wb.insert_or_update(b"key", &i.to_be_bytes());
+        }
+        kvstore.write(&wb).expect("Write failed");
+
+        // Only the last write was committed
+        assert_eq!(kvstore.latest_sequence(), 1);
+        assert_eq!(
+            kvstore.get(b"key").expect("Get failed"),
+            Some(99i32.to_be_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn scan_prefix() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        for prefix in [[0x01u8], [0x02], [0xff]] {
+            for suffix in 0..10u8 {
+                kvstore
+                    .insert_or_update(&[prefix[0], suffix], &[suffix])
+                    .expect("Insert failed");
+            }
+        }
+
+        let keys = |it: Iter| it.map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+
+        let result = keys(kvstore.scan_prefix(&[0x02]).expect("Scan failed"));
+        assert_eq!(result, (0..10u8).map(|s| vec![0x02, s]).collect::<Vec<_>>());
+
+        // A prefix without a successor scans to the end of the keyspace
+        let result = keys(kvstore.scan_prefix(&[0xff]).expect("Scan failed"));
+        assert_eq!(result, (0..10u8).map(|s| vec![0xff, s]).collect::<Vec<_>>());
+
+        let result = keys(
+            kvstore
+                .scan_prefix_range(&[0x01], &[3][..]..&[6][..])
+                .expect("Scan failed"),
+        );
+        assert_eq!(result, vec![vec![0x01, 3], vec![0x01, 4], vec![0x01, 5]]);
+
+        // An unbounded end stops at the end of the prefix
+        let result = keys(
+            kvstore
+                .scan_prefix_range(&[0x01], &[8][..]..)
+                .expect("Scan failed"),
+        );
+        assert_eq!(result, vec![vec![0x01, 8], vec![0x01, 9]]);
+        let result = keys(kvstore.scan_prefix_range(&[0xff], ..).expect("Scan failed"));
+        assert_eq!(result, (0..10u8).map(|s| vec![0xff, s]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn db_identity_is_stable() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("db");
+
+        let identity = DB::new(&db_path)
+            .expect("Failed to create a new DB")
+            .get_db_identity()
+            .to_string();
+        let kvstore = DB::new(&db_path).expect("Failed to create a new DB");
+        assert_eq!(kvstore.get_db_identity(), identity);
+
+        let other = DB::new(temp_dir.path().join("other")).expect("Failed to create a new DB");
+        assert_ne!(other.get_db_identity(), identity);
+    }
+
+    #[test]
+    fn directory_layout() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("db");
+
+        let kvstore = DB::new(&db_path).expect("Failed to create a new DB");
+        assert_eq!(kvstore.path(), db_path);
+        kvstore.insert_or_update(b"key", b"value").unwrap();
+
+        let mut files: Vec<_> = fs::read_dir(&db_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "000002.log",
+                "CURRENT",
+                "IDENTITY",
+                "LOCK",
+                "MANIFEST-000001",
+                "OPTIONS"
+            ]
+        );
+
+        // The DB cannot be opened twice, even through a different path
+        match DB::new(db_path.join("..").join("db")) {
+            Err(Error::AlreadyOpen(_)) => {}
+            _ => panic!("Expected an AlreadyOpen error"),
+        }
+        drop(kvstore);
+
+        // The LOCK file keeps out other processes
+        let lock = File::open(db_path.join(LOCK_FILE_NAME)).unwrap();
+        lock.lock().unwrap();
+        match DB::new(&db_path) {
+            Err(Error::Locked(_)) => {}
+            _ => panic!("Expected a Locked error"),
+        }
+        drop(lock);
+        let kvstore = DB::new(&db_path).expect("Failed to reopen the DB");
+        assert_eq!(kvstore.get(b"key").unwrap(), Some(b"value".to_vec()));
+
+        // An open DB cannot be destroyed
+        assert!(matches!(
+            DB::destroy(&db_path, &Options::default()),
+            Err(Error::AlreadyOpen(_))
+        ));
+        drop(kvstore);
+        let lock = File::open(db_path.join(LOCK_FILE_NAME)).unwrap();
+        lock.lock().unwrap();
+        assert!(matches!(
+            DB::destroy(&db_path, &Options::default()),
+            Err(Error::Locked(_))
+        ));
+        drop(lock);
+
+        // Destroying the DB deletes its files and then its directory
+        DB::destroy(&db_path, &Options::default()).unwrap();
+        assert!(!db_path.exists());
+        DB::destroy(&db_path, &Options::default()).unwrap();
+
+        // Files that don't belong to the DB are kept
+        drop(DB::new(&db_path).unwrap());
+        fs::write(db_path.join("notes.txt"), b"keep").unwrap();
+        fs::write(db_path.join("000001.log.bak"), b"keep").unwrap();
+        DB::destroy(&db_path, &Options::default()).unwrap();
+        let mut files: Vec<_> = fs::read_dir(&db_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["000001.log.bak", "notes.txt"]);
+    }
+
+    #[test]
+    fn max_open_iterators() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let options = Options::default().max_open_iterators(Some(2));
+        let kvstore = DB::open(db_path, options).expect("Failed to create a new DB");
+
+        let first = kvstore.scan(&b"a"[..]..b"z").expect("Scan failed");
+        let second = kvstore.scan_prefix(b"a").expect("Scan failed");
+        assert_eq!(kvstore.open_iterators(), 2);
+        assert!(matches!(
+            kvstore.scan_prefix_range(b"a", &b"b"[..]..b"c"),
+            Err(Error::TooManyIterators(2))
+        ));
+
+        drop(first);
+        assert_eq!(kvstore.open_iterators(), 1);
+        assert!(kvstore.scan(&b"a"[..]..b"z").is_ok());
+
+        drop(second);
+        assert_eq!(kvstore.open_iterators(), 0);
+        assert!(kvstore.open_iterator_backtraces().is_empty());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "open iterator")]
+    fn leaked_iterator_fails_on_drop() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        std::mem::forget(kvstore.scan(&b"a"[..]..b"z").expect("Scan failed"));
+    }
+}
+
+#[cfg(test)]
+/// Module for testing recovery functionality.
+mod test_recovery {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        options::WalChecksum,
+        test_utils::{delete_keys, validate_key_values},
+    };
+
+    #[test]
+    fn recovery() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        let count = 1000;
+
+        /**********************************/
+        /*           STAGE 1              */
+        /* Basic CRUD Operations Testing  */
+        /**********************************/
 
         // Insert initial set of values into the database
-        let mut data = test_utils::populate(count, &mut kvstore);
+        let mut data = test_utils::populate(count, &kvstore);
         validate_key_values(&data, None, &kvstore);
 
         // Update certain values in the database
-        test_utils::update(&mut data, &mut kvstore);
+        test_utils::update(&mut data, &kvstore);
         validate_key_values(&data, None, &kvstore);
 
         // Delete every second key from the database
@@ -284,7 +2476,7 @@ mod test_recovery {
                 keys_to_delete.insert(*key);
             }
         }
-        delete_keys(&keys_to_delete, &mut kvstore);
+        delete_keys(&keys_to_delete, &kvstore);
         validate_key_values(&data, Some(&keys_to_delete), &kvstore);
 
         /**********************************/
@@ -293,7 +2485,696 @@ mod test_recovery {
         /**********************************/
 
         // Re-instantiate the database to simulate recovery and validate the integrity of data post-recovery
-        let kvstore = DB::new(log_file_path).expect("Failed to create a new DB");
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
         validate_key_values(&data, Some(&keys_to_delete), &kvstore);
     }
+
+    #[test]
+    fn latest_sequence_is_monotonic_across_restarts() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        assert_eq!(kvstore.latest_sequence(), 0);
+
+        test_utils::populate(10, &kvstore);
+        assert_eq!(kvstore.latest_sequence(), 10);
+
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"key", b"value");
+        wb.delete(b"key");
+        kvstore.write(&wb).expect("Write failed");
+        assert_eq!(kvstore.latest_sequence(), 12);
+
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        assert_eq!(kvstore.latest_sequence(), 12);
+
+        kvstore.delete(b"key").expect("Delete failed");
+        assert_eq!(kvstore.latest_sequence(), 13);
+    }
+
+    #[test]
+    fn recovery_of_writes_bypassing_the_wal() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let unlogged = WriteOptions::default().disable_wal(true);
+
+        let mut kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        kvstore.checkpoint_interval = 2;
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"b", b"2");
+        wb.delete(b"a");
+        kvstore.write_opt(&wb, &unlogged).unwrap();
+        assert_eq!(kvstore.get(b"a").unwrap(), None);
+        assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert!(matches!(
+            kvstore.write_opt(&wb, &unlogged.clone().sync(true)),
+            Err(Error::ValueError(_))
+        ));
+
+        // Unlogged writes are lost by a crash, but still consumed sequences
+        kvstore.insert_or_update(b"c", b"3").unwrap();
+        kvstore.insert_or_update(b"d", b"4").unwrap();
+        assert_eq!(kvstore.latest_sequence(), 5);
+        kvstore.poison("simulated crash".to_string());
+        drop(kvstore);
+        let mut kvstore = DB::new(db_path).expect("Recovery failed");
+        kvstore.checkpoint_interval = 2;
+        assert_eq!(kvstore.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kvstore.get(b"b").unwrap(), None);
+        assert_eq!(kvstore.latest_sequence(), 5);
+
+        // A flush makes them durable
+        kvstore.write_opt(&wb, &unlogged).unwrap();
+        kvstore.flush().unwrap();
+        kvstore.insert_or_update(b"e", b"5").unwrap();
+        kvstore.insert_or_update(b"f", b"6").unwrap();
+        let sequence = kvstore.latest_sequence();
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Recovery failed");
+        assert_eq!(kvstore.get(b"a").unwrap(), None);
+        assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(kvstore.get(b"f").unwrap(), Some(b"6".to_vec()));
+        assert_eq!(kvstore.latest_sequence(), sequence);
+    }
+
+    #[test]
+    fn recovery_of_range_deletion_bypassing_the_wal() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let unlogged = WriteOptions::default().disable_wal(true);
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        for key in [b"a", b"b", b"c"] {
+            kvstore.insert_or_update(key, b"1").unwrap();
+        }
+        let mut wb = write_batch::WriteBatch::new();
+        wb.delete_range(b"a", b"c");
+        kvstore.write_opt(&wb, &unlogged).unwrap();
+        kvstore.insert_or_update(b"b", b"2").unwrap();
+
+        // The flush deletes the range again and keeps the keys written since
+        kvstore.flush().unwrap();
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Recovery failed");
+        assert_eq!(kvstore.get(b"a").unwrap(), None);
+        assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(kvstore.get(b"c").unwrap(), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn recovery_of_expiring_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let hour = Duration::from_secs(3600);
+
+        let kvstore = DB::open_with_ttl(db_path, hour).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        kvstore
+            .insert_or_update_with_ttl(b"b", b"2", Duration::ZERO)
+            .unwrap();
+        // A batch written an hour ago, whose TTL elapsed
+        let mut wb = write_batch::WriteBatch::new();
+        wb.set_metadata(write_batch::BatchMetadata {
+            write_time_micros: write_batch::now_micros() - 2 * hour.as_micros() as u64,
+            ..Default::default()
+        });
+        wb.insert_or_update(b"c", b"3");
+        kvstore.write(&wb).unwrap();
+        // Unlogged values keep their expiry when they are flushed
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update_with_expiry(b"d", b"4", 1);
+        wb.insert_or_update(b"e", b"5");
+        kvstore
+            .write_opt(&wb, &WriteOptions::default().disable_wal(true))
+            .unwrap();
+        kvstore.flush().unwrap();
+
+        let check = |kvstore: &DB| {
+            assert_eq!(kvstore.get(b"a").unwrap(), Some(b"1".to_vec()));
+            assert_eq!(kvstore.get(b"b").unwrap(), None);
+            assert_eq!(kvstore.get(b"c").unwrap(), None);
+            assert_eq!(kvstore.get(b"d").unwrap(), None);
+            let keys: Vec<Vec<u8>> = kvstore.scan(..).unwrap().map(|(key, _)| key).collect();
+            assert_eq!(keys, vec![b"a".to_vec(), b"e".to_vec()]);
+        };
+        check(&kvstore);
+        drop(kvstore);
+        let kvstore = DB::open_with_ttl(db_path, hour).expect("Recovery failed");
+        check(&kvstore);
+
+        // The TTL of the DB only applies while it is open with it
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Recovery failed");
+        assert_eq!(kvstore.get(b"c").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(kvstore.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn recovery_after_close() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let unlogged = WriteOptions::default().disable_wal(true);
+
+        // Closing makes unlogged writes durable
+        let mut kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"b", b"2");
+        kvstore.write_opt(&wb, &unlogged).unwrap();
+        kvstore.close().unwrap();
+        assert_eq!(kvstore.wal_stats().fsync_count, 1);
+        drop(kvstore);
+
+        // So does dropping the DB
+        let kvstore = DB::new(db_path).expect("Recovery failed");
+        assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
+        let mut wb = write_batch::WriteBatch::new();
+        wb.delete(b"a");
+        wb.insert_or_update(b"c", b"3");
+        kvstore.write_opt(&wb, &unlogged).unwrap();
+        drop(kvstore);
+
+        let kvstore = DB::new(db_path).expect("Recovery failed");
+        assert_eq!(kvstore.get(b"a").unwrap(), None);
+        assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(kvstore.get(b"c").unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn recovery_verifies_checkpoints() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let mut kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        kvstore.checkpoint_interval = 10;
+        let data = test_utils::populate(100, &kvstore);
+        let mut keys_to_delete = std::collections::HashSet::new();
+        keys_to_delete.insert(5);
+        delete_keys(&keys_to_delete, &kvstore);
+
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Recovery failed");
+        validate_key_values(&data, Some(&keys_to_delete), &kvstore);
+        assert_eq!(kvstore.latest_sequence(), 101);
+
+        // A checkpoint that disagrees with the replayed state fails recovery
+        let wal_path = kvstore.wal_path();
+        drop(kvstore);
+        let mut log_writer = LogWriter::new(wal_path.to_str().unwrap(), false).unwrap();
+        let checkpoint = write_batch::Checkpoint {
+            sequence: 101,
+            rolling_crc: 0,
+            key_count: 99,
+        };
+        let checkpoint_wb = write_batch::WriteBatch::new_checkpoint(checkpoint);
+        log_writer
+            .append(
+                &checkpoint_wb
+                    .to_wal_payload(CompressionType::None, 0)
+                    .unwrap(),
+            )
+            .unwrap();
+        match DB::new(db_path) {
+            Err(error::Error::Corruption(_)) => {}
+            _ => panic!("Expected a Corruption error"),
+        }
+    }
+
+    #[test]
+    fn recovery_with_wal_checksums() {
+        for checksum in [
+            WalChecksum::Crc32c,
+            WalChecksum::XxHash64,
+            WalChecksum::Crc64,
+        ] {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path();
+            let options = Options::default().wal_checksum(checksum);
+            let kvstore = DB::open(db_path, options.clone()).unwrap();
+            kvstore.insert_or_update(b"a", b"1").unwrap();
+            let wal_path = kvstore.wal_path();
+            drop(kvstore);
+            let header = log_header::LogHeader::read(File::open(&wal_path).unwrap()).unwrap();
+            assert_eq!(header.checksum, checksum.into());
+
+            // The WAL keeps its checksum when reopened with other options
+            let kvstore = DB::new(db_path).unwrap();
+            kvstore.insert_or_update(b"b", b"2").unwrap();
+            drop(kvstore);
+            let kvstore = DB::new(db_path).unwrap();
+            assert_eq!(kvstore.get(b"a").unwrap(), Some(b"1".to_vec()));
+            assert_eq!(kvstore.get(b"b").unwrap(), Some(b"2".to_vec()));
+            drop(kvstore);
+
+            // Corruption is still detected
+            let mut wal = fs::read(&wal_path).unwrap();
+            let last = wal.len() - 1;
+            wal[last] ^= 0xff;
+            fs::write(&wal_path, &wal).unwrap();
+            let options = options.wal_recovery_mode(WalRecoveryMode::AbsoluteConsistency);
+            assert!(matches!(
+                DB::open(db_path, options),
+                Err(Error::Corruption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn recovery_restores_sequences_from_the_wal() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"a", b"1");
+        wb.insert_or_update(b"b", b"2");
+        kvstore.write(&wb).expect("Write failed");
+        kvstore.delete(b"a").expect("Delete failed");
+
+        // Every batch in the WAL carries the sequence number of its first entry
+        let mut tail = kvstore.tail_wal(Duration::from_millis(1)).unwrap();
+        tail.set_timeout(Some(Duration::ZERO));
+        let mut sequences = Vec::new();
+        while let Some(wb) = tail.next_batch() {
+            sequences.push(wb.unwrap().sequence());
+        }
+        assert_eq!(sequences, vec![1, 3]);
+
+        // A batch whose sequence number goes backwards is corrupt
+        let wal_path = kvstore.wal_path();
+        drop(kvstore);
+        let mut log_writer = LogWriter::new(wal_path.to_str().unwrap(), false).unwrap();
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"c", b"3");
+        wb.set_sequence(2);
+        log_writer
+            .append(&wb.to_wal_payload(CompressionType::None, 0).unwrap())
+            .unwrap();
+        drop(log_writer);
+        match DB::new_with_recovery_mode(db_path, WalRecoveryMode::AbsoluteConsistency) {
+            Err(error::Error::Corruption(_)) => {}
+            _ => panic!("Expected a Corruption error"),
+        }
+    }
+
+    #[test]
+    fn recovery_rejects_newer_batch_versions() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        kvstore.insert_or_update(b"a", b"1").unwrap();
+        let wal_path = kvstore.wal_path();
+        drop(kvstore);
+
+        // A batch at the end of the WAL, written in a newer format
+        let mut log_writer = LogWriter::new(wal_path.to_str().unwrap(), false).unwrap();
+        let mut wb = write_batch::WriteBatch::new();
+        wb.insert_or_update(b"b", b"2");
+        wb.set_sequence(2);
+        let mut payload = wb.to_wal_payload(CompressionType::None, 0).unwrap();
+        // The format version is the 6th byte of the header
+        payload[5] = write_batch::FORMAT_VERSION + 1;
+        log_writer.append(&payload).unwrap();
+        drop(log_writer);
+        let wal_len = fs::metadata(&wal_path).unwrap().len();
+
+        // No recovery mode takes it for a torn tail and truncates it away
+        for mode in [
+            WalRecoveryMode::AbsoluteConsistency,
+            WalRecoveryMode::TolerateCorruptedTail,
+            WalRecoveryMode::PointInTime,
+            WalRecoveryMode::SkipAnyCorruption,
+        ] {
+            match DB::new_with_recovery_mode(db_path, mode) {
+                Err(Error::IncompatibleVersion { found, supported }) => {
+                    assert_eq!(found, write_batch::FORMAT_VERSION + 1);
+                    assert_eq!(supported, write_batch::FORMAT_VERSION);
+                }
+                _ => panic!("Expected an IncompatibleVersion error in {:?} mode", mode),
+            }
+            assert_eq!(fs::metadata(&wal_path).unwrap().len(), wal_len);
+        }
+    }
+
+    /// Creates a DB holding the keys `0..count` with 1KB values, spanning
+    /// several WAL blocks, then lets `damage` modify its WAL.
+    fn damaged_db(count: i32, damage: impl FnOnce(&mut Vec<u8>)) -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        let kvstore = DB::new(temp_dir.path()).expect("Failed to create a new DB");
+        for i in 0..count {
+            kvstore
+                .insert_or_update(&i.to_be_bytes(), &[1; 1000])
+                .unwrap();
+        }
+        let wal_path = kvstore.wal_path();
+        drop(kvstore);
+        let mut wal = fs::read(&wal_path).unwrap();
+        damage(&mut wal);
+        fs::write(&wal_path, &wal).unwrap();
+        temp_dir
+    }
+
+    fn recovered_keys(kvstore: &DB, count: i32) -> Vec<i32> {
+        (0..count)
+            .filter(|i| kvstore.get(&i.to_be_bytes()).unwrap().is_some())
+            .collect()
+    }
+
+    #[test]
+    fn recovery_modes_with_corruption() {
+        let count = 100;
+        let block_size = log_record::DEFAULT_BLOCK_SIZE;
+        let corrupt = |wal: &mut Vec<u8>| wal[block_size + block_size / 2] ^= 0xff;
+
+        for mode in [
+            WalRecoveryMode::AbsoluteConsistency,
+            WalRecoveryMode::TolerateCorruptedTail,
+        ] {
+            let temp_dir = damaged_db(count, corrupt);
+            match DB::new_with_recovery_mode(temp_dir.path(), mode) {
+                Err(Error::Corruption(_)) => {}
+                _ => panic!("Expected a Corruption error in {:?} mode", mode),
+            }
+        }
+
+        // Skipping loses the rest of the corrupt block, but nothing after it
+        let temp_dir = damaged_db(count, corrupt);
+        let kvstore =
+            DB::new_with_recovery_mode(temp_dir.path(), WalRecoveryMode::SkipAnyCorruption)
+                .unwrap();
+        let skipped = recovered_keys(&kvstore, count);
+        assert!(skipped.len() > count as usize / 2 && skipped.len() < count as usize);
+        assert!(skipped.contains(&(count - 1)));
+        let corrupted = kvstore.wal_corrupted_ranges();
+        assert_eq!(corrupted.len(), 1);
+        assert!(corrupted[0].contains(&((block_size + block_size / 2) as u64)));
+        assert!(corrupted[0].end > 2 * block_size as u64);
+        // Sequence numbers are recorded in the batches, so none are reused
+        assert_eq!(kvstore.latest_sequence(), count as u64);
+
+        // Point-in-time recovery stops at the corruption and truncates the WAL there
+        let temp_dir = damaged_db(count, corrupt);
+        let kvstore =
+            DB::new_with_recovery_mode(temp_dir.path(), WalRecoveryMode::PointInTime).unwrap();
+        let recovered = recovered_keys(&kvstore, count);
+        assert_eq!(recovered, (0..recovered.len() as i32).collect::<Vec<_>>());
+        assert!(!recovered.is_empty() && recovered.len() < skipped.len());
+        assert_eq!(kvstore.latest_sequence(), recovered.len() as u64);
+        assert!(fs::metadata(kvstore.wal_path()).unwrap().size() < (2 * block_size) as u64);
+
+        // Writes after the recovery survive the next one
+        kvstore.insert_or_update(b"new", b"value").unwrap();
+        drop(kvstore);
+        let kvstore = DB::new(temp_dir.path()).unwrap();
+        assert_eq!(recovered_keys(&kvstore, count), recovered);
+        assert_eq!(kvstore.get(b"new").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn recovery_modes_with_torn_tail() {
+        let count = 100;
+        // A crash can tear the payload or the header of the last record
+        let tear_payload: fn(&mut Vec<u8>) = |wal| wal.truncate(wal.len() - 10);
+        let tear_header: fn(&mut Vec<u8>) = |wal| wal.extend_from_slice(&[1, 2, 3]);
+
+        let temp_dir = damaged_db(count, tear_payload);
+        match DB::new_with_recovery_mode(temp_dir.path(), WalRecoveryMode::AbsoluteConsistency) {
+            Err(Error::Corruption(_)) => {}
+            _ => panic!("Expected a Corruption error"),
+        }
+
+        for mode in [
+            WalRecoveryMode::TolerateCorruptedTail,
+            WalRecoveryMode::PointInTime,
+            WalRecoveryMode::SkipAnyCorruption,
+        ] {
+            let temp_dir = damaged_db(count, tear_payload);
+            let kvstore = DB::new_with_recovery_mode(temp_dir.path(), mode).unwrap();
+            assert_eq!(
+                recovered_keys(&kvstore, count),
+                (0..count - 1).collect::<Vec<_>>()
+            );
+        }
+
+        // By default, the torn tail is truncated and the DB opens
+        for (tear, recovered) in [(tear_payload, count - 1), (tear_header, count)] {
+            let temp_dir = damaged_db(count, tear);
+            let kvstore = DB::new(temp_dir.path()).unwrap();
+            assert_eq!(
+                recovered_keys(&kvstore, count),
+                (0..recovered).collect::<Vec<_>>()
+            );
+            kvstore.insert_or_update(b"new", b"value").unwrap();
+
+            drop(kvstore);
+            let kvstore =
+                DB::new_with_recovery_mode(temp_dir.path(), WalRecoveryMode::AbsoluteConsistency)
+                    .unwrap();
+            assert_eq!(recovered_keys(&kvstore, count).len(), recovered as usize);
+            assert_eq!(kvstore.get(b"new").unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn recovery_with_wal_compression() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let options = Options::default().wal_compression(CompressionType::Snappy);
+        let kvstore = DB::open(db_path, options).expect("Failed to create a new DB");
+
+        // Bulk-load a single large batch of highly compressible values
+        let count: i32 = 1000;
+        let value = [7u8; 256];
+        let mut wb = write_batch::WriteBatch::new();
+        for i in 0..count {
+            wb.insert_or_update(&i.to_be_bytes(), &value);
+        }
+        kvstore.write(&wb).expect("Write failed");
+        assert!((fs::metadata(kvstore.wal_path()).unwrap().size() as usize) < wb.len() / 10);
+
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        for i in 0..count {
+            assert_eq!(
+                kvstore.get(&i.to_be_bytes()).expect("Get failed"),
+                Some(value.to_vec())
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+/// Module for testing the visibility rules of deletes documented on `DB::delete`.
+mod test_semantics {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn collect_scan(kvstore: &DB, start: &[u8], end: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        kvstore.scan(start..end).expect("Scan failed").collect()
+    }
+
+    /// Runs `check` against a freshly written DB and again after recovery.
+    fn check_with_recovery(write: impl FnOnce(&DB), check: impl Fn(&DB)) {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        write(&kvstore);
+        check(&kvstore);
+
+        drop(kvstore);
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+        check(&kvstore);
+    }
+
+    #[test]
+    fn deleted_key_is_hidden_from_get_and_scan() {
+        check_with_recovery(
+            |db| {
+                db.insert_or_update(b"a", b"1").unwrap();
+                db.insert_or_update(b"b", b"2").unwrap();
+                db.insert_or_update(b"c", b"3").unwrap();
+                db.delete(b"b").unwrap();
+            },
+            |db| {
+                assert_eq!(db.get(b"b").unwrap(), None);
+                assert_eq!(
+                    collect_scan(db, b"a", b"z"),
+                    vec![
+                        (b"a".to_vec(), b"1".to_vec()),
+                        (b"c".to_vec(), b"3".to_vec())
+                    ]
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn delete_of_absent_key_is_a_noop() {
+        check_with_recovery(
+            |db| {
+                db.insert_or_update(b"a", b"1").unwrap();
+                db.delete(b"missing").unwrap();
+            },
+            |db| {
+                assert_eq!(db.get(b"missing").unwrap(), None);
+                assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+                assert_eq!(collect_scan(db, b"a", b"z").len(), 1);
+            },
+        );
+    }
+
+    #[test]
+    fn write_after_delete_is_visible() {
+        check_with_recovery(
+            |db| {
+                db.insert_or_update(b"a", b"1").unwrap();
+                db.delete(b"a").unwrap();
+                db.insert_or_update(b"a", b"2").unwrap();
+            },
+            |db| {
+                assert_eq!(db.get(b"a").unwrap(), Some(b"2".to_vec()));
+                assert_eq!(
+                    collect_scan(db, b"a", b"z"),
+                    vec![(b"a".to_vec(), b"2".to_vec())]
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn last_write_wins_within_a_batch() {
+        check_with_recovery(
+            |db| {
+                let mut wb = write_batch::WriteBatch::new();
+                wb.insert_or_update(b"a", b"1");
+                wb.delete(b"a");
+                wb.delete(b"b");
+                wb.insert_or_update(b"b", b"2");
+                db.write(&wb).unwrap();
+            },
+            |db| {
+                assert_eq!(db.get(b"a").unwrap(), None);
+                assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+            },
+        );
+    }
+
+    #[test]
+    fn empty_value_is_a_delete() {
+        check_with_recovery(
+            |db| {
+                db.insert_or_update(b"a", b"1").unwrap();
+                db.insert_or_update(b"a", b"").unwrap();
+            },
+            |db| {
+                assert_eq!(db.get(b"a").unwrap(), None);
+                assert!(collect_scan(db, b"a", b"z").is_empty());
+            },
+        );
+    }
+
+    #[test]
+    fn snapshot_does_not_observe_later_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = DB::new(temp_dir.path()).unwrap();
+        db.insert_or_update(b"a", b"1").unwrap();
+        db.insert_or_update(b"b", b"2").unwrap();
+        let size = |db: &DB| -> u64 {
+            db.get_property(properties::CUR_SIZE_ALL_MEM_TABLES)
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        let initial_size = size(&db);
+
+        let snapshot = db.snapshot().unwrap();
+        db.insert_or_update(b"a", b"3").unwrap();
+        db.delete(b"b").unwrap();
+        db.insert_or_update(b"c", b"4").unwrap();
+
+        assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(snapshot.get(b"c").unwrap(), None);
+        let kv = |k: &[u8], v: &[u8]| (k.to_vec(), v.to_vec());
+        assert_eq!(
+            snapshot.scan(..).unwrap().collect::<Vec<_>>(),
+            vec![kv(b"a", b"1"), kv(b"b", b"2")]
+        );
+        assert_eq!(
+            snapshot.scan_prefix(b"b").unwrap().collect::<Vec<_>>(),
+            vec![kv(b"b", b"2")]
+        );
+        assert_eq!(
+            db.scan(..).unwrap().collect::<Vec<_>>(),
+            vec![kv(b"a", b"3"), kv(b"c", b"4")]
+        );
+
+        // Releasing the snapshot drops the versions only it could see, and
+        // leaves the tombstone of `c`
+        db.insert_or_update(b"a", b"1").unwrap();
+        db.insert_or_update(b"b", b"2").unwrap();
+        db.delete(b"c").unwrap();
+        drop(snapshot);
+        assert_eq!(size(&db), initial_size + 1);
+    }
+
+    #[test]
+    fn delete_range_hides_the_keys_in_the_range() {
+        check_with_recovery(
+            |db| {
+                for key in [b"a", b"b", b"c", b"d"] {
+                    db.insert_or_update(key, b"1").unwrap();
+                }
+                db.delete_range(b"b", b"d").unwrap();
+                db.insert_or_update(b"c", b"2").unwrap();
+                db.delete_range(b"z", b"a").unwrap();
+            },
+            |db| {
+                assert_eq!(db.get(b"b").unwrap(), None);
+                assert_eq!(db.get(b"c").unwrap(), Some(b"2".to_vec()));
+                assert_eq!(
+                    collect_scan(db, b"a", b"z"),
+                    vec![
+                        (b"a".to_vec(), b"1".to_vec()),
+                        (b"c".to_vec(), b"2".to_vec()),
+                        (b"d".to_vec(), b"1".to_vec())
+                    ]
+                );
+                assert_eq!(
+                    db.get_property(properties::ESTIMATE_NUM_KEYS),
+                    Some("3".to_string())
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn single_delete_hides_the_key() {
+        check_with_recovery(
+            |db| {
+                db.insert_or_update(b"a", b"1").unwrap();
+                db.insert_or_update(b"b", b"2").unwrap();
+                db.single_delete(b"a").unwrap();
+                db.single_delete(b"c").unwrap();
+            },
+            |db| {
+                assert_eq!(db.get(b"a").unwrap(), None);
+                assert_eq!(
+                    collect_scan(db, b"a", b"z"),
+                    vec![(b"b".to_vec(), b"2".to_vec())]
+                );
+                assert_eq!(
+                    db.get_property(properties::ESTIMATE_NUM_KEYS),
+                    Some("1".to_string())
+                );
+            },
+        );
+    }
 }
@@ -0,0 +1,216 @@
+//! This module defines the header written at the start of every log file.
+//!
+//! The header identifies the file as a mini-lsm log and records the format
+//! version, the block size and the checksum the log was written with, so
+//! readers don't have to assume them. It takes the first bytes of the first block:
+//!
+//! +-------------+---------------+------------------+----------------+
+//! | Magic (8B)  | Version (4B)  | Block size (4B)  | Checksum (4B)  |
+//! +-------------+---------------+------------------+----------------+
+//!
+//! Logs written before the header was introduced start directly with a record,
+//! and are read as version 0 with the default block size.
+//!
+//! Versions:
+//! - 1: Records store the CRC32C of their payload.
+//! - 2: Records store the masked CRC32C of their type and payload, as in RocksDB.
+//! - 3: The header records the checksum of the records.
+
+use std::io::Read;
+
+use num_traits::FromPrimitive;
+
+use crate::{
+    error::{Error, Result},
+    log_record::{Checksum, DEFAULT_BLOCK_SIZE, LOG_RECORD_HEADER_SIZE, MIN_RECORD_SIZE},
+};
+
+/// The bytes every log file with a header starts with.
+pub const LOG_MAGIC: [u8; 8] = *b"mini-lsm";
+
+/// The size of the header in bytes.
+pub const LOG_HEADER_SIZE: usize = 20;
+
+/// The size of the header of logs written in format versions 1 and 2, which
+/// don't record the checksum.
+const LOG_HEADER_SIZE_V1: usize = 16;
+
+/// The version of the log format written by this version of the library.
+pub const LOG_FORMAT_VERSION: u32 = 3;
+
+/// The largest block size that can be recorded: the payload of a record
+/// filling a block must fit in the 2-byte size field of its header.
+const MAX_BLOCK_SIZE: usize = u16::MAX as usize + LOG_RECORD_HEADER_SIZE;
+
+/// Returns whether logs can be divided into blocks of `block_size` bytes.
+pub fn is_valid_block_size(block_size: usize) -> bool {
+    (LOG_HEADER_SIZE + MIN_RECORD_SIZE..=MAX_BLOCK_SIZE).contains(&block_size)
+}
+
+/// The header of a log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogHeader {
+    /// The version of the format the log was written in.
+    pub version: u32,
+    /// The size of the blocks the log is divided into.
+    pub block_size: usize,
+    /// The checksum the records of the log are written with.
+    pub checksum: Checksum,
+}
+
+impl Default for LogHeader {
+    fn default() -> Self {
+        LogHeader {
+            version: LOG_FORMAT_VERSION,
+            block_size: DEFAULT_BLOCK_SIZE,
+            checksum: Checksum::default(),
+        }
+    }
+}
+
+impl LogHeader {
+    /// The header assumed for logs written without one.
+    pub const LEGACY: LogHeader = LogHeader {
+        version: 0,
+        block_size: DEFAULT_BLOCK_SIZE,
+        checksum: Checksum::Crc32c,
+    };
+
+    /// Returns the number of bytes the header takes at the start of the log.
+    pub fn size(&self) -> usize {
+        match self.version {
+            0 => 0,
+            1 | 2 => LOG_HEADER_SIZE_V1,
+            _ => LOG_HEADER_SIZE,
+        }
+    }
+
+    /// Serializes the header in the format of its version.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(LOG_HEADER_SIZE);
+        bytes.extend_from_slice(&LOG_MAGIC);
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&(self.block_size as u32).to_be_bytes());
+        if self.version > 2 {
+            bytes.extend_from_slice(&u32::from(self.checksum.value()).to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Parses the header at the start of a log.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The first bytes of the log, up to `LOG_HEADER_SIZE` of them.
+    ///
+    /// # Returns
+    ///
+    /// Returns `LogHeader::LEGACY` if the log does not start with a header, or
+    /// `Err(Error::IncompatibleVersion)` if it was written in a newer format.
+    pub fn decode(bytes: &[u8]) -> Result<LogHeader> {
+        if bytes.len() < LOG_HEADER_SIZE_V1 || bytes[0..8] != LOG_MAGIC {
+            return Ok(LogHeader::LEGACY);
+        }
+        let version = u32::from_be_bytes(bytes[8..12].try_into()?);
+        if version > LOG_FORMAT_VERSION {
+            return Err(Error::IncompatibleVersion {
+                found: version.try_into().unwrap_or(u8::MAX),
+                supported: LOG_FORMAT_VERSION as u8,
+            });
+        }
+        let block_size = u32::from_be_bytes(bytes[12..16].try_into()?) as usize;
+        if !is_valid_block_size(block_size) {
+            return Err(Error::Corruption(format!(
+                "invalid block size in the log header: {}",
+                block_size
+            )));
+        }
+        let checksum = match version {
+            1 => Checksum::Crc32c,
+            2 => Checksum::MaskedCrc32c,
+            _ => {
+                let value = bytes
+                    .get(LOG_HEADER_SIZE_V1..LOG_HEADER_SIZE)
+                    .ok_or_else(|| Error::Corruption("the log header is cut short".to_string()))?;
+                let value = u32::from_be_bytes(value.try_into()?);
+                u8::try_from(value)
+                    .ok()
+                    .and_then(Checksum::from_u8)
+                    .ok_or_else(|| {
+                        Error::Corruption(format!("invalid checksum in the log header: {}", value))
+                    })?
+            }
+        };
+        Ok(LogHeader {
+            version,
+            block_size,
+            checksum,
+        })
+    }
+
+    /// Reads and parses the header at the start of a log.
+    ///
+    /// # Arguments
+    ///
+    /// * `log` - The log, positioned at its start.
+    pub fn read(log: impl Read) -> Result<LogHeader> {
+        let mut bytes = Vec::with_capacity(LOG_HEADER_SIZE);
+        log.take(LOG_HEADER_SIZE as u64).read_to_end(&mut bytes)?;
+        LogHeader::decode(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let header = LogHeader {
+            version: LOG_FORMAT_VERSION,
+            block_size: 4096,
+            checksum: Checksum::XxHash64,
+        };
+        assert_eq!(LogHeader::decode(&header.encode()).unwrap(), header);
+
+        // Version 1 and 2 headers imply the checksum
+        for (version, checksum) in [(1, Checksum::Crc32c), (2, Checksum::MaskedCrc32c)] {
+            let header = LogHeader {
+                version,
+                block_size: 4096,
+                checksum,
+            };
+            let bytes = header.encode();
+            assert_eq!(bytes.len(), header.size());
+            assert_eq!(LogHeader::decode(&bytes).unwrap(), header);
+        }
+
+        // Logs without a header are read as legacy logs
+        assert_eq!(LogHeader::decode(&[]).unwrap(), LogHeader::LEGACY);
+        assert_eq!(
+            LogHeader::decode(&[7; LOG_HEADER_SIZE]).unwrap(),
+            LogHeader::LEGACY
+        );
+
+        let mut newer = header.encode();
+        newer[8..12].copy_from_slice(&(LOG_FORMAT_VERSION + 1).to_be_bytes());
+        assert!(matches!(
+            LogHeader::decode(&newer),
+            Err(Error::IncompatibleVersion { .. })
+        ));
+
+        let mut invalid = header.encode();
+        invalid[12..16].copy_from_slice(&1u32.to_be_bytes());
+        assert!(matches!(
+            LogHeader::decode(&invalid),
+            Err(Error::Corruption(_))
+        ));
+
+        let mut invalid = header.encode();
+        invalid[16..20].copy_from_slice(&0u32.to_be_bytes());
+        assert!(matches!(
+            LogHeader::decode(&invalid),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
@@ -1,9 +1,19 @@
-use std::io::{BufRead, BufReader};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    error::{self},
+    error::{self, Error},
     lending_iterator::LendingIterator,
-    log_record::{LogRecord, DEFAULT_BLOCK_SIZE, MIN_RECORD_SIZE},
+    log_header::LogHeader,
+    log_record::{
+        Checksum, LogRecord, RecordType, LOG_RECORD_HEADER_SIZE, MIN_RECORD_SIZE, SIZE_OFFSET,
+    },
 };
 
 pub struct LogReader {
@@ -33,59 +43,225 @@ impl LogReader {
     ///
     /// # Returns
     ///
-    /// Returns a Result containing the Iter instance if successful, or an error if the file cannot be opened or the buffer capacity is invalid.
+    /// Returns a Result containing the Iter instance if successful, or an error if the file cannot be opened.
     pub fn to_iter(&self) -> error::Result<Iter> {
-        // TODO: store and read the block size from the header of the WAL file
-        let buffer_capacity = DEFAULT_BLOCK_SIZE * 4;
-        let f = std::fs::File::open(&self.file_path)?;
-        Ok(Iter {
-            reader: BufReader::with_capacity(buffer_capacity, f),
-            curr_idx: 0,
-            bytes_remaining: 0,
-            bytes_read: 0,
+        self.to_iter_at(0)
+    }
+
+    /// Returns an iterator over the log entries starting at the block containing `offset`.
+    ///
+    /// Records never straddle a block boundary, so reading can start at any block.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The file offset to start reading at, rounded down to a block boundary.
+    ///
+    /// # Returns
+    ///
+    /// Returns a Result containing the Iter instance if successful, or an error if the file cannot be opened
+    /// or its header is invalid.
+    pub fn to_iter_at(&self, offset: u64) -> error::Result<Iter> {
+        let mut file = File::open(&self.file_path)?;
+        let header = LogHeader::read(&mut file)?;
+
+        let mut iter = Iter {
+            file,
+            block_size: header.block_size,
+            header_size: header.size(),
+            checksum: header.checksum,
+            block: Vec::with_capacity(header.block_size),
+            block_offset: 0,
+            pos: 0,
+            skipped: Vec::new(),
+            follow: false,
+        };
+        iter.seek(offset - offset % header.block_size as u64)?;
+        Ok(iter)
+    }
+
+    /// Returns an iterator that follows the log as it is written, starting at
+    /// the block containing `offset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The file offset to start reading at, rounded down to a block boundary.
+    /// * `poll_interval` - How long to wait before checking the file for new records again.
+    ///
+    /// # Returns
+    ///
+    /// Returns a Result containing the TailingIter instance if successful, or an error if the file cannot be opened.
+    pub fn to_tailing_iter(
+        &self,
+        offset: u64,
+        poll_interval: Duration,
+    ) -> error::Result<TailingIter> {
+        let mut iter = self.to_iter_at(offset)?;
+        iter.follow = true;
+        Ok(TailingIter {
+            iter,
+            poll_interval,
+            timeout: None,
         })
     }
 }
 
+/// An iterator over the records of a log file, reading it one block at a time.
+///
+/// When a record is corrupt, the iterator yields an error and resynchronizes at
+/// the next block boundary, so callers that tolerate corruption can keep
+/// iterating. The byte ranges skipped this way are reported by `skipped`.
+/// Records of a batch that was cut short by a skip are still returned, and it is
+/// up to the caller to discard them.
 pub struct Iter {
-    reader: std::io::BufReader<std::fs::File>,
-    bytes_remaining: usize,
-    bytes_read: usize,
-    curr_idx: usize,
+    file: File,
+    /// The size of the blocks of the log, as recorded in its header.
+    block_size: usize,
+    /// The number of bytes the header takes at the start of the first block.
+    header_size: usize,
+    /// The checksum the records are written with, as implied by the header.
+    checksum: Checksum,
+    /// The bytes of the current block. Only the last block of the file can be short.
+    block: Vec<u8>,
+    /// The file offset of the current block.
+    block_offset: u64,
+    /// The position of the next record in the current block.
+    pos: usize,
+    skipped: Vec<Range<u64>>,
+    /// Whether the log is still being written, in which case a record cut
+    /// short by the end of the file is still being appended and not corrupt.
+    follow: bool,
 }
 
 impl Iter {
-    fn min_record_size_bytes_remaining(&self) -> bool {
-        self.bytes_remaining >= MIN_RECORD_SIZE
+    /// Returns the byte ranges of the file skipped because they were corrupt.
+    pub fn skipped(&self) -> &[Range<u64>] {
+        &self.skipped
     }
 
-    fn consume_remaining_bytes(&mut self) {
-        assert_eq!(self.bytes_read, self.bytes_remaining + self.curr_idx);
-        self.reader.consume(self.bytes_remaining + self.curr_idx);
-        self.curr_idx += self.bytes_remaining;
-        self.bytes_remaining = 0;
+    /// Returns the file offset of the next record.
+    pub fn offset(&self) -> u64 {
+        self.block_offset + self.pos as u64
     }
 
-    fn fill_buffer(&mut self) -> error::Result<()> {
-        assert_eq!(self.bytes_read, self.bytes_remaining + self.curr_idx);
-        self.curr_idx = 0;
-        match self.reader.fill_buf() {
-            Result::Err(err) => return Err(error::Error::Io(err)),
-            Result::Ok(bytes_read) => {
-                self.bytes_read = bytes_read.len();
-                self.bytes_remaining = bytes_read.len();
+    /// Moves the iterator to `offset`, so that reading resumes from there.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The file offset of a record, as reported by `offset`, or a
+    ///   block boundary. Offsets in the header are moved to the first record.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the file cannot be seeked.
+    pub fn seek(&mut self, offset: u64) -> error::Result<()> {
+        let block_offset = offset - offset % self.block_size as u64;
+        self.file.seek(SeekFrom::Start(block_offset))?;
+        self.block.clear();
+        self.block_offset = block_offset;
+        self.pos = (offset - block_offset) as usize;
+        // The header takes the first bytes of the first block
+        if block_offset == 0 {
+            self.pos = self.pos.max(self.header_size);
+        }
+        Ok(())
+    }
+
+    /// Makes sure the current block holds another record, reading the next
+    /// block of the file once the current one has been consumed.
+    ///
+    /// A short block is the last one of the file, so it is topped up with
+    /// whatever has been appended to the file since it was read.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(false)` if the end of the file has been reached.
+    fn fill_block(&mut self) -> error::Result<bool> {
+        loop {
+            if self.pos + MIN_RECORD_SIZE <= self.block.len() {
+                return Ok(true);
+            }
+            // The rest of a full block is padding
+            if self.block.len() == self.block_size {
+                self.block_offset += self.block_size as u64;
+                self.block.clear();
+                self.pos = 0;
+            }
+            if self.top_up()? == 0 {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Reads the part of the current block that has not been read yet.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of bytes read.
+    fn top_up(&mut self) -> error::Result<usize> {
+        let missing = self.block_size - self.block.len();
+        let read = (&mut self.file)
+            .take(missing as u64)
+            .read_to_end(&mut self.block)?;
+        Ok(read)
+    }
+
+    /// Returns whether the record at the current position extends past the
+    /// end of the file.
+    fn record_cut_short(&self) -> bool {
+        let size = &self.block[self.pos + SIZE_OFFSET..self.pos + SIZE_OFFSET + 2];
+        let size = usize::from(u16::from_be_bytes([size[0], size[1]]));
+        self.block.len() < self.block_size
+            && self.pos + LOG_RECORD_HEADER_SIZE + size > self.block.len()
+    }
+
+    /// Returns whether a complete record can be read.
+    ///
+    /// A record cut short by the end of the file is either still being
+    /// appended, when following the log, or was torn by a crash.
+    fn ready(&mut self) -> error::Result<bool> {
+        if !self.fill_block()? {
+            return Ok(false);
+        }
+        if self.record_cut_short() {
+            if !self.follow {
+                return Ok(false);
             }
+            self.top_up()?;
+            return Ok(!self.record_cut_short());
+        }
+        Ok(true)
+    }
+
+    /// Returns the offset of the incomplete record the file ends with, if any,
+    /// e.g. because of a crash in the middle of an append. Only meaningful
+    /// once the iterator has returned `None`.
+    pub fn torn_tail(&self) -> Option<u64> {
+        if self.block.len() < self.block_size && self.pos < self.block.len() {
+            return Some(self.offset());
         }
+        None
+    }
+
+    /// Skips the rest of the current block, recording the skipped range.
+    fn skip_block(&mut self) -> error::Result<()> {
+        let start = self.offset();
+        let end = self.block_offset + self.block.len() as u64;
+        self.skipped.push(start..end);
+        self.file
+            .seek(SeekFrom::Start(self.block_offset + self.block_size as u64))?;
+        self.block.resize(self.block_size, 0);
+        self.pos = self.block_size;
         Ok(())
     }
 
-    fn read_record(&mut self) -> error::Result<LogRecord> {
-        assert_eq!(self.bytes_read, self.bytes_remaining + self.curr_idx);
-        let buffer = &self.reader.buffer()[self.curr_idx..];
-        let record = LogRecord::from_serialized_bytes(buffer)?;
-        self.curr_idx += record.len();
-        self.bytes_remaining -= record.len();
-        Ok(record)
+    /// Validates the record at the current position and returns its length.
+    fn check_record(&self) -> error::Result<usize> {
+        let record = LogRecord::from_serialized_bytes(&self.block[self.pos..])?;
+        if record.rtype == RecordType::None {
+            return Err(Error::InvalidRecordType(record.rtype.value()));
+        }
+        record.validate_crc(self.checksum)?;
+        Ok(record.len())
     }
 }
 
@@ -98,24 +274,189 @@ impl LendingIterator for Iter {
     /// # Returns
     ///
     /// - `Some(result)`: If there is a next item, returns `Some` with the result.
-    /// - `None`: If there are no more items, returns `None`.
+    ///   A corrupt record is returned as `Err(Error::Corruption)`, after which the
+    ///   iterator continues at the next block.
+    /// - `None`: If there are no more items, returns `None`. An incomplete
+    ///   record at the end of the file is reported by `torn_tail`.
     fn next<'b>(&'b mut self) -> Option<Self::Item<'b>> {
-        // Check if the remaining bytes in the buffer are less than the minimum record size
-        // If so, read more data from the file.
-        if !self.min_record_size_bytes_remaining() {
-            self.consume_remaining_bytes();
-            match self.fill_buffer() {
+        match self.ready() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(err) => return Some(Err(err)),
+        }
+
+        match self.check_record() {
+            Ok(len) => {
+                let start = self.pos;
+                self.pos += len;
+                Some(LogRecord::from_serialized_bytes(
+                    &self.block[start..start + len],
+                ))
+            }
+            Err(err) => {
+                let offset = self.offset();
+                if let Err(err) = self.skip_block() {
+                    return Some(Err(err));
+                }
+                Some(Err(Error::Corruption(format!(
+                    "invalid WAL record at offset {}: {}",
+                    offset, err
+                ))))
+            }
+        }
+    }
+}
+
+/// An iterator that follows a log as it is written.
+///
+/// Instead of ending at the end of the file, it waits for new records to be
+/// appended, polling the file every `poll_interval`. It only ends when no
+/// record shows up within the timeout, if one is set.
+pub struct TailingIter {
+    iter: Iter,
+    poll_interval: Duration,
+    timeout: Option<Duration>,
+}
+
+impl TailingIter {
+    /// Sets how long `next` waits for a new record before returning `None`.
+    /// Without a timeout, `next` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the file offset of the next record.
+    pub fn offset(&self) -> u64 {
+        self.iter.offset()
+    }
+
+    /// Moves the iterator to `offset`, as reported by `offset`, so that
+    /// following resumes from there.
+    pub fn seek(&mut self, offset: u64) -> error::Result<()> {
+        self.iter.seek(offset)
+    }
+}
+
+impl LendingIterator for TailingIter {
+    type Item<'b> = error::Result<LogRecord<'b>>;
+
+    /// Waits for the next record and returns it.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(result)`: If a record was appended before the timeout, returns `Some` with the result.
+    /// - `None`: If the timeout expired first, returns `None`.
+    fn next<'b>(&'b mut self) -> Option<Self::Item<'b>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            match self.iter.ready() {
+                Ok(true) => break,
+                Ok(false) => {}
                 Err(err) => return Some(Err(err)),
-                _ => (),
             }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+            thread::sleep(self.poll_interval);
         }
+        self.iter.next()
+    }
+}
 
-        // If we still don't have enough bytes to read a record,
-        // that means we have reached the end of the file.
-        if !self.min_record_size_bytes_remaining() {
-            return None;
+/// An iterator over the records of a sequence of log files, e.g. rotated WAL
+/// segments in log-number order, read as one logical stream.
+///
+/// Only the last file may end with a torn record: earlier files were complete
+/// when the next one was started, so a torn record in them is corruption.
+// WAL segments are not rotated yet, so only tests chain them for now.
+#[allow(dead_code)]
+pub struct ChainIter {
+    /// The files that have not been opened yet.
+    files: VecDeque<String>,
+    /// The file being read, and the iterator over its records.
+    current: Option<(String, Iter)>,
+}
+
+#[allow(dead_code)]
+impl ChainIter {
+    /// Creates an iterator over the records of `files`, in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The paths of the log files. They are opened as they are reached.
+    pub fn new(files: Vec<String>) -> ChainIter {
+        ChainIter {
+            files: files.into(),
+            current: None,
         }
-        Some(self.read_record())
+    }
+
+    /// Returns the path of the file being read, and the file offset of its next record.
+    pub fn position(&self) -> Option<(&str, u64)> {
+        self.current
+            .as_ref()
+            .map(|(path, iter)| (path.as_str(), iter.offset()))
+    }
+
+    /// Returns the offset of the incomplete record the last file ends with, if
+    /// any. Only meaningful once the iterator has returned `None`.
+    pub fn torn_tail(&self) -> Option<u64> {
+        self.current.as_ref().and_then(|(_, iter)| iter.torn_tail())
+    }
+
+    /// Makes sure the current file holds another record, moving on to the
+    /// next file once the current one has been read to its end.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(false)` if the end of the last file has been reached.
+    fn ready(&mut self) -> error::Result<bool> {
+        loop {
+            if let Some((path, iter)) = &mut self.current {
+                if iter.ready()? {
+                    return Ok(true);
+                }
+                if self.files.is_empty() {
+                    return Ok(false);
+                }
+                if let Some(offset) = iter.torn_tail() {
+                    return Err(Error::Corruption(format!(
+                        "{} ends with a torn record at offset {}, but is followed by {}",
+                        path, offset, self.files[0]
+                    )));
+                }
+            }
+            let Some(path) = self.files.pop_front() else {
+                return Ok(false);
+            };
+            let iter = LogReader::new(&path)?.to_iter()?;
+            self.current = Some((path, iter));
+        }
+    }
+}
+
+impl LendingIterator for ChainIter {
+    type Item<'b> = error::Result<LogRecord<'b>>;
+
+    /// Advances the iterator and returns the next record, from the next file
+    /// once the current one has been read.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(result)`: If there is a next record, returns `Some` with the result.
+    ///   Corrupt records are returned as errors, as by `Iter`.
+    /// - `None`: If the end of the last file has been reached.
+    fn next<'b>(&'b mut self) -> Option<Self::Item<'b>> {
+        match self.ready() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(err) => {
+                // Skip the rest of the file the error was found in
+                self.current = None;
+                return Some(Err(err));
+            }
+        }
+        self.current.as_mut()?.1.next()
     }
 }
 
@@ -123,9 +464,14 @@ impl LendingIterator for Iter {
 mod tests {
     use std::fs::File;
 
+    use rand::RngCore;
+    use tempfile::{NamedTempFile, TempDir};
+
     use crate::{
+        log_header::LOG_HEADER_SIZE,
+        log_record::DEFAULT_BLOCK_SIZE,
         log_writer::LogWriter,
-        write_batch::{WriteBatch, WriteBatchBuilder},
+        write_batch::{CompressionType, WriteBatch, WriteBatchBuilder},
     };
 
     use super::*;
@@ -143,9 +489,8 @@ mod tests {
         File::create(file_path).unwrap();
         let log_reader = LogReader::new(file_path).unwrap();
         let mut iter = log_reader.to_iter().unwrap();
-        assert_eq!(iter.curr_idx, 0);
-        assert_eq!(iter.bytes_remaining, 0);
-        assert_eq!(iter.next().is_none(), true);
+        assert_eq!(iter.offset(), 0);
+        assert!(iter.next().is_none());
     }
 
     #[test]
@@ -163,7 +508,8 @@ mod tests {
             data.push((i.to_be_bytes(), i.to_be_bytes()));
         }
         let mut log_writer = LogWriter::new(file_path, true).unwrap();
-        log_writer.append(wb.as_bytes()).unwrap();
+        let payload = wb.to_wal_payload(CompressionType::None, 0).unwrap();
+        log_writer.append(&payload).unwrap();
 
         // Read the log file and construct a write batch
         let mut builder = WriteBatchBuilder::new();
@@ -184,4 +530,304 @@ mod tests {
         }
         builder.consume();
     }
+
+    #[test]
+    fn test_records_across_block_boundaries() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+
+        // Growing payloads end at every alignment relative to the block
+        // boundaries, including right before one and with no room for a header
+        let mut payloads: Vec<Vec<u8>> = (1..600u32)
+            .map(|len| (0..len).map(|i| (len + i) as u8).collect())
+            .collect();
+        payloads.push(vec![7; 3 * DEFAULT_BLOCK_SIZE]);
+        let mut log_writer = LogWriter::new(file_path, true).unwrap();
+        for payload in &payloads {
+            log_writer.append(payload).unwrap();
+        }
+        drop(log_writer);
+        assert!(std::fs::metadata(file_path).unwrap().len() > 8 * DEFAULT_BLOCK_SIZE as u64);
+
+        let log_reader = LogReader::new(file_path).unwrap();
+        let mut log_iter = log_reader.to_iter().unwrap();
+        let mut assembled = Vec::new();
+        let mut read = Vec::new();
+        while let Some(record) = log_iter.next() {
+            let record = record.unwrap();
+            assembled.extend_from_slice(record.payload);
+            if matches!(record.rtype, RecordType::Full | RecordType::Last) {
+                read.push(std::mem::take(&mut assembled));
+            }
+        }
+        assert_eq!(read, payloads);
+        assert!(log_iter.skipped().is_empty());
+        assert_eq!(log_iter.torn_tail(), None);
+    }
+
+    #[test]
+    fn test_seek() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+        let payloads: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; DEFAULT_BLOCK_SIZE / 3]).collect();
+        let mut log_writer = LogWriter::new(file_path, true).unwrap();
+        for payload in &payloads {
+            log_writer.append(payload).unwrap();
+        }
+        drop(log_writer);
+
+        // Remember the offset of every payload starting with a full record
+        let log_reader = LogReader::new(file_path).unwrap();
+        let mut log_iter = log_reader.to_iter().unwrap();
+        let mut offsets = Vec::new();
+        let mut payload = 0;
+        loop {
+            let offset = log_iter.offset();
+            let Some(record) = log_iter.next() else {
+                break;
+            };
+            let record = record.unwrap();
+            if matches!(record.rtype, RecordType::Full | RecordType::First) {
+                offsets.push((offset, payload));
+            }
+            if matches!(record.rtype, RecordType::Full | RecordType::Last) {
+                payload += 1;
+            }
+        }
+        assert_eq!(payload, payloads.len());
+
+        for (offset, payload) in offsets {
+            log_iter.seek(offset).unwrap();
+            assert_eq!(log_iter.offset(), offset);
+            assert_eq!(log_iter.next().unwrap().unwrap().payload[0], payload as u8);
+        }
+
+        // Seeking to the start skips the header
+        log_iter.seek(0).unwrap();
+        assert_eq!(log_iter.offset(), LOG_HEADER_SIZE as u64);
+        assert_eq!(log_iter.next().unwrap().unwrap().payload, payloads[0]);
+    }
+
+    #[test]
+    fn test_chain_iter() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths: Vec<String> = (1..=4)
+            .map(|number| {
+                let path = temp_dir.path().join(format!("{:06}.log", number));
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        // A segment spanning several blocks, an empty segment, and two short ones
+        let mut expected = Vec::new();
+        for (path, count) in paths.iter().zip([5u8, 0, 1, 2]) {
+            let mut log_writer = LogWriter::new(path, true).unwrap();
+            for i in 0..count {
+                let payload = vec![i; DEFAULT_BLOCK_SIZE / 2];
+                log_writer.append(&payload).unwrap();
+                expected.push(payload);
+            }
+        }
+
+        let read_all = |iter: &mut ChainIter| {
+            let mut read = Vec::new();
+            let mut assembled = Vec::new();
+            while let Some(record) = iter.next() {
+                let record = record?;
+                assembled.extend_from_slice(record.payload);
+                if matches!(record.rtype, RecordType::Full | RecordType::Last) {
+                    read.push(std::mem::take(&mut assembled));
+                }
+            }
+            Ok::<_, Error>(read)
+        };
+        let mut chain = ChainIter::new(paths.clone());
+        assert_eq!(read_all(&mut chain).unwrap(), expected);
+        assert_eq!(chain.position().unwrap().0, paths[3]);
+        assert_eq!(chain.torn_tail(), None);
+
+        // A torn record is only expected at the end of the last segment
+        let len = std::fs::metadata(&paths[3]).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&paths[3])
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        let mut chain = ChainIter::new(paths.clone());
+        assert_eq!(read_all(&mut chain).unwrap().len(), expected.len() - 1);
+        assert!(chain.torn_tail().is_some());
+
+        let mut chain = ChainIter::new(paths[2..].to_vec());
+        File::options()
+            .write(true)
+            .open(&paths[2])
+            .unwrap()
+            .set_len(LOG_HEADER_SIZE as u64 + 10)
+            .unwrap();
+        assert!(matches!(read_all(&mut chain), Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn test_resync_after_corruption() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+
+        // Write compressed batches spanning several blocks
+        let batch_count = 40;
+        let mut log_writer = LogWriter::new(file_path, true).unwrap();
+        for i in 0..batch_count {
+            let mut wb = WriteBatch::new();
+            for j in 0..100u32 {
+                let mut value = vec![0; 32];
+                rand::thread_rng().fill_bytes(&mut value);
+                wb.insert_or_update(&(i * 1000 + j).to_be_bytes(), &value);
+            }
+            let payload = wb.to_wal_payload(CompressionType::Snappy, 0).unwrap();
+            log_writer.append(&payload).unwrap();
+        }
+        drop(log_writer);
+
+        // Corrupt a byte in the middle of the second block
+        let mut bytes = std::fs::read(file_path).unwrap();
+        assert!(bytes.len() > 3 * DEFAULT_BLOCK_SIZE);
+        bytes[DEFAULT_BLOCK_SIZE + DEFAULT_BLOCK_SIZE / 2] ^= 0xff;
+        std::fs::write(file_path, &bytes).unwrap();
+
+        let mut builder = WriteBatchBuilder::new();
+        let log_reader = LogReader::new(file_path).unwrap();
+        let mut log_iter = log_reader.to_iter().unwrap();
+        let mut recovered = 0;
+        let mut errors = 0;
+        while let Some(record) = log_iter.next() {
+            let result = record.and_then(|record| builder.accumulate_record(&record));
+            if result.is_err() {
+                errors += 1;
+                builder.consume();
+                continue;
+            }
+            if builder.is_ready() {
+                builder.get_write_batch().validate().unwrap();
+                recovered += 1;
+                builder.consume();
+            }
+        }
+
+        // Only the batches in the skipped half block are lost
+        assert!(errors > 0);
+        assert!(recovered > 0 && recovered < batch_count);
+        assert!(recovered >= batch_count - 10, "recovered {}", recovered);
+        let skipped = log_iter.skipped();
+        assert_eq!(skipped.len(), 1);
+        let corrupt = (DEFAULT_BLOCK_SIZE + DEFAULT_BLOCK_SIZE / 2) as u64;
+        assert!(skipped[0].start <= corrupt && skipped[0].start >= DEFAULT_BLOCK_SIZE as u64);
+        assert_eq!(skipped[0].end, 2 * DEFAULT_BLOCK_SIZE as u64);
+    }
+
+    #[test]
+    fn test_tailing_iter() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap().to_string();
+        let mut log_writer = LogWriter::new(&file_path, true).unwrap();
+        log_writer.append(b"before").unwrap();
+
+        let log_reader = LogReader::new(&file_path).unwrap();
+        let mut tail = log_reader
+            .to_tailing_iter(0, Duration::from_millis(1))
+            .unwrap();
+        tail.set_timeout(Some(Duration::from_secs(10)));
+
+        // Payloads spanning several blocks are followed as they are appended
+        let payloads: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; DEFAULT_BLOCK_SIZE / 3]).collect();
+        let writer = {
+            let payloads = payloads.clone();
+            thread::spawn(move || {
+                for payload in payloads {
+                    log_writer.append(&payload).unwrap();
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        assert_eq!(tail.next().unwrap().unwrap().payload, b"before");
+        for payload in &payloads {
+            let mut assembled = Vec::new();
+            loop {
+                let record = tail.next().unwrap().unwrap();
+                assembled.extend_from_slice(record.payload);
+                if matches!(record.rtype, RecordType::Full | RecordType::Last) {
+                    break;
+                }
+            }
+            assert_eq!(&assembled, payload);
+        }
+        writer.join().unwrap();
+
+        // Once the writer is done, `next` times out
+        tail.set_timeout(Some(Duration::from_millis(10)));
+        assert!(tail.next().is_none());
+        assert_eq!(tail.offset(), std::fs::metadata(&file_path).unwrap().len());
+    }
+
+    #[test]
+    fn test_torn_tail() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+        let mut log_writer = LogWriter::new(file_path, true).unwrap();
+        log_writer.append(&[1; 100]).unwrap();
+        log_writer.append(&[2; 100]).unwrap();
+        let len = std::fs::metadata(file_path).unwrap().len();
+
+        let log_reader = LogReader::new(file_path).unwrap();
+        let mut log_iter = log_reader.to_iter().unwrap();
+        while log_iter.next().is_some() {}
+        assert_eq!(log_iter.torn_tail(), None);
+
+        // Both a cut short payload and a cut short header are torn
+        let record_start = len - 100 - LOG_RECORD_HEADER_SIZE as u64;
+        for torn_len in [len - 1, record_start + 3] {
+            File::options()
+                .write(true)
+                .open(file_path)
+                .unwrap()
+                .set_len(torn_len)
+                .unwrap();
+            let mut log_iter = log_reader.to_iter().unwrap();
+            assert_eq!(log_iter.next().unwrap().unwrap().payload, [1; 100]);
+            assert!(log_iter.next().is_none());
+            assert_eq!(log_iter.torn_tail(), Some(record_start));
+        }
+    }
+
+    #[test]
+    fn test_read_legacy_log() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+
+        // Version 1 logs are appended to with unmasked payload CRCs
+        let header = LogHeader {
+            version: 1,
+            block_size: DEFAULT_BLOCK_SIZE,
+            checksum: Checksum::Crc32c,
+        };
+        std::fs::write(file_path, header.encode()).unwrap();
+        let mut log_writer = LogWriter::new(file_path, false).unwrap();
+        log_writer.append(&[1; 100]).unwrap();
+        log_writer.append(&[2; 100]).unwrap();
+        let bytes = std::fs::read(file_path).unwrap();
+        let record = LogRecord::from_serialized_bytes(&bytes[header.size()..]).unwrap();
+        record.validate_crc(Checksum::Crc32c).unwrap();
+
+        // Logs written before the header was introduced start with a record
+        let versions = [bytes.clone(), bytes[header.size()..].to_vec()];
+        for bytes in versions {
+            std::fs::write(file_path, &bytes).unwrap();
+            let log_reader = LogReader::new(file_path).unwrap();
+            let mut log_iter = log_reader.to_iter().unwrap();
+            assert_eq!(log_iter.next().unwrap().unwrap().payload, [1; 100]);
+            assert_eq!(log_iter.next().unwrap().unwrap().payload, [2; 100]);
+            assert!(log_iter.next().is_none());
+            assert_eq!(log_iter.torn_tail(), None);
+        }
+    }
 }
@@ -1,4 +1,5 @@
 use crate::error::{Error, Result};
+use crate::options::WalChecksum;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive;
 use std::{array::TryFromSliceError, mem};
@@ -13,7 +14,7 @@ pub const BLOCK_PADDING: [u8; LOG_RECORD_HEADER_SIZE] = [0, 0, 0, 0, 0, 0, 0];
 pub const DEFAULT_BUFFER_CAPACITY: usize = 128 * 1024; // TODO: move this to a constants file
 
 const CRC_OFFSET: usize = 0;
-const SIZE_OFFSET: usize = 4;
+pub const SIZE_OFFSET: usize = 4;
 const TYPE_OFFSET: usize = 6;
 const PAYLOAD_OFFSET: usize = 7;
 
@@ -32,6 +33,74 @@ impl RecordType {
     }
 }
 
+/// The value added to a rotated CRC when masking it, as in RocksDB.
+const CRC_MASK_DELTA: u32 = 0xa282ead8;
+
+/// Masks a CRC, so that computing the CRC of a string containing embedded
+/// CRCs does not degrade the CRC of the outer string.
+pub fn mask_crc(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(CRC_MASK_DELTA)
+}
+
+/// The CRC64 variant used to checksum records.
+const CRC64: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+
+/// The checksum stored in the header of the records of a log.
+///
+/// The value of each kind is recorded in the log header from format version 3 on.
+#[derive(Clone, Copy, Default, FromPrimitive, ToPrimitive, PartialEq, Eq, Debug)]
+pub enum Checksum {
+    /// CRC32C over the payload, written by log format versions 0 and 1.
+    Crc32c = 1,
+    /// Masked CRC32C over the type and the payload, as in RocksDB, so that
+    /// bit flips in the type are detected too.
+    #[default]
+    MaskedCrc32c = 2,
+    /// The low 32 bits of the xxHash64 of the type and the payload.
+    XxHash64 = 3,
+    /// The low 32 bits of the CRC64 of the type and the payload.
+    Crc64 = 4,
+}
+
+impl Checksum {
+    pub fn value(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Computes the checksum of a record.
+    pub fn compute(&self, rtype: RecordType, payload: &[u8]) -> u32 {
+        match self {
+            Checksum::Crc32c => crc32c::crc32c(payload),
+            Checksum::MaskedCrc32c => {
+                let crc = crc32c::crc32c(&[rtype.value()]);
+                mask_crc(crc32c::crc32c_append(crc, payload))
+            }
+            Checksum::XxHash64 => {
+                let mut hasher = xxhash_rust::xxh64::Xxh64::new(0);
+                hasher.update(&[rtype.value()]);
+                hasher.update(payload);
+                hasher.digest() as u32
+            }
+            Checksum::Crc64 => {
+                let mut digest = CRC64.digest();
+                digest.update(&[rtype.value()]);
+                digest.update(payload);
+                digest.finalize() as u32
+            }
+        }
+    }
+}
+
+impl From<WalChecksum> for Checksum {
+    fn from(checksum: WalChecksum) -> Self {
+        match checksum {
+            WalChecksum::Crc32c => Checksum::MaskedCrc32c,
+            WalChecksum::XxHash64 => Checksum::XxHash64,
+            WalChecksum::Crc64 => Checksum::Crc64,
+        }
+    }
+}
+
 /// Represents a log record.
 ///
 // Record Format:
@@ -42,7 +111,8 @@ impl RecordType {
 // |CRC (4B) | Size (2B) | Type (1B) | Payload   |
 // +---------+-----------+-----------+--- ... ---+
 //
-// CRC = 32bit hash computed over the payload using CRC
+// CRC = masked CRC32C computed over the type and the payload
+//       (logs written before format version 2 store the CRC32C of the payload)
 // Size = Length of the payload data
 // Type = Type of record
 //      (kZeroType, kFullType, kFirstType, kLastType, kMiddleType )
@@ -66,13 +136,17 @@ fn bytes_to_type<'a, T: TryFrom<&'a [u8], Error = TryFromSliceError>>(
 impl<'a> LogRecord<'a> {
     /// Validates the CRC (Cyclic Redundancy Check) of the log record.
     ///
+    /// # Arguments
+    ///
+    /// * `checksum` - The checksum the log the record was read from is written with.
+    ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the CRC is valid.
     ///
     /// Returns `Err(Error::InvalidCrc)` if the CRC is invalid.
-    pub fn validate_crc(&self) -> Result<()> {
-        let actual_crc = crc32c::crc32c(self.payload);
+    pub fn validate_crc(&self, checksum: Checksum) -> Result<()> {
+        let actual_crc = checksum.compute(self.rtype, self.payload);
         if self.crc == actual_crc {
             return Ok(());
         }
@@ -89,7 +163,7 @@ impl<'a> LogRecord<'a> {
     /// Returns a `Result` containing the deserialized `LogRecord` if successful.
     ///
     /// Returns `Err(Error::WalRecordTooSmall)` if the serialized bytes are too small to form a valid log record.
-    pub fn from_serialized_bytes(bytes: &[u8]) -> Result<LogRecord> {
+    pub fn from_serialized_bytes(bytes: &[u8]) -> Result<LogRecord<'_>> {
         let phantom_record = LogRecord::phantom();
         if bytes.len() < MIN_RECORD_SIZE {
             return Err(Error::WalRecordTooSmall(bytes.len(), MIN_RECORD_SIZE));
@@ -98,6 +172,14 @@ impl<'a> LogRecord<'a> {
             &bytes[SIZE_OFFSET..SIZE_OFFSET + mem::size_of_val(&phantom_record.size)],
         )?);
 
+        if bytes.len() < PAYLOAD_OFFSET + usize::from(payload_size) {
+            return Err(Error::Corruption(format!(
+                "the WAL record has a payload of {} bytes but only {} bytes are left",
+                payload_size,
+                bytes.len() - PAYLOAD_OFFSET
+            )));
+        }
+
         let record_type = u8::from_be_bytes(bytes_to_type(
             &bytes[TYPE_OFFSET..TYPE_OFFSET + mem::size_of_val(&phantom_record.rtype)],
         )?);
@@ -107,12 +189,11 @@ impl<'a> LogRecord<'a> {
             )?),
             size: payload_size,
             rtype: RecordType::from_u8(record_type).ok_or(Error::InvalidRecordType(record_type))?,
-            payload: &bytes[PAYLOAD_OFFSET
-                ..(PAYLOAD_OFFSET + (TryInto::<usize>::try_into(payload_size).unwrap()))],
+            payload: &bytes[PAYLOAD_OFFSET..(PAYLOAD_OFFSET + usize::from(payload_size))],
         })
     }
 
-    /// Creates a new `LogRecord`.
+    /// Creates a new `LogRecord` with the checksum of the current log format.
     ///
     /// # Arguments
     ///
@@ -122,15 +203,30 @@ impl<'a> LogRecord<'a> {
     /// # Returns
     ///
     /// Returns the newly created `LogRecord`.
-    pub fn new(rtype: RecordType, payload: &[u8]) -> LogRecord {
+    pub fn new(rtype: RecordType, payload: &[u8]) -> LogRecord<'_> {
+        LogRecord::with_checksum(rtype, payload, Checksum::default())
+    }
+
+    /// Creates a new `LogRecord` with the given kind of checksum, e.g. to
+    /// append to a log written in an older format.
+    pub fn with_checksum(rtype: RecordType, payload: &[u8], checksum: Checksum) -> LogRecord<'_> {
         LogRecord {
-            crc: crc32c::crc32c(payload),
+            crc: checksum.compute(rtype, payload),
             rtype,
             size: payload.len().try_into().unwrap(),
             payload,
         }
     }
 
+    /// Serializes the log record at the end of `buf`.
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+        buf.reserve(self.len());
+        buf.extend_from_slice(&self.crc.to_be_bytes());
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf.push(self.rtype.value());
+        buf.extend_from_slice(self.payload);
+    }
+
     fn phantom() -> LogRecord<'a> {
         LogRecord {
             crc: 0,
@@ -165,7 +261,24 @@ mod tests {
             rtype: RecordType::Full,
             payload,
         };
-        assert_eq!(record.validate_crc().is_ok(), true);
+        assert!(record.validate_crc(Checksum::Crc32c).is_ok());
+        assert!(record.validate_crc(Checksum::MaskedCrc32c).is_err());
+    }
+
+    #[test]
+    fn test_masked_crc_covers_type() {
+        let payload = b"test payload";
+        let mut record = LogRecord::new(RecordType::Full, payload);
+        let crc = crc32c::crc32c_append(crc32c::crc32c(&[RecordType::Full.value()]), payload);
+        assert_eq!(record.crc, mask_crc(crc));
+        assert_ne!(record.crc, crc);
+        record.validate_crc(Checksum::MaskedCrc32c).unwrap();
+
+        // A flipped type is detected
+        record.rtype = RecordType::First;
+        record
+            .validate_crc(Checksum::MaskedCrc32c)
+            .expect_err("Expected an error");
     }
 
     #[test]
@@ -178,7 +291,39 @@ mod tests {
             rtype: RecordType::Full,
             payload,
         };
-        record.validate_crc().expect_err("Expected an error");
+        record
+            .validate_crc(Checksum::Crc32c)
+            .expect_err("Expected an error");
+    }
+
+    #[test]
+    fn test_checksums() {
+        let payload = b"test payload";
+        for checksum in [
+            Checksum::Crc32c,
+            Checksum::MaskedCrc32c,
+            Checksum::XxHash64,
+            Checksum::Crc64,
+        ] {
+            let record = LogRecord::with_checksum(RecordType::Full, payload, checksum);
+            record.validate_crc(checksum).unwrap();
+            assert_eq!(Checksum::from_u8(checksum.value()), Some(checksum));
+
+            let mut corrupted = payload.to_vec();
+            corrupted[0] ^= 1;
+            let corrupted = LogRecord {
+                payload: &corrupted,
+                ..record
+            };
+            corrupted
+                .validate_crc(checksum)
+                .expect_err("Expected an error");
+        }
+        assert_eq!(
+            Checksum::XxHash64.compute(RecordType::Full, payload),
+            xxhash_rust::xxh64::xxh64(&[&[RecordType::Full.value()][..], payload].concat(), 0)
+                as u32
+        );
     }
 
     #[test]
@@ -217,13 +362,23 @@ mod tests {
     fn test_new() {
         let payload = b"test payload";
         let record = LogRecord::new(RecordType::Full, payload);
-        let crc = crc32c::crc32c(payload);
+        let crc = Checksum::MaskedCrc32c.compute(RecordType::Full, payload);
         assert_eq!(record.crc, crc);
         assert_eq!(record.size, payload.len() as u16);
         assert_eq!(record.rtype, RecordType::Full);
         assert_eq!(record.payload, payload);
     }
 
+    #[test]
+    fn test_serialize_into() {
+        let payload = b"test payload";
+        let record = LogRecord::new(RecordType::Full, payload);
+        let mut buf = vec![1];
+        record.serialize_into(&mut buf);
+        assert_eq!(buf.len(), 1 + record.len());
+        assert_eq!(LogRecord::from_serialized_bytes(&buf[1..]).unwrap(), record);
+    }
+
     #[test]
     fn test_len() {
         let payload = b"test payload";
@@ -1,30 +1,68 @@
 use crate::buffer_consumer::BufferConsumer;
 use crate::error::{Error, Result};
 use crate::file_writer::FileWriter;
+use crate::log_header::LogHeader;
 use crate::log_record::{
-    LogRecord, RecordType, BLOCK_PADDING, DEFAULT_BLOCK_SIZE, LOG_RECORD_HEADER_SIZE,
-    MIN_RECORD_SIZE,
+    Checksum, LogRecord, RecordType, BLOCK_PADDING, DEFAULT_BUFFER_CAPACITY,
+    LOG_RECORD_HEADER_SIZE, MIN_RECORD_SIZE,
 };
-use std::cmp::min;
+use crate::metrics::Metrics;
+use crate::options::WalPreallocation;
+use std::{cmp::min, fs::File, sync::Arc};
 
+/// The counters of a `LogWriter`, since it was created.
 pub struct Stats {
-    record_count: usize,
+    record_count: u64,
+    bytes_written: u64,
+    padding_bytes: u64,
 }
 
 impl Stats {
     fn new() -> Stats {
-        Stats { record_count: 0 }
+        Stats {
+            record_count: 0,
+            bytes_written: 0,
+            padding_bytes: 0,
+        }
     }
 
-    fn consume_record(&mut self, _record: &LogRecord) {
+    fn consume_record(&mut self, record: &LogRecord) {
         self.record_count += 1;
+        self.bytes_written += record.len() as u64;
+    }
+
+    fn consume_padding(&mut self, len: usize) {
+        self.bytes_written += len as u64;
+        self.padding_bytes += len as u64;
+    }
+
+    /// Returns the number of records written.
+    pub fn record_count(&self) -> u64 {
+        self.record_count
+    }
+
+    /// Returns the number of bytes written, including record headers and padding.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns the number of bytes written to pad the end of blocks.
+    pub fn padding_bytes(&self) -> u64 {
+        self.padding_bytes
     }
 }
 
 /// The `LogWriter` struct represents a log writer that appends log records to a file.
 pub struct LogWriter {
     fw: FileWriter,
+    /// The checksum the records of the log are written with.
+    checksum: Checksum,
+    /// The size of the blocks the log is divided into.
+    block_size: usize,
     block_pos: usize,
+    /// The records of the payload being appended, serialized so that they
+    /// reach the file in a single write. Reused across appends.
+    buf: Vec<u8>,
     stats: Stats,
 }
 
@@ -40,47 +78,106 @@ impl LogWriter {
     ///
     /// Returns a `Result` containing the `LogWriter` instance if successful, or an error if the file cannot be opened.
     pub fn new(file_path: &str, truncate: bool) -> Result<LogWriter> {
-        let file_writer = FileWriter::new(file_path, truncate)?;
+        LogWriter::with_header(file_path, truncate, LogHeader::default())
+    }
+
+    /// Creates a new `LogWriter` instance like `new`, writing a new log in
+    /// the format described by `header`.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file where the log records will be written.
+    /// * `truncate` - A flag indicating whether to truncate the file if it already exists.
+    /// * `header` - The header of a new log, with the checksum of its records and
+    ///   its block size. Existing logs keep the format recorded in their header.
+    pub fn with_header(file_path: &str, truncate: bool, header: LogHeader) -> Result<LogWriter> {
+        let mut file_writer = FileWriter::new(file_path, truncate)?;
+        // A new log starts with a header. Existing logs are appended to in the
+        // format they were created in.
+        let header = if file_writer.offset() == 0 {
+            file_writer.append(&header.encode())?;
+            file_writer.flush()?;
+            header
+        } else {
+            LogHeader::read(File::open(file_path)?)?
+        };
+        // Keep appending to the block the existing records left off in, so that
+        // readers can rely on records never straddling a block boundary.
+        let block_pos = (file_writer.offset() % header.block_size as u64) as usize;
         Ok(LogWriter {
             fw: file_writer,
-            block_pos: 0,
+            checksum: header.checksum,
+            block_size: header.block_size,
+            block_pos,
+            buf: Vec::new(),
             stats: Stats::new(),
         })
     }
 
+    /// Sets the metrics the writer reports its fsyncs to.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.fw.set_metrics(metrics);
+    }
+
+    /// Sets how the writer reserves disk space ahead of appends, or disables
+    /// preallocation if `preallocation` is `None`.
+    pub fn set_preallocation(&mut self, preallocation: Option<WalPreallocation>) {
+        self.fw.set_preallocation(preallocation);
+    }
+
     /// Returns the remaining capacity in the current log block.
     fn remaining_block_capacity(&self) -> usize {
-        crate::log_record::DEFAULT_BLOCK_SIZE - self.block_pos
+        self.block_size - self.block_pos
     }
 
-    /// Adds padding to the current log block if necessary.
-    ///
-    /// # Returns
-    ///
-    /// Returns `Ok(())` if successful, or an error if the padding cannot be added.
-    fn add_block_padding(&mut self) -> Result<()> {
-        let remaining_block_size = DEFAULT_BLOCK_SIZE - self.block_pos;
+    /// Pads the rest of the current log block and starts a new one if the
+    /// block cannot fit another record.
+    fn add_block_padding(&mut self) {
+        let remaining_block_size = self.block_size - self.block_pos;
         if remaining_block_size < MIN_RECORD_SIZE {
-            self.fw.append(&BLOCK_PADDING[0..remaining_block_size])?;
+            self.buf
+                .extend_from_slice(&BLOCK_PADDING[0..remaining_block_size]);
+            self.stats.consume_padding(remaining_block_size);
+            self.block_pos = 0;
         }
-        self.block_pos = 0;
-        Ok(())
     }
 
-    /// Appends a log record to the log file.
+    /// Flushes the records buffered by the log to the OS, without syncing them.
     ///
-    /// # Arguments
+    /// # Returns
     ///
-    /// * `record` - The log record to append.
+    /// Returns `Ok(())` if successful, or an error if the log cannot be flushed.
+    pub fn flush(&mut self) -> Result<()> {
+        self.fw.flush()
+    }
+
+    /// Flushes the log to the OS and fsyncs it to disk.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if successful, or an error if the record cannot be appended.
-    fn append_record(&mut self, record: &LogRecord) -> Result<()> {
-        self.fw.append(&record.crc.to_be_bytes())?;
-        self.fw.append(&record.size.to_be_bytes())?;
-        self.fw.append(&record.rtype.value().to_be_bytes())?;
-        self.fw.append(record.payload)
+    /// Returns `Ok(())` if successful, or an error if the log cannot be synced.
+    pub fn sync(&mut self) -> Result<()> {
+        self.fw.sync()
+    }
+
+    /// Returns the counters of the writer.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Returns the number of blocks of the log file that hold data.
+    pub fn block_count(&self) -> u64 {
+        self.fw.offset().div_ceil(self.block_size as u64)
+    }
+
+    /// Returns the offset in the log file at which the next record will be written.
+    pub fn offset(&self) -> u64 {
+        self.fw.offset()
+    }
+
+    /// Returns a new handle to the log file, e.g. to sync it from another thread.
+    pub fn try_clone_file(&self) -> Result<File> {
+        self.fw.try_clone_file()
     }
 
     /// Appends a payload to the log file.
@@ -98,9 +195,10 @@ impl LogWriter {
         }
 
         let mut record_count = 0;
+        self.buf.clear();
         let pconsumer = BufferConsumer::new(payload);
         while !pconsumer.done() {
-            self.add_block_padding()?;
+            self.add_block_padding();
 
             let consume_count = min(
                 pconsumer.remaining(),
@@ -121,12 +219,16 @@ impl LogWriter {
                 }
             };
 
-            let record = LogRecord::new(rtype, payload);
+            let record = LogRecord::with_checksum(rtype, payload, self.checksum);
             record_count += 1;
-            self.append_record(&record)?;
+            record.serialize_into(&mut self.buf);
             self.stats.consume_record(&record);
             self.block_pos += record.len();
         }
+        let appended = self.fw.append(&self.buf);
+        // Don't hold on to the memory of an unusually large payload
+        self.buf.shrink_to(DEFAULT_BUFFER_CAPACITY);
+        appended?;
         self.fw.flush()
     }
 }
@@ -136,6 +238,7 @@ mod tests {
     use rand::RngCore;
     use tempfile::NamedTempFile;
 
+    use crate::log_header::{LogHeader, LOG_HEADER_SIZE};
     use crate::log_record::{LogRecord, DEFAULT_BLOCK_SIZE, LOG_RECORD_HEADER_SIZE};
 
     use super::LogWriter;
@@ -152,7 +255,8 @@ mod tests {
 
         // validate the contents of the file
         let reader = std::fs::read(log_file_path).unwrap();
-        let record = LogRecord::from_serialized_bytes(&reader).unwrap();
+        assert_eq!(LogHeader::decode(&reader).unwrap(), LogHeader::default());
+        let record = LogRecord::from_serialized_bytes(&reader[LOG_HEADER_SIZE..]).unwrap();
         assert_eq!(record.payload, payload);
     }
 
@@ -195,10 +299,13 @@ mod tests {
             LogWriter::new(log_file_path, true).expect("Failed to create a log writer");
         writer.append(&payload).expect("Failed writing the payload");
         // The payload should spill over to the third block
-        // The first block should contain DEFAULT_BLOCK_SIZE - LOG_RECORD_HEADER_SIZE bytes of the payload
+        // The first block should contain DEFAULT_BLOCK_SIZE - LOG_HEADER_SIZE - LOG_RECORD_HEADER_SIZE bytes of the payload
         // The second block should contain DEFAULT_BLOCK_SIZE - LOG_RECORD_HEADER_SIZE bytes of the payload
-        // The third block should contain 2 * LOG_RECORD_HEADER_SIZE bytes of the payload + LOG_RECORD_HEADER_SIZE bytes of the header
-        assert_eq!(writer.block_pos, 3 * LOG_RECORD_HEADER_SIZE);
+        // The third block should contain LOG_HEADER_SIZE + 2 * LOG_RECORD_HEADER_SIZE bytes of the payload + LOG_RECORD_HEADER_SIZE bytes of the header
+        assert_eq!(
+            writer.block_pos,
+            LOG_HEADER_SIZE + 3 * LOG_RECORD_HEADER_SIZE
+        );
     }
 
     #[test]
@@ -206,12 +313,15 @@ mod tests {
         let temp_file = NamedTempFile::new().unwrap();
         let log_file_path = temp_file.path().to_str().unwrap();
 
-        let payload_size = DEFAULT_BLOCK_SIZE - LOG_RECORD_HEADER_SIZE - 1;
+        let payload_size = DEFAULT_BLOCK_SIZE - LOG_HEADER_SIZE - LOG_RECORD_HEADER_SIZE - 1;
         let mut payload: Vec<u8> = vec![0; payload_size];
         rand::thread_rng().fill_bytes(&mut payload);
         let mut writer = LogWriter::new(log_file_path, true).expect("Failed creating a log writer");
         writer.append(&payload).expect("Failed writing the payload");
-        assert_eq!(writer.block_pos, payload_size + LOG_RECORD_HEADER_SIZE);
+        assert_eq!(
+            writer.block_pos,
+            LOG_HEADER_SIZE + payload_size + LOG_RECORD_HEADER_SIZE
+        );
 
         let payload_size = 1;
         let mut payload: Vec<u8> = vec![0; payload_size];
@@ -219,5 +329,49 @@ mod tests {
         writer.append(&payload).expect("Failed writing the payload");
         // This payload should be written to the next block
         assert_eq!(writer.block_pos, payload_size + LOG_RECORD_HEADER_SIZE);
+
+        let stats = writer.stats();
+        assert_eq!(stats.record_count(), 2);
+        assert_eq!(stats.padding_bytes(), 1);
+        assert_eq!(
+            stats.bytes_written(),
+            (DEFAULT_BLOCK_SIZE - LOG_HEADER_SIZE) as u64
+                + (payload_size + LOG_RECORD_HEADER_SIZE) as u64
+        );
+        assert_eq!(writer.block_count(), 2);
+    }
+
+    #[test]
+    fn test_records_stay_block_aligned() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let log_file_path = temp_file.path().to_str().unwrap();
+
+        let mut writer = LogWriter::new(log_file_path, true).expect("Failed creating a log writer");
+        writer
+            .append(&[1; 100])
+            .expect("Failed writing the payload");
+        writer
+            .append(&[2; 100])
+            .expect("Failed writing the payload");
+        assert_eq!(
+            writer.block_pos,
+            LOG_HEADER_SIZE + 2 * (100 + LOG_RECORD_HEADER_SIZE)
+        );
+
+        // A reopened writer continues in the block the file ends in
+        drop(writer);
+        let mut writer =
+            LogWriter::new(log_file_path, false).expect("Failed creating a log writer");
+        assert_eq!(
+            writer.block_pos,
+            LOG_HEADER_SIZE + 2 * (100 + LOG_RECORD_HEADER_SIZE)
+        );
+        writer
+            .append(&vec![3; DEFAULT_BLOCK_SIZE])
+            .expect("Failed writing the payload");
+        assert_eq!(
+            std::fs::metadata(log_file_path).unwrap().len() % DEFAULT_BLOCK_SIZE as u64,
+            writer.block_pos as u64
+        );
     }
 }
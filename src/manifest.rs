@@ -0,0 +1,527 @@
+//! This module provides the MANIFEST, which persists the set of live SST files.
+//!
+//! The MANIFEST is a log of `VersionEdit`s, written with the same record format
+//! as the WAL. Each edit describes the files added to or removed from the LSM
+//! tree along with bookkeeping counters. Replaying all the edits in order at
+//! open time reconstructs the `VersionState`.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    lending_iterator::LendingIterator,
+    log_reader::LogReader,
+    log_record::RecordType,
+    log_writer::LogWriter,
+};
+
+/// The number of levels in the LSM tree.
+pub const NUM_LEVELS: usize = 7;
+
+const TAG_LOG_NUMBER: u8 = 1;
+const TAG_NEXT_FILE_NUMBER: u8 = 2;
+const TAG_LAST_SEQUENCE: u8 = 3;
+const TAG_NEW_FILE: u8 = 4;
+const TAG_DELETED_FILE: u8 = 5;
+
+/// Metadata describing a single SST file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number: u64,
+    pub level: u32,
+    pub size: u64,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+}
+
+/// A change to the `VersionState`, persisted as one MANIFEST record.
+///
+// Edit Format:
+//
+// A sequence of tagged fields, each starting with a 1 byte tag:
+//
+// LOG_NUMBER       | u64
+// NEXT_FILE_NUMBER | u64
+// LAST_SEQUENCE    | u64
+// NEW_FILE         | level (u32) | number (u64) | size (u64) | smallest (u32 len + bytes) | largest (u32 len + bytes)
+// DELETED_FILE     | level (u32) | number (u64)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub last_sequence: Option<u64>,
+    pub new_files: Vec<FileMetaData>,
+    pub deleted_files: Vec<(u32, u64)>,
+}
+
+impl VersionEdit {
+    /// Records the addition of a file.
+    #[allow(dead_code)]
+    pub fn add_file(&mut self, file: FileMetaData) {
+        self.new_files.push(file);
+    }
+
+    /// Records the removal of the file `number` from `level`.
+    #[allow(dead_code)]
+    pub fn delete_file(&mut self, level: u32, number: u64) {
+        self.deleted_files.push((level, number));
+    }
+
+    /// Serializes the edit.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let fields = [
+            (TAG_LOG_NUMBER, self.log_number),
+            (TAG_NEXT_FILE_NUMBER, self.next_file_number),
+            (TAG_LAST_SEQUENCE, self.last_sequence),
+        ];
+        for (tag, value) in fields {
+            if let Some(value) = value {
+                buf.push(tag);
+                buf.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        for file in &self.new_files {
+            buf.push(TAG_NEW_FILE);
+            buf.extend_from_slice(&file.level.to_be_bytes());
+            buf.extend_from_slice(&file.number.to_be_bytes());
+            buf.extend_from_slice(&file.size.to_be_bytes());
+            for key in [&file.smallest, &file.largest] {
+                buf.extend_from_slice(&u32::try_from(key.len()).unwrap().to_be_bytes());
+                buf.extend_from_slice(key);
+            }
+        }
+        for (level, number) in &self.deleted_files {
+            buf.push(TAG_DELETED_FILE);
+            buf.extend_from_slice(&level.to_be_bytes());
+            buf.extend_from_slice(&number.to_be_bytes());
+        }
+        buf
+    }
+
+    /// Deserializes an edit.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::Corruption)` if the bytes are not a valid edit.
+    pub fn decode(bytes: &[u8]) -> Result<VersionEdit> {
+        let mut decoder = Decoder { bytes, pos: 0 };
+        let mut edit = VersionEdit::default();
+        while !decoder.done() {
+            match decoder.u8()? {
+                TAG_LOG_NUMBER => edit.log_number = Some(decoder.u64()?),
+                TAG_NEXT_FILE_NUMBER => edit.next_file_number = Some(decoder.u64()?),
+                TAG_LAST_SEQUENCE => edit.last_sequence = Some(decoder.u64()?),
+                TAG_NEW_FILE => edit.new_files.push(FileMetaData {
+                    level: decoder.level()?,
+                    number: decoder.u64()?,
+                    size: decoder.u64()?,
+                    smallest: decoder.bytes()?,
+                    largest: decoder.bytes()?,
+                }),
+                TAG_DELETED_FILE => edit.deleted_files.push((decoder.level()?, decoder.u64()?)),
+                tag => {
+                    return Err(Error::Corruption(format!(
+                        "unknown version edit tag {}",
+                        tag
+                    )))
+                }
+            }
+        }
+        Ok(edit)
+    }
+}
+
+/// A bounds-checked reader over the bytes of an encoded `VersionEdit`.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn done(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let data = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| Error::Corruption("truncated version edit".to_string()))?;
+        self.pos += n;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn level(&mut self) -> Result<u32> {
+        let level = self.u32()?;
+        if level as usize >= NUM_LEVELS {
+            return Err(Error::Corruption(format!("invalid level {}", level)));
+        }
+        Ok(level)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+/// The state of the LSM tree reconstructed from the MANIFEST.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionState {
+    pub log_number: u64,
+    pub next_file_number: u64,
+    pub last_sequence: u64,
+    pub levels: [Vec<FileMetaData>; NUM_LEVELS],
+}
+
+impl VersionState {
+    /// Applies an edit to the state.
+    ///
+    /// Files in level 0 may overlap and are kept in the order they were created;
+    /// the files of the other levels are kept sorted by their smallest key.
+    pub fn apply(&mut self, edit: &VersionEdit) {
+        if let Some(log_number) = edit.log_number {
+            self.log_number = log_number;
+        }
+        if let Some(next_file_number) = edit.next_file_number {
+            self.next_file_number = next_file_number;
+        }
+        if let Some(last_sequence) = edit.last_sequence {
+            self.last_sequence = last_sequence;
+        }
+        for (level, number) in &edit.deleted_files {
+            self.levels[*level as usize].retain(|file| file.number != *number);
+        }
+        for file in &edit.new_files {
+            let level = &mut self.levels[file.level as usize];
+            level.push(file.clone());
+            if file.level == 0 {
+                level.sort_by_key(|file| file.number);
+            } else {
+                level.sort_by(|a, b| a.smallest.cmp(&b.smallest));
+            }
+        }
+    }
+
+    /// Returns an edit that recreates this state from scratch.
+    pub fn snapshot(&self) -> VersionEdit {
+        VersionEdit {
+            log_number: Some(self.log_number),
+            next_file_number: Some(self.next_file_number),
+            last_sequence: Some(self.last_sequence),
+            new_files: self.levels.iter().flatten().cloned().collect(),
+            deleted_files: Vec::new(),
+        }
+    }
+}
+
+/// The name of the file pointing at the live MANIFEST.
+pub const CURRENT_FILE_NAME: &str = "CURRENT";
+
+/// The MANIFEST is rotated once it grows beyond this many bytes.
+pub const DEFAULT_MAX_MANIFEST_SIZE: usize = 4 * 1024 * 1024;
+
+/// Returns the path of the MANIFEST with the given file number in `dir`.
+pub fn manifest_file_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("MANIFEST-{:06}", number))
+}
+
+/// Atomically replaces the contents of `path` with `data`.
+///
+/// The data is written to a temporary file which is fsynced and renamed over
+/// `path`, and the parent directory is fsynced so that the rename itself is durable.
+/// A crash at any point leaves either the old or the new contents in place.
+pub fn write_file_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+/// Fsyncs a directory, making the creation, removal or renaming of its entries durable.
+pub fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Returns the file number of the MANIFEST the CURRENT file in `dir` points at,
+/// or `None` if there is no CURRENT file.
+pub fn read_current(dir: &Path) -> Result<Option<u64>> {
+    let contents = match fs::read_to_string(dir.join(CURRENT_FILE_NAME)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(Error::Io(err)),
+    };
+    contents
+        .strip_suffix('\n')
+        .and_then(|name| name.strip_prefix("MANIFEST-"))
+        .and_then(|number| number.parse().ok())
+        .map(Some)
+        .ok_or_else(|| Error::Corruption(format!("invalid CURRENT file: {:?}", contents)))
+}
+
+/// The live MANIFEST of a database directory.
+///
+/// The MANIFEST keeps the `VersionState` it has written in memory. Once the file
+/// grows beyond `max_size` bytes, a new MANIFEST starting with a snapshot of the
+/// state is written and the CURRENT file is atomically switched over to it.
+pub struct Manifest {
+    dir: PathBuf,
+    number: u64,
+    log_writer: LogWriter,
+    state: VersionState,
+    size: usize,
+    max_size: usize,
+}
+
+impl Manifest {
+    /// Creates a new MANIFEST in `dir` holding `state`, and points CURRENT at it.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The database directory.
+    /// * `state` - The initial state recorded in the MANIFEST.
+    /// * `max_size` - The size in bytes beyond which the MANIFEST is rotated.
+    pub fn create(dir: &Path, state: VersionState, max_size: usize) -> Result<Manifest> {
+        let mut state = state;
+        let number = state.next_file_number;
+        state.next_file_number += 1;
+
+        let path = manifest_file_path(dir, number);
+        let mut log_writer = LogWriter::new(path_str(&path)?, true)?;
+        let snapshot = state.snapshot().encode();
+        log_writer.append(&snapshot)?;
+        log_writer.sync()?;
+        write_file_atomically(
+            &dir.join(CURRENT_FILE_NAME),
+            format!("MANIFEST-{:06}\n", number).as_bytes(),
+        )?;
+
+        Ok(Manifest {
+            dir: dir.to_path_buf(),
+            number,
+            log_writer,
+            state,
+            size: snapshot.len(),
+            max_size,
+        })
+    }
+
+    /// Opens the MANIFEST the CURRENT file in `dir` points at, replays it, and
+    /// continues appending to it.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(None)` if the directory has no CURRENT file.
+    pub fn recover(dir: &Path, max_size: usize) -> Result<Option<Manifest>> {
+        let Some(number) = read_current(dir)? else {
+            return Ok(None);
+        };
+        let path = manifest_file_path(dir, number);
+        let state = replay(path_str(&path)?)?;
+        let size = fs::metadata(&path)?.len() as usize;
+        Ok(Some(Manifest {
+            dir: dir.to_path_buf(),
+            number,
+            log_writer: LogWriter::new(path_str(&path)?, false)?,
+            state,
+            size,
+            max_size,
+        }))
+    }
+
+    /// Returns the state recorded in the MANIFEST.
+    pub fn state(&self) -> &VersionState {
+        &self.state
+    }
+
+    /// Returns the file number of the MANIFEST.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// Durably appends an edit to the MANIFEST and applies it to the state.
+    ///
+    /// The MANIFEST is rotated afterwards if it has grown too large.
+    pub fn log_and_apply(&mut self, edit: &VersionEdit) -> Result<()> {
+        let encoded = edit.encode();
+        self.log_writer.append(&encoded)?;
+        self.log_writer.sync()?;
+        self.size += encoded.len();
+        self.state.apply(edit);
+
+        if self.size > self.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Switches to a new MANIFEST containing a snapshot of the current state,
+    /// and removes the old one.
+    fn rotate(&mut self) -> Result<()> {
+        let old_path = manifest_file_path(&self.dir, self.number);
+        *self = Manifest::create(&self.dir, self.state.clone(), self.max_size)?;
+        fs::remove_file(old_path)?;
+        Ok(())
+    }
+}
+
+/// Replays the MANIFEST at `file_path` and returns the resulting state.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or contains an invalid edit.
+pub fn replay(file_path: &str) -> Result<VersionState> {
+    let log_reader = LogReader::new(file_path)?;
+    let mut iter = log_reader.to_iter()?;
+    let mut state = VersionState::default();
+    let mut edit_bytes = Vec::new();
+    while let Some(record_or_error) = iter.next() {
+        let record = record_or_error?;
+        edit_bytes.extend_from_slice(record.payload);
+        match record.rtype {
+            RecordType::Full | RecordType::Last => {
+                state.apply(&VersionEdit::decode(&edit_bytes)?);
+                edit_bytes.clear();
+            }
+            RecordType::First | RecordType::Middle => {}
+            RecordType::None => return Err(Error::InvalidRecordType(record.rtype.value())),
+        }
+    }
+    Ok(state)
+}
+
+/// Returns `path` as a string, as expected by the log reader and writer.
+pub fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::ValueError(format!("path {:?} is not valid UTF-8", path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn file(number: u64, level: u32, smallest: &[u8], largest: &[u8]) -> FileMetaData {
+        FileMetaData {
+            number,
+            level,
+            size: 1024,
+            smallest: smallest.to_vec(),
+            largest: largest.to_vec(),
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut edit = VersionEdit {
+            log_number: Some(3),
+            next_file_number: Some(10),
+            last_sequence: Some(42),
+            ..Default::default()
+        };
+        edit.add_file(file(7, 1, b"a", b"m"));
+        edit.delete_file(0, 5);
+        assert_eq!(VersionEdit::decode(&edit.encode()).unwrap(), edit);
+
+        let encoded = edit.encode();
+        VersionEdit::decode(&encoded[..encoded.len() - 1]).expect_err("Expected a truncated edit");
+        VersionEdit::decode(&[42]).expect_err("Expected an unknown tag");
+    }
+
+    #[test]
+    fn recover() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        assert!(Manifest::recover(dir, DEFAULT_MAX_MANIFEST_SIZE)
+            .unwrap()
+            .is_none());
+
+        let state = VersionState {
+            next_file_number: 1,
+            ..Default::default()
+        };
+        let mut manifest = Manifest::create(dir, state, DEFAULT_MAX_MANIFEST_SIZE).unwrap();
+        assert_eq!(read_current(dir).unwrap(), Some(1));
+
+        let mut edit = VersionEdit {
+            next_file_number: Some(5),
+            ..Default::default()
+        };
+        edit.add_file(file(2, 0, b"a", b"z"));
+        edit.add_file(file(3, 1, b"m", b"z"));
+        edit.add_file(file(4, 1, b"a", b"l"));
+        manifest.log_and_apply(&edit).unwrap();
+
+        // Compact file 2 into level 1
+        let mut edit = VersionEdit {
+            next_file_number: Some(6),
+            last_sequence: Some(100),
+            ..Default::default()
+        };
+        edit.delete_file(0, 2);
+        edit.add_file(file(5, 1, b"n", b"p"));
+        manifest.log_and_apply(&edit).unwrap();
+
+        let recovered = Manifest::recover(dir, DEFAULT_MAX_MANIFEST_SIZE)
+            .unwrap()
+            .unwrap();
+        let state = recovered.state();
+        assert_eq!(state, manifest.state());
+        assert_eq!(state.next_file_number, 6);
+        assert_eq!(state.last_sequence, 100);
+        assert!(state.levels[0].is_empty());
+        let numbers: Vec<u64> = state.levels[1].iter().map(|f| f.number).collect();
+        assert_eq!(numbers, vec![4, 3, 5]);
+    }
+
+    #[test]
+    fn rotate() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+
+        let mut manifest = Manifest::create(dir, VersionState::default(), 256).unwrap();
+        let first_number = manifest.number();
+        for number in 1..20 {
+            let mut edit = VersionEdit {
+                next_file_number: Some(manifest.state().next_file_number + 1),
+                ..Default::default()
+            };
+            edit.add_file(file(number * 100, 1, &[number as u8], &[number as u8]));
+            manifest.log_and_apply(&edit).unwrap();
+        }
+
+        // The MANIFEST was rotated, CURRENT points at the new one and the old one is gone
+        assert_ne!(manifest.number(), first_number);
+        assert_eq!(read_current(dir).unwrap(), Some(manifest.number()));
+        assert!(!manifest_file_path(dir, first_number).exists());
+
+        let recovered = Manifest::recover(dir, 256).unwrap().unwrap();
+        assert_eq!(recovered.state(), manifest.state());
+        assert_eq!(recovered.state().levels[1].len(), 19);
+    }
+}
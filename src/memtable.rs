@@ -1,53 +1,805 @@
-use std::collections::BTreeMap;
+use std::{
+    cmp::Reverse,
+    collections::{btree_map, BTreeMap, BTreeSet},
+    mem,
+    ops::Bound,
+};
 
 use tinyvec::TinyVec;
+
+use crate::{
+    arena::{Arena, ArenaSlice},
+    keys::{InternalKey, InternalKeyRef, LookupKey, ValueType, MAX_SEQUENCE},
+    write_batch::now_micros,
+};
+
+type Bytes = TinyVec<[u8; 16]>;
+
+/// The number of bytes a version takes in the table besides its user key and
+/// value. Nodes of the tree hold several entries, so this is a lower bound.
+const ENTRY_OVERHEAD: usize = mem::size_of::<InternalKey>() + mem::size_of::<Version>();
+
+/// The number of bytes of pruned values below which the arena is never reclaimed.
+const MIN_ARENA_GARBAGE: usize = 1 << 16;
+
+/// Bounds of the versions in the table, borrowing their user keys.
+type VersionBounds<'k> = (Bound<LookupKey<'k>>, Bound<LookupKey<'k>>);
+
+/// Returns the versions of `table` within `bounds`, without copying the user
+/// keys of the bounds.
+fn range<'a>(
+    table: &'a BTreeMap<InternalKey, Version>,
+    (start, end): VersionBounds,
+) -> btree_map::Range<'a, InternalKey, Version> {
+    let start = start.as_ref().map(|key| key as &dyn InternalKeyRef);
+    let end = end.as_ref().map(|key| key as &dyn InternalKeyRef);
+    table.range::<dyn InternalKeyRef, _>((start, end))
+}
+
+/// Returns the bounds of the versions of `key` visible at `sequence`, newest first.
+fn versions_at(key: &[u8], sequence: u64) -> VersionBounds<'_> {
+    (
+        Bound::Included(LookupKey::new(key, sequence, ValueType::FOR_SEEK)),
+        Bound::Included(LookupKey::new(key, 0, ValueType::Deletion)),
+    )
+}
+
+/// Returns the bounds of every version of the keys within `start` and `end`.
+fn versions_within<'k>(start: Bound<&'k [u8]>, end: Bound<&'k [u8]>) -> VersionBounds<'k> {
+    let newest = |key| LookupKey::new(key, MAX_SEQUENCE, ValueType::FOR_SEEK);
+    let oldest = |key| LookupKey::new(key, 0, ValueType::Deletion);
+    let start = match start {
+        Bound::Included(key) => Bound::Included(newest(key)),
+        Bound::Excluded(key) => Bound::Excluded(oldest(key)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let end = match end {
+        Bound::Included(key) => Bound::Included(oldest(key)),
+        Bound::Excluded(key) => Bound::Excluded(newest(key)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+/// Returns the number of bytes taken by a version.
+fn version_size(key: &[u8], value: &[u8]) -> usize {
+    key.len() + value.len()
+}
+
+/// The value of a version, with the time it expires at.
+struct Version {
+    /// The value, in the arena of the memtable.
+    value: ArenaSlice,
+    /// The time the value expires at, in microseconds since the Unix epoch, or 0 if it never does.
+    expires_at: u64,
+}
+
+impl Version {
+    /// Returns whether the value expired before `now`. A `now` of 0 ignores expiry.
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at != 0 && self.expires_at <= now
+    }
+}
+
+/// A range deletion, hiding the versions of the keys from `start`, inclusive,
+/// to `end`, exclusive, written before `sequence`.
+struct RangeTombstone {
+    start: Bytes,
+    end: Bytes,
+    sequence: u64,
+}
+
+impl RangeTombstone {
+    fn covers(&self, key: &[u8]) -> bool {
+        self.start.as_slice() <= key && key < self.end.as_slice()
+    }
+
+    /// Returns whether the range of `other` is within the range of `self`.
+    fn covers_range(&self, other: &RangeTombstone) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+}
+
+/// Returns the sequence number of the newest range tombstone covering `key` as
+/// of `sequence`, or 0 if there is none.
+fn covering_sequence(range_tombstones: &[RangeTombstone], key: &[u8], sequence: u64) -> u64 {
+    range_tombstones
+        .iter()
+        .filter(|tombstone| tombstone.sequence <= sequence && tombstone.covers(key))
+        .map(|tombstone| tombstone.sequence)
+        .max()
+        .unwrap_or(0)
+}
+
 pub struct Iter<'a> {
-    it: std::collections::btree_map::Range<'a, TinyVec<[u8; 16]>, TinyVec<[u8; 16]>>,
+    it: btree_map::Range<'a, InternalKey, Version>,
+    arena: &'a Arena,
+    range_tombstones: &'a [RangeTombstone],
+    sequence: u64,
+    /// The time the iterator was created at, to skip the expired values.
+    now: u64,
+    /// The last key whose visible version was reached, so its older versions are skipped.
+    last_key: Option<&'a [u8]>,
 }
 
 impl<'a> Iterator for Iter<'a> {
     type Item = (&'a [u8], &'a [u8]);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.it.next().map(|(k, v)| (k.as_slice(), v.as_slice()))
+        loop {
+            let (key, version) = self.it.next()?;
+            let user_key = key.user_key();
+            if key.sequence() > self.sequence || self.last_key == Some(user_key) {
+                continue;
+            }
+            self.last_key = Some(user_key);
+            if key.value_type() == ValueType::Value
+                && covering_sequence(self.range_tombstones, user_key, self.sequence)
+                    < key.sequence()
+                && !version.is_expired(self.now)
+            {
+                return Some((user_key, self.arena.get(version.value)));
+            }
+        }
     }
 }
 
-// Single Threaded BTree Memtable
+/// Single Threaded BTree Memtable keeping versions of each key.
+///
+/// Every write adds a version of its key under an internal key carrying its
+/// sequence number and value type, and a delete adds a tombstone. A range
+/// deletion adds a range tombstone hiding the older versions of the keys it
+/// covers. A read at a sequence number sees the newest version written at or
+/// before it. Older versions are only kept while a snapshot can still see them.
+///
+/// The newest version of a key is kept even if it is a tombstone, and so are
+/// range tombstones, as they will have to hide the older versions of the keys
+/// in the SSTs the memtable is flushed on top of. A range tombstone is only
+/// dropped once a newer one covers all of its range.
+///
+/// Values may expire, after which reads skip them as if they were deleted. The
+/// expired values are still counted as live keys, so that the count does not
+/// depend on when the memtable is read.
 pub struct Memtable {
-    table: BTreeMap<TinyVec<[u8; 16]>, TinyVec<[u8; 16]>>,
+    table: BTreeMap<InternalKey, Version>,
+    /// The range tombstones not hidden by a newer one, oldest first.
+    range_tombstones: Vec<RangeTombstone>,
+    /// The sequence numbers of the open snapshots, with how many are open at each.
+    snapshots: BTreeMap<u64, usize>,
+    /// The keys with older versions kept for snapshots, to prune once those are released.
+    stale: BTreeSet<Bytes>,
+    /// Whether range tombstones hidden by a newer one are kept for snapshots,
+    /// to prune once those are released.
+    stale_range_tombstones: bool,
+    /// The number of keys whose newest version is not a tombstone.
+    live: usize,
+    /// The number of bytes of the keys and values of all the versions in the
+    /// table, and of the bounds of the range tombstones.
+    size: usize,
+    /// The number of bytes of the user keys of the versions in the table.
+    key_size: usize,
+    /// The values of the versions in the table.
+    arena: Arena,
+    /// The number of bytes of the values pruned from the table since the arena was created.
+    garbage: usize,
+    /// The sequence number of the newest version or range tombstone added.
+    last_sequence: u64,
 }
 
 impl Memtable {
     pub fn new() -> Memtable {
         Memtable {
             table: BTreeMap::new(),
+            range_tombstones: Vec::new(),
+            snapshots: BTreeMap::new(),
+            stale: BTreeSet::new(),
+            stale_range_tombstones: false,
+            live: 0,
+            size: 0,
+            key_size: 0,
+            arena: Arena::new(),
+            garbage: 0,
+            last_sequence: 0,
+        }
+    }
+
+    /// Adds the version of `key` written at `sequence`, where a `None` value is a delete.
+    pub fn put(&mut self, key: &[u8], sequence: u64, value: Option<&[u8]>) {
+        self.put_with_expiry(key, sequence, value, 0);
+    }
+
+    /// Adds the version of `key` written at `sequence` like `put`, with a value
+    /// that expires at `expires_at`, in microseconds since the Unix epoch, or
+    /// never if it is 0.
+    pub fn put_with_expiry(
+        &mut self,
+        key: &[u8],
+        sequence: u64,
+        value: Option<&[u8]>,
+        expires_at: u64,
+    ) {
+        let value_type = match value {
+            Some(_) => ValueType::Value,
+            None => ValueType::Deletion,
+        };
+        self.add_version(
+            key,
+            sequence,
+            value_type,
+            value.unwrap_or_default(),
+            expires_at,
+        );
+    }
+
+    /// Deletes `key` at `sequence` like a `put` of `None`, on the promise that
+    /// it was written at most once since it was last deleted.
+    pub fn single_delete(&mut self, key: &[u8], sequence: u64) {
+        self.add_version(key, sequence, ValueType::SingleDeletion, &[], 0);
+    }
+
+    fn add_version(
+        &mut self,
+        key: &[u8],
+        sequence: u64,
+        value_type: ValueType,
+        value: &[u8],
+        expires_at: u64,
+    ) {
+        self.last_sequence = self.last_sequence.max(sequence);
+        let was_live = self.visible_version(key, MAX_SEQUENCE).is_some();
+        match (was_live, value_type == ValueType::Value) {
+            (false, true) => self.live += 1,
+            (true, false) => self.live -= 1,
+            _ => {}
+        }
+        self.size += version_size(key, value);
+        self.key_size += key.len();
+        self.table.insert(
+            InternalKey::new(key, sequence, value_type),
+            Version {
+                value: self.arena.alloc(value),
+                expires_at,
+            },
+        );
+        self.prune(key);
+    }
+
+    /// Deletes the keys from `start`, inclusive, to `end`, exclusive, at `sequence`.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8], sequence: u64) {
+        self.last_sequence = self.last_sequence.max(sequence);
+        let bounds = (Bound::Included(start), Bound::Excluded(end));
+        if crate::keys::is_empty_range(bounds.0, bounds.1) {
+            return;
+        }
+        self.live -= self.scan_with_expired(bounds.0, bounds.1).count();
+        let mut keys: Vec<Bytes> = Vec::new();
+        for key in range(&self.table, versions_within(bounds.0, bounds.1)).map(|(key, _)| key) {
+            if keys.last().map(Bytes::as_slice) != Some(key.user_key()) {
+                keys.push(Bytes::from(key.user_key()));
+            }
+        }
+        self.size += start.len() + end.len();
+        let tombstone = RangeTombstone {
+            start: Bytes::from(start),
+            end: Bytes::from(end),
+            sequence,
+        };
+        // Only the new tombstone can hide the older ones, so they need not be
+        // compared with each other again.
+        let snapshots = &self.snapshots;
+        let mut size = self.size;
+        let mut stale = false;
+        self.range_tombstones.retain(|older| {
+            if !tombstone.covers_range(older) {
+                return true;
+            }
+            if snapshots.range(older.sequence..sequence).next().is_some() {
+                stale = true;
+                return true;
+            }
+            size -= older.start.len() + older.end.len();
+            false
+        });
+        self.size = size;
+        self.stale_range_tombstones |= stale;
+        self.range_tombstones.push(tombstone);
+        for key in keys {
+            self.prune(&key);
+        }
+    }
+
+    /// Drops the range tombstones hidden by a newer one covering all of their
+    /// range, unless a snapshot was taken in between.
+    fn prune_range_tombstones(&mut self) {
+        self.stale_range_tombstones = false;
+        // Newest first
+        let mut kept: Vec<RangeTombstone> = Vec::with_capacity(self.range_tombstones.len());
+        for tombstone in mem::take(&mut self.range_tombstones).into_iter().rev() {
+            // The oldest newer tombstone covering it leaves the least room for a snapshot
+            let newer = kept
+                .iter()
+                .rev()
+                .find(|newer| newer.covers_range(&tombstone));
+            match newer {
+                Some(newer)
+                    if self
+                        .snapshots
+                        .range(tombstone.sequence..newer.sequence)
+                        .next()
+                        .is_none() =>
+                {
+                    self.size -= tombstone.start.len() + tombstone.end.len();
+                }
+                Some(_) => {
+                    self.stale_range_tombstones = true;
+                    kept.push(tombstone);
+                }
+                None => kept.push(tombstone),
+            }
+        }
+        kept.reverse();
+        self.range_tombstones = kept;
+    }
+
+    /// Drops the versions of `key` that no read can see anymore.
+    ///
+    /// A version is visible from its sequence number until the next version of
+    /// the key or the next range tombstone covering it, so older versions are
+    /// only kept if a snapshot was taken in between. Tombstones are kept like
+    /// values, except for a single deletion meeting the value it deletes.
+    fn prune(&mut self, key: &[u8]) {
+        // The versions newest first, with whether they are in the table rather
+        // than range tombstones
+        let mut versions: Vec<(u64, ValueType, bool)> =
+            range(&self.table, versions_at(key, MAX_SEQUENCE))
+                .map(|(key, _)| (key.sequence(), key.value_type(), true))
+                .chain(
+                    self.range_tombstones
+                        .iter()
+                        .filter(|tombstone| tombstone.covers(key))
+                        .map(|tombstone| (tombstone.sequence, ValueType::Deletion, false)),
+                )
+                .collect();
+        versions.sort_by_key(|version| Reverse(version.0));
+        let mut kept = Vec::with_capacity(versions.len());
+        let mut doomed = Vec::new();
+        let mut newer = None;
+        for &version in &versions {
+            let sequence = version.0;
+            match newer {
+                Some(newer) if self.snapshots.range(sequence..newer).next().is_none() => {
+                    doomed.push(version)
+                }
+                _ => kept.push(version),
+            }
+            newer = Some(sequence);
+        }
+        // The key was written once since it was last deleted, so once the value
+        // is dropped nothing older can reappear without the single deletion.
+        if let Some(&(sequence, ValueType::SingleDeletion, true)) = kept.last() {
+            let older = versions.iter().find(|version| version.0 < sequence);
+            if let Some((_, ValueType::Value, true)) = older {
+                doomed.extend(kept.pop());
+            }
+        }
+
+        for (sequence, value_type, in_table) in doomed {
+            if !in_table {
+                continue;
+            }
+            let lookup = LookupKey::new(key, sequence, value_type);
+            if let Some(version) = self.table.remove(&lookup as &dyn InternalKeyRef) {
+                self.size -= version_size(key, self.arena.get(version.value));
+                self.key_size -= key.len();
+                self.garbage += version.value.len();
+            }
         }
+        if kept.len() > 1 {
+            self.stale.insert(Bytes::from(key));
+        } else {
+            self.stale.remove(key);
+        }
+        self.reclaim_arena();
     }
 
-    pub fn insert_or_update(&mut self, key: &[u8], value: &[u8]) {
-        self.table
-            .insert(tinyvec::TinyVec::from(key), tinyvec::TinyVec::from(value));
+    /// Copies the values still in the table to a new arena once more than half
+    /// of the current one holds pruned values, so that overwriting keys for a
+    /// long time does not grow the memtable without bound.
+    fn reclaim_arena(&mut self) {
+        if self.garbage <= MIN_ARENA_GARBAGE.max(self.arena.memory_usage() / 2) {
+            return;
+        }
+        let mut arena = Arena::new();
+        for version in self.table.values_mut() {
+            version.value = arena.alloc(self.arena.get(version.value));
+        }
+        self.arena = arena;
+        self.garbage = 0;
     }
 
+    /// Returns the newest value of `key`, or `None` if it does not exist.
     pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
-        // TODO: avoid copying the key to construct the TinyVec
-        self.table
-            .get(&tinyvec::TinyVec::from(key))
-            .map(|v| v.as_slice())
+        self.get_at(key, u64::MAX)
+    }
+
+    /// Returns the value of `key` as of `sequence`, or `None` if it did not exist
+    /// then or expired since.
+    pub fn get_at(&self, key: &[u8], sequence: u64) -> Option<&[u8]> {
+        self.visible_version(key, sequence)
+            .filter(|version| !version.is_expired(now_micros()))
+            .map(|version| self.arena.get(version.value))
+    }
+
+    /// Returns whether `key` has an unexpired value, without looking at it.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the newest value of `key` with the time it expires at, or 0 if
+    /// it never does, even if it already expired.
+    pub fn get_with_expiry(&self, key: &[u8]) -> Option<(&[u8], u64)> {
+        self.visible_version(key, MAX_SEQUENCE)
+            .map(|version| (self.arena.get(version.value), version.expires_at))
+    }
+
+    /// Returns the version of `key` holding its value as of `sequence`,
+    /// expired or not, or `None` if the key did not exist then.
+    fn visible_version(&self, key: &[u8], sequence: u64) -> Option<&Version> {
+        range(&self.table, versions_at(key, sequence))
+            .next()
+            .filter(|(version, _)| {
+                version.value_type() == ValueType::Value
+                    && covering_sequence(&self.range_tombstones, key, sequence) < version.sequence()
+            })
+            .map(|(_, version)| version)
     }
 
-    pub fn delete(&mut self, key: &[u8]) -> bool {
-        // TODO: avoid copying the key to construct the TinyVec
-        self.table.remove(key).is_some()
+    /// Keeps the versions visible at `sequence` until `release_snapshot` is
+    /// called with the same sequence number.
+    pub fn add_snapshot(&mut self, sequence: u64) {
+        *self.snapshots.entry(sequence).or_default() += 1;
     }
 
-    pub fn scan(&self, start: &[u8], end: &[u8]) -> Iter {
-        // TODO: avoid copying the key to construct the TinyVec
+    /// Releases a snapshot added by `add_snapshot`, dropping the versions only it could see.
+    pub fn release_snapshot(&mut self, sequence: u64) {
+        if let btree_map::Entry::Occupied(mut entry) = self.snapshots.entry(sequence) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+        for key in std::mem::take(&mut self.stale) {
+            self.prune(&key);
+        }
+        if self.stale_range_tombstones {
+            self.prune_range_tombstones();
+        }
+    }
+
+    /// Returns the sequence number of the newest write added, or 0 if none was.
+    ///
+    /// A write batch is added under a single lock, so every version up to it
+    /// belongs to a batch that was added in full.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Returns the number of live keys in the memtable, including the expired ones.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    /// Returns the number of bytes of the keys and values in the memtable,
+    /// including the older versions kept for snapshots.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns an estimate of the number of bytes of memory taken by the
+    /// memtable: the keys of the versions and their entries in the table, the
+    /// blocks of the arena holding the values, and the range tombstones.
+    ///
+    /// Unlike `size`, this counts the space the arena allocated but did not
+    /// hand out yet, and the values pruned from the table since its last reclaim.
+    pub fn approximate_memory_usage(&self) -> usize {
+        let range_tombstones: usize = self
+            .range_tombstones
+            .iter()
+            .map(|tombstone| {
+                mem::size_of::<RangeTombstone>() + tombstone.start.len() + tombstone.end.len()
+            })
+            .sum();
+        self.key_size
+            + self.table.len() * ENTRY_OVERHEAD
+            + self.arena.memory_usage()
+            + range_tombstones
+    }
+
+    /// Returns the number of bytes taken by the keys and values within the bounds.
+    pub fn range_size(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> u64 {
+        self.scan_bounds(start, end)
+            .map(|(key, value)| (key.len() + value.len()) as u64)
+            .sum()
+    }
+
+    pub fn scan_bounds(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Iter<'_> {
+        self.scan_at(start, end, u64::MAX)
+    }
+
+    /// Returns an iterator over the keys within the bounds, with their values as of `sequence`.
+    pub fn scan_at(&self, start: Bound<&[u8]>, end: Bound<&[u8]>, sequence: u64) -> Iter<'_> {
+        self.iter(start, end, sequence, now_micros())
+    }
+
+    /// Returns an iterator over the keys within the bounds, with their values
+    /// as of `sequence`, from the last key to the first.
+    pub fn scan_rev_at<'a>(
+        &'a self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        sequence: u64,
+    ) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + 'a {
+        let now = now_micros();
+        let mut last_key = None;
+        // The versions of a key come oldest first, so each key is looked up
+        // again for the version visible at `sequence`.
+        range(&self.table, versions_within(start, end))
+            .rev()
+            .filter_map(move |(key, _)| {
+                let user_key = key.user_key();
+                if last_key == Some(user_key) {
+                    return None;
+                }
+                last_key = Some(user_key);
+                self.visible_version(user_key, sequence)
+                    .filter(|version| !version.is_expired(now))
+                    .map(|version| (user_key, self.arena.get(version.value)))
+            })
+    }
+
+    /// Returns an iterator over the keys within the bounds like `scan_bounds`,
+    /// including the keys whose values expired.
+    pub fn scan_with_expired(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Iter<'_> {
+        self.iter(start, end, MAX_SEQUENCE, 0)
+    }
+
+    fn iter(&self, start: Bound<&[u8]>, end: Bound<&[u8]>, sequence: u64, now: u64) -> Iter<'_> {
         Iter {
-            it: self
-                .table
-                .range(tinyvec::TinyVec::from(start)..tinyvec::TinyVec::from(end)),
+            it: range(&self.table, versions_within(start, end)),
+            arena: &self.arena,
+            range_tombstones: &self.range_tombstones,
+            sequence,
+            now,
+            last_key: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use super::Memtable;
+
+    #[test]
+    fn insert_get_delete() {
+        let mut memtable = Memtable::new();
+        assert_eq!(memtable.get(b"key"), None);
+
+        memtable.put(b"key", 1, Some(b"value"));
+        assert_eq!(memtable.get(b"key"), Some(&b"value"[..]));
+        assert_eq!(memtable.size(), 8);
+        assert_eq!(
+            memtable.approximate_memory_usage(),
+            3 + super::ENTRY_OVERHEAD + memtable.arena.memory_usage()
+        );
+
+        let large_value = [1u8; 64];
+        memtable.put(b"key", 2, Some(&large_value));
+        assert_eq!(memtable.get(b"key"), Some(&large_value[..]));
+        assert_eq!(memtable.size(), 67);
+        assert_eq!(memtable.len(), 1);
+
+        // Only the newest tombstone is kept
+        memtable.put(b"key", 3, None);
+        memtable.put(b"key", 4, None);
+        assert_eq!(memtable.get(b"key"), None);
+        assert_eq!(memtable.table.len(), 1);
+        assert_eq!(memtable.size(), 3);
+        assert_eq!(memtable.len(), 0);
+        assert_eq!(
+            memtable.approximate_memory_usage(),
+            3 + super::ENTRY_OVERHEAD + memtable.arena.memory_usage()
+        );
+    }
+
+    fn at(memtable: &Memtable, sequence: u64) -> Vec<(&[u8], &[u8])> {
+        memtable
+            .scan_at(Bound::Unbounded, Bound::Unbounded, sequence)
+            .collect()
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut memtable = Memtable::new();
+        memtable.put(b"a", 1, Some(b"1"));
+        memtable.put(b"b", 2, Some(b"2"));
+        memtable.add_snapshot(2);
+        memtable.put(b"a", 3, Some(b"3"));
+        memtable.put(b"b", 4, None);
+        memtable.put(b"c", 5, Some(b"5"));
+        memtable.put(b"a", 6, Some(b"6"));
+
+        // The snapshot sees the versions as of its sequence number
+        assert_eq!(memtable.get_at(b"a", 2), Some(&b"1"[..]));
+        assert_eq!(memtable.get_at(b"b", 2), Some(&b"2"[..]));
+        assert_eq!(memtable.get_at(b"c", 2), None);
+        assert_eq!(at(&memtable, 2), [(&b"a"[..], &b"1"[..]), (b"b", b"2")]);
+        assert_eq!(
+            at(&memtable, u64::MAX),
+            [(&b"a"[..], &b"6"[..]), (b"c", b"5")]
+        );
+        assert_eq!(memtable.len(), 2);
+
+        // Only the versions the snapshot can see were kept, and they are
+        // dropped once it is released, except for the tombstone of `b`
+        assert_eq!(memtable.table.len(), 5);
+        memtable.release_snapshot(2);
+        assert_eq!(memtable.table.len(), 3);
+        assert_eq!(memtable.size(), 5);
+        assert_eq!(
+            at(&memtable, u64::MAX),
+            [(&b"a"[..], &b"6"[..]), (b"c", b"5")]
+        );
+    }
+
+    #[test]
+    fn scan() {
+        let mut memtable = Memtable::new();
+        for i in 0..10u8 {
+            memtable.put(&[i], u64::from(i) + 1, Some(&[i * 2]));
+        }
+
+        let result: Vec<(&[u8], &[u8])> = memtable
+            .scan_bounds(Bound::Included(&[2]), Bound::Excluded(&[5]))
+            .collect();
+        assert_eq!(
+            result,
+            vec![(&[2u8][..], &[4u8][..]), (&[3], &[6]), (&[4], &[8])]
+        );
+        let result: Vec<(&[u8], &[u8])> = memtable
+            .scan_bounds(Bound::Excluded(&[7]), Bound::Included(&[8]))
+            .collect();
+        assert_eq!(result, vec![(&[8u8][..], &[16u8][..])]);
+        assert_eq!(
+            memtable.range_size(Bound::Included(&[2]), Bound::Excluded(&[5])),
+            6
+        );
+        assert_eq!(memtable.range_size(Bound::Unbounded, Bound::Unbounded), 20);
+    }
+
+    #[test]
+    fn delete_range() {
+        let mut memtable = Memtable::new();
+        for (i, key) in [b"a", b"b", b"c", b"d"].into_iter().enumerate() {
+            memtable.put(key, i as u64 + 1, Some(b"1"));
+        }
+        memtable.add_snapshot(4);
+        memtable.delete_range(b"b", b"d", 5);
+        memtable.put(b"c", 6, Some(b"2"));
+
+        assert_eq!(memtable.get(b"b"), None);
+        assert_eq!(memtable.get(b"c"), Some(&b"2"[..]));
+        assert_eq!(memtable.get_at(b"b", 4), Some(&b"1"[..]));
+        assert_eq!(
+            at(&memtable, u64::MAX),
+            [(&b"a"[..], &b"1"[..]), (b"c", b"2"), (b"d", b"1")]
+        );
+        assert_eq!(at(&memtable, 5), [(&b"a"[..], &b"1"[..]), (b"d", b"1")]);
+        assert_eq!(at(&memtable, 4).len(), 4);
+        assert_eq!(memtable.len(), 3);
+
+        // The covered versions are dropped with the snapshot, but not the tombstone
+        memtable.release_snapshot(4);
+        assert_eq!(memtable.table.len(), 3);
+        assert_eq!(memtable.range_tombstones.len(), 1);
+        assert_eq!(memtable.size(), 8);
+
+        // An inverted range deletes nothing
+        memtable.delete_range(b"z", b"a", 7);
+        assert_eq!(memtable.len(), 3);
+        assert_eq!(memtable.range_tombstones.len(), 1);
+    }
+
+    #[test]
+    fn range_tombstones_hidden_by_newer_ones() {
+        let mut memtable = Memtable::new();
+        memtable.put(b"b", 1, Some(b"1"));
+
+        // Deleting the same range again replaces the tombstone
+        memtable.delete_range(b"a", b"c", 2);
+        memtable.delete_range(b"a", b"c", 3);
+        assert_eq!(memtable.range_tombstones.len(), 1);
+        assert_eq!(memtable.size(), 2);
+
+        // A narrower tombstone hides only part of the older one
+        memtable.delete_range(b"b", b"c", 4);
+        assert_eq!(memtable.range_tombstones.len(), 2);
+
+        // A snapshot in between keeps the older tombstones until it is released
+        memtable.add_snapshot(4);
+        memtable.put(b"b", 5, Some(b"2"));
+        memtable.delete_range(b"a", b"d", 6);
+        assert_eq!(memtable.range_tombstones.len(), 3);
+        assert_eq!(memtable.get_at(b"b", 4), None);
+        assert_eq!(memtable.get(b"b"), None);
+        memtable.release_snapshot(4);
+        assert_eq!(memtable.range_tombstones.len(), 1);
+        assert_eq!(memtable.size(), 2);
+        assert_eq!(memtable.get(b"b"), None);
+    }
+
+    #[test]
+    fn single_delete() {
+        let mut memtable = Memtable::new();
+        memtable.put(b"a", 1, Some(b"1"));
+        memtable.add_snapshot(1);
+        memtable.single_delete(b"a", 2);
+
+        assert_eq!(memtable.get(b"a"), None);
+        assert_eq!(memtable.get_at(b"a", 1), Some(&b"1"[..]));
+        assert_eq!(at(&memtable, u64::MAX), []);
+        assert_eq!(memtable.len(), 0);
+
+        // The tombstone and the value it deletes are dropped together
+        memtable.release_snapshot(1);
+        assert!(memtable.table.is_empty());
+        assert_eq!(memtable.size(), 0);
+
+        // Without the value, the tombstone is kept to hide it in the SSTs
+        memtable.single_delete(b"b", 3);
+        assert_eq!(memtable.table.len(), 1);
+    }
+
+    #[test]
+    fn reclaim_arena() {
+        let mut memtable = Memtable::new();
+        let value = [1u8; 100];
+        for sequence in 1..=10_000 {
+            memtable.put(b"key", sequence, Some(&value));
+        }
+        assert_eq!(memtable.get(b"key"), Some(&value[..]));
+        assert_eq!(memtable.size(), 103);
+        // The overwritten values were dropped along with their arena
+        assert!(memtable.arena.memory_usage() < 2 * super::MIN_ARENA_GARBAGE);
+    }
+
+    #[test]
+    fn expiry() {
+        let mut memtable = Memtable::new();
+        memtable.put_with_expiry(b"a", 1, Some(b"1"), 1);
+        memtable.put_with_expiry(b"b", 2, Some(b"2"), u64::MAX);
+        memtable.put(b"c", 3, Some(b"3"));
+
+        assert_eq!(memtable.get(b"a"), None);
+        assert_eq!(memtable.get(b"b"), Some(&b"2"[..]));
+        assert_eq!(memtable.get_with_expiry(b"a"), Some((&b"1"[..], 1)));
+        assert_eq!(
+            at(&memtable, u64::MAX),
+            [(&b"b"[..], &b"2"[..]), (b"c", b"3")]
+        );
+        let keys: Vec<&[u8]> = memtable
+            .scan_with_expired(Bound::Unbounded, Bound::Unbounded)
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, [&b"a"[..], b"b", b"c"]);
+
+        // Expired keys still count as live, until they are deleted
+        assert_eq!(memtable.len(), 3);
+        memtable.delete_range(b"a", b"b", 4);
+        assert_eq!(memtable.len(), 2);
+        memtable.put(b"a", 5, Some(b"5"));
+        assert_eq!(memtable.get(b"a"), Some(&b"5"[..]));
+        assert_eq!(memtable.len(), 3);
+    }
+}
@@ -0,0 +1,143 @@
+//! This module provides the metrics the engine collects about its I/O.
+//!
+//! Metrics are updated with relaxed atomics from the threads doing the work and
+//! can be read at any time through `DB::metrics`.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// The number of buckets of a `Histogram`. Bucket `i` counts the samples in
+/// `[2^(i-1), 2^i)` microseconds, and the last bucket also counts everything above.
+const NUM_BUCKETS: usize = 32;
+
+/// A lock-free histogram of latencies with power-of-two microsecond buckets.
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; NUM_BUCKETS],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Histogram {
+    /// Records a sample.
+    pub fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(NUM_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Returns the number of samples recorded.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the mean of the samples, or zero if there are none.
+    pub fn mean(&self) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed) / count)
+    }
+
+    /// Returns the largest sample recorded.
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_micros.load(Ordering::Relaxed))
+    }
+
+    /// Returns an upper bound of the `p`-th percentile of the samples, where `p` is in `[0, 100]`.
+    ///
+    /// The bound is the upper edge of the bucket containing the percentile,
+    /// capped by the largest sample recorded.
+    pub fn percentile(&self, p: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((p / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, samples) in self.buckets.iter().enumerate() {
+            seen += samples.load(Ordering::Relaxed);
+            if seen >= rank {
+                let upper_micros = (1u64 << bucket) - 1;
+                return Duration::from_micros(upper_micros).min(self.max());
+            }
+        }
+        self.max()
+    }
+}
+
+/// A snapshot of the counters of the WAL writer of a DB, since the DB was opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WalStats {
+    /// The number of records written. A batch spanning several blocks is
+    /// written as several records.
+    pub records_written: u64,
+    /// The number of bytes written, including record headers and padding.
+    pub bytes_written: u64,
+    /// The number of bytes written to pad the end of blocks.
+    pub padding_bytes: u64,
+    /// The number of blocks of the WAL that hold data, including those
+    /// written before the DB was opened.
+    pub blocks_used: u64,
+    /// The number of fsyncs of the WAL, including background syncs.
+    pub fsync_count: u64,
+}
+
+/// The metrics of a DB.
+#[derive(Default)]
+pub struct Metrics {
+    fsync_count: AtomicU64,
+    fsync_latency: Histogram,
+}
+
+impl Metrics {
+    /// Records an fsync that took `latency`.
+    pub(crate) fn record_fsync(&self, latency: Duration) {
+        self.fsync_count.fetch_add(1, Ordering::Relaxed);
+        self.fsync_latency.record(latency);
+    }
+
+    /// Returns the number of fsyncs issued.
+    pub fn fsync_count(&self) -> u64 {
+        self.fsync_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the distribution of fsync latencies.
+    pub fn fsync_latency(&self) -> &Histogram {
+        &self.fsync_latency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), Duration::ZERO);
+        assert_eq!(histogram.mean(), Duration::ZERO);
+
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        histogram.record(Duration::from_secs(1));
+
+        assert_eq!(histogram.count(), 101);
+        assert_eq!(histogram.max(), Duration::from_secs(1));
+        assert_eq!(
+            histogram.mean(),
+            Duration::from_micros((5050 + 1_000_000) / 101)
+        );
+        // The median (51us) falls in the [32, 64) bucket
+        assert_eq!(histogram.percentile(50.0), Duration::from_micros(63));
+        assert_eq!(histogram.percentile(100.0), Duration::from_secs(1));
+    }
+}
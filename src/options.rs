@@ -0,0 +1,272 @@
+//! This module provides the options of a DB and of individual DB operations.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event_listener::EventListener, log_record::DEFAULT_BLOCK_SIZE, snapshot::Snapshot,
+    write_batch::CompressionType,
+};
+
+/// Options for a single write.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// The maximum time the write may take before it fails with `Error::TimedOut`,
+    /// or `None` to wait indefinitely.
+    ///
+    /// A write that times out is not applied at all: the deadline is only
+    /// checked before the batch is committed to the WAL.
+    pub timeout: Option<Duration>,
+    /// Whether to fsync the WAL before the write returns.
+    ///
+    /// Unsynced writes are handed to the OS and survive a crash of the process,
+    /// but not of the machine, unless the WAL is synced in the background (see
+    /// `Options::wal_sync_interval`).
+    pub sync: bool,
+    /// An identifier attached to the tracing spans of the write, to correlate
+    /// them with the caller's own traces. Defaults to the sequence number of
+    /// the first entry of the batch.
+    pub correlation_id: Option<u64>,
+    /// Whether to apply the write to the memtable only, without appending it
+    /// to the WAL, e.g. for bulk loads that can be redone after a crash.
+    ///
+    /// Such writes are lost on a crash until `DB::flush` is called or the DB is
+    /// closed. They cannot be combined with `sync`.
+    pub disable_wal: bool,
+}
+
+impl WriteOptions {
+    /// Sets the timeout of the write.
+    pub fn timeout(mut self, timeout: Duration) -> WriteOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether to fsync the WAL before the write returns.
+    pub fn sync(mut self, sync: bool) -> WriteOptions {
+        self.sync = sync;
+        self
+    }
+
+    /// Sets whether to bypass the WAL.
+    pub fn disable_wal(mut self, disable_wal: bool) -> WriteOptions {
+        self.disable_wal = disable_wal;
+        self
+    }
+
+    /// Sets the identifier attached to the tracing spans of the write.
+    pub fn correlation_id(mut self, correlation_id: u64) -> WriteOptions {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
+    /// Returns the instant by which a write started at `start` must complete.
+    pub(crate) fn deadline(&self, start: Instant) -> Option<Instant> {
+        self.timeout.map(|timeout| start + timeout)
+    }
+}
+
+/// Options for a single read, see `DB::get_opt` and `DB::scan_opt`.
+#[derive(Clone, Debug, Default)]
+pub struct ReadOptions<'a> {
+    /// The smallest key a scan may return, on top of its own range.
+    pub iterate_lower_bound: Option<Vec<u8>>,
+    /// The key right after the last one a scan may return, on top of its own range.
+    pub iterate_upper_bound: Option<Vec<u8>>,
+    /// The snapshot to read as of, or `None` to read the latest data.
+    pub snapshot: Option<&'a Snapshot<'a>>,
+}
+
+impl<'a> ReadOptions<'a> {
+    /// Sets the smallest key a scan may return.
+    pub fn iterate_lower_bound(mut self, bound: &[u8]) -> ReadOptions<'a> {
+        self.iterate_lower_bound = Some(bound.to_vec());
+        self
+    }
+
+    /// Sets the key right after the last one a scan may return.
+    pub fn iterate_upper_bound(mut self, bound: &[u8]) -> ReadOptions<'a> {
+        self.iterate_upper_bound = Some(bound.to_vec());
+        self
+    }
+
+    /// Sets the snapshot to read as of.
+    pub fn snapshot(mut self, snapshot: &'a Snapshot<'a>) -> ReadOptions<'a> {
+        self.snapshot = Some(snapshot);
+        self
+    }
+}
+
+/// How to handle corruption found in the WAL when a DB is opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalRecoveryMode {
+    /// Fail on any corruption, including a batch cut short at the end of the WAL.
+    AbsoluteConsistency,
+    /// Drop corruption at the end of the WAL, as left by a crash in the middle
+    /// of an append, and fail on corruption anywhere else.
+    #[default]
+    TolerateCorruptedTail,
+    /// Stop replaying at the first corruption, recovering the DB as it was
+    /// right before it. The rest of the WAL is discarded.
+    PointInTime,
+    /// Skip corrupt records and batches, and replay everything else.
+    SkipAnyCorruption,
+}
+
+/// The checksum protecting the records of new WAL files.
+///
+/// The checksum is recorded in the header of each WAL, so existing WALs keep
+/// being read and appended to with the checksum they were created with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalChecksum {
+    /// CRC32C, which is the fastest on CPUs with hardware support for it.
+    #[default]
+    Crc32c,
+    /// xxHash64, which is fast on any CPU.
+    XxHash64,
+    /// CRC64.
+    Crc64,
+}
+
+/// How the WAL reserves disk space ahead of appends.
+///
+/// Reserving space in large chunks spares appends from updating the file system
+/// metadata on every extension and keeps the WAL contiguous on disk. The size of
+/// the WAL as seen by readers is not affected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalPreallocation {
+    /// The number of bytes reserved at a time.
+    pub size: u64,
+    /// Whether to also zero the reserved space, so the first writes to it don't
+    /// have to convert it from unwritten space. Not supported by every file system.
+    pub zero_fill: bool,
+}
+
+impl Default for WalPreallocation {
+    fn default() -> Self {
+        WalPreallocation {
+            size: 4 * 1024 * 1024,
+            zero_fill: false,
+        }
+    }
+}
+
+/// Options for opening a DB with `DB::open`.
+///
+/// ```ignore
+/// let options = Options::default()
+///     .wal_compression(CompressionType::Snappy)
+///     .wal_sync_interval(Some(Duration::from_millis(100)));
+/// let db = DB::open("path/to/db", options)?;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// How to handle corruption found in the WAL.
+    pub wal_recovery_mode: WalRecoveryMode,
+    /// The checksum protecting the records of new WAL files.
+    pub wal_checksum: WalChecksum,
+    /// The size of the blocks the WAL is divided into. It is recorded in the
+    /// OPTIONS file and cannot change over the lifetime of the DB.
+    pub block_size: usize,
+    /// The compression applied to large write batches appended to the WAL.
+    pub wal_compression: CompressionType,
+    /// How the WAL reserves disk space ahead of appends, or `None` to disable preallocation.
+    pub wal_preallocation: Option<WalPreallocation>,
+    /// How often to fsync the WAL in the background, or `None` to only sync it
+    /// on request.
+    pub wal_sync_interval: Option<Duration>,
+    /// Whether to collapse duplicate keys in write batches before they are committed.
+    pub dedup_write_batches: bool,
+    /// The maximum number of iterators that may be open at once, or `None` for no limit.
+    pub max_open_iterators: Option<usize>,
+    /// How long values live after they are written, or `None` for them to live
+    /// until they are overwritten or deleted. Reads skip the expired values.
+    ///
+    /// It applies to the values written before the DB was opened as well, but
+    /// not to those written by a DB without a TTL, which never expire. The TTL
+    /// of a write batch or value takes precedence.
+    pub ttl: Option<Duration>,
+    /// The listeners notified of the events of the DB, in order.
+    pub listeners: Vec<Arc<dyn EventListener>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            wal_recovery_mode: WalRecoveryMode::default(),
+            wal_checksum: WalChecksum::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            wal_compression: CompressionType::None,
+            wal_preallocation: Some(WalPreallocation::default()),
+            wal_sync_interval: None,
+            dedup_write_batches: false,
+            max_open_iterators: None,
+            ttl: None,
+            listeners: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Sets how to handle corruption found in the WAL.
+    pub fn wal_recovery_mode(mut self, mode: WalRecoveryMode) -> Options {
+        self.wal_recovery_mode = mode;
+        self
+    }
+
+    /// Sets the checksum protecting the records of new WAL files.
+    pub fn wal_checksum(mut self, checksum: WalChecksum) -> Options {
+        self.wal_checksum = checksum;
+        self
+    }
+
+    /// Sets the size of the blocks the WAL is divided into.
+    pub fn block_size(mut self, block_size: usize) -> Options {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Sets the compression applied to large write batches appended to the WAL.
+    pub fn wal_compression(mut self, compression: CompressionType) -> Options {
+        self.wal_compression = compression;
+        self
+    }
+
+    /// Sets how the WAL reserves disk space ahead of appends.
+    pub fn wal_preallocation(mut self, preallocation: Option<WalPreallocation>) -> Options {
+        self.wal_preallocation = preallocation;
+        self
+    }
+
+    /// Sets how often to fsync the WAL in the background.
+    pub fn wal_sync_interval(mut self, interval: Option<Duration>) -> Options {
+        self.wal_sync_interval = interval;
+        self
+    }
+
+    /// Sets whether to collapse duplicate keys in write batches.
+    pub fn dedup_write_batches(mut self, dedup: bool) -> Options {
+        self.dedup_write_batches = dedup;
+        self
+    }
+
+    /// Sets the maximum number of iterators that may be open at once.
+    pub fn max_open_iterators(mut self, max_open: Option<usize>) -> Options {
+        self.max_open_iterators = max_open;
+        self
+    }
+
+    /// Sets how long values live after they are written.
+    pub fn ttl(mut self, ttl: Option<Duration>) -> Options {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Adds a listener notified of the events of the DB.
+    pub fn listener(mut self, listener: Arc<dyn EventListener>) -> Options {
+        self.listeners.push(listener);
+        self
+    }
+}
@@ -0,0 +1,179 @@
+//! This module manages the OPTIONS file of a DB.
+//!
+//! The OPTIONS file records the options that determine how the data on disk is
+//! laid out and ordered. They are checked on every open, so that opening a DB
+//! with incompatible options fails with a clear error instead of misreading it.
+//! It also records the TTL the DB is open with, which may change from one open
+//! to the next, so that secondaries expire values like the primary does.
+//!
+//! The file is a list of `name=value` lines. Unknown names are ignored, which
+//! lets newer versions record additional options without breaking older ones.
+
+use std::{collections::HashMap, fmt::Display, fs, io, path::Path, time::Duration};
+
+use crate::{
+    error::{Error, Result},
+    log_record::DEFAULT_BLOCK_SIZE,
+    manifest::write_file_atomically,
+};
+
+/// The name of the OPTIONS file in the DB directory.
+pub const OPTIONS_FILE_NAME: &str = "OPTIONS";
+
+/// The name of the only comparator supported so far, which orders keys bytewise.
+pub const BYTEWISE_COMPARATOR: &str = "bytewise";
+
+/// The options recorded in the OPTIONS file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistedOptions {
+    /// The name of the comparator ordering the keys, which must not change.
+    pub comparator: String,
+    /// The size of the blocks the WAL is divided into, which must not change.
+    pub block_size: usize,
+    /// The TTL the DB is open with, see `Options::ttl`.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for PersistedOptions {
+    fn default() -> Self {
+        PersistedOptions {
+            comparator: BYTEWISE_COMPARATOR.to_string(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            ttl: None,
+        }
+    }
+}
+
+impl PersistedOptions {
+    fn encode(&self) -> String {
+        let mut encoded = format!(
+            "# mini-lsm OPTIONS file\ncomparator={}\nblock_size={}\n",
+            self.comparator, self.block_size
+        );
+        if let Some(ttl) = self.ttl {
+            encoded.push_str(&format!("ttl_micros={}\n", ttl.as_micros()));
+        }
+        encoded
+    }
+
+    fn decode(contents: &str) -> Result<PersistedOptions> {
+        let values: HashMap<&str, &str> = contents
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .collect();
+        let value = |name: &str| {
+            values
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::Corruption(format!("OPTIONS file is missing `{}`", name)))
+        };
+        let block_size = value("block_size")?;
+        // The TTL is only recorded if the DB has one
+        let ttl = match values.get("ttl_micros") {
+            Some(ttl_micros) => Some(Duration::from_micros(ttl_micros.parse().map_err(|_| {
+                Error::Corruption(format!(
+                    "invalid ttl_micros in OPTIONS file: {}",
+                    ttl_micros
+                ))
+            })?)),
+            None => None,
+        };
+        Ok(PersistedOptions {
+            comparator: value("comparator")?.to_string(),
+            block_size: block_size.parse().map_err(|_| {
+                Error::Corruption(format!(
+                    "invalid block_size in OPTIONS file: {}",
+                    block_size
+                ))
+            })?,
+            ttl,
+        })
+    }
+
+    /// Checks that a DB created with `self` can be opened with `other`.
+    fn check_compatible(&self, other: &PersistedOptions) -> Result<()> {
+        let mismatch = |name: &str, found: &dyn Display, expected: &dyn Display| {
+            Err(Error::IncompatibleOptions(format!(
+                "the DB was created with {}={} but is being opened with {}={}",
+                name, found, name, expected
+            )))
+        };
+        if self.comparator != other.comparator {
+            return mismatch("comparator", &self.comparator, &other.comparator);
+        }
+        if self.block_size != other.block_size {
+            return mismatch("block_size", &self.block_size, &other.block_size);
+        }
+        Ok(())
+    }
+}
+
+/// Reads the OPTIONS file at `path`, or returns the default options if there is none.
+pub fn load(path: &Path) -> Result<PersistedOptions> {
+    match fs::read_to_string(path) {
+        Ok(contents) => PersistedOptions::decode(&contents),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(PersistedOptions::default()),
+        Err(err) => Err(Error::Io(err)),
+    }
+}
+
+/// Validates `options` against the OPTIONS file at `path`, if there is one,
+/// and then persists `options` to it.
+///
+/// # Returns
+///
+/// Returns `Err(Error::IncompatibleOptions)` if the DB was created with options
+/// that `options` cannot read.
+pub fn check_and_persist(path: &Path, options: &PersistedOptions) -> Result<()> {
+    match fs::read_to_string(path) {
+        Ok(contents) => PersistedOptions::decode(&contents)?.check_compatible(options)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(Error::Io(err)),
+    }
+    write_file_atomically(path, options.encode().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn check_and_persist() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("OPTIONS");
+        let options = PersistedOptions::default();
+
+        super::check_and_persist(&path, &options).unwrap();
+        super::check_and_persist(&path, &options).unwrap();
+        assert_eq!(
+            PersistedOptions::decode(&fs::read_to_string(&path).unwrap()).unwrap(),
+            options
+        );
+
+        let other = PersistedOptions {
+            block_size: options.block_size * 2,
+            ..options.clone()
+        };
+        match super::check_and_persist(&path, &other) {
+            Err(Error::IncompatibleOptions(message)) => assert!(message.contains("block_size")),
+            _ => panic!("Expected IncompatibleOptions error"),
+        }
+
+        // The TTL may change from one open to the next
+        let other = PersistedOptions {
+            ttl: Some(Duration::from_millis(1500)),
+            ..options.clone()
+        };
+        super::check_and_persist(&path, &other).unwrap();
+        assert_eq!(load(&path).unwrap(), other);
+        super::check_and_persist(&path, &options).unwrap();
+        assert_eq!(load(&path).unwrap().ttl, None);
+
+        // Unknown options written by newer versions are ignored
+        fs::write(&path, format!("{}future_option=1\n", options.encode())).unwrap();
+        super::check_and_persist(&path, &options).unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+//! The mini-lsm prelude.
+//!
+//! Re-exports the types needed by most users of the crate, so they can be
+//! imported at once:
+//!
+//! ```ignore
+//! use mini_lsm::prelude::*;
+//!
+//! let db = DB::new("path/to/db")?;
+//! let mut wb = WriteBatch::new();
+//! wb.insert_or_update(b"key", b"value");
+//! db.write(&wb)?;
+//! ```
+//!
+//! `Result` is deliberately not part of the prelude to avoid shadowing
+//! `std::result::Result`; use `mini_lsm::Result` instead.
+
+pub use crate::{
+    backup::{BackupEngine, BackupInfo},
+    db_iterator::DBIterator,
+    error::Error,
+    event_listener::EventListener,
+    options::{Options, ReadOptions, WalChecksum, WalPreallocation, WalRecoveryMode, WriteOptions},
+    scoped::{ScopedDB, ScopedIter},
+    secondary::SecondaryDB,
+    snapshot::Snapshot,
+    write_batch::{BatchMetadata, CompressionType, WriteBatch},
+    CompareAndSwapError, DbState, Iter, ScanPage, DB,
+};
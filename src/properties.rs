@@ -0,0 +1,31 @@
+//! This module lists the properties exposed by `DB::get_property`.
+//!
+//! Properties are introspection values meant for monitoring and debugging.
+//! Their values are returned as strings, so that properties which are not
+//! numbers can be added later without changing the API.
+
+/// The estimated number of keys in the DB.
+///
+/// It counts the keys whose newest version is a value, including the values
+/// whose TTL expired, until they are overwritten or deleted.
+pub const ESTIMATE_NUM_KEYS: &str = "mini-lsm.estimate-num-keys";
+
+/// The number of bytes of the keys and values held by the memtable.
+pub const CUR_SIZE_ALL_MEM_TABLES: &str = "mini-lsm.cur-size-all-mem-tables";
+
+/// The approximate number of bytes of memory taken by the memtable, including
+/// its internal overhead and the space it allocated ahead.
+pub const SIZE_ALL_MEM_TABLES: &str = "mini-lsm.size-all-mem-tables";
+
+/// The number of SST files at level `N`, queried as e.g. `mini-lsm.num-files-at-level0`.
+///
+/// The memtable is not flushed to SST files yet, so it is not available.
+pub const NUM_FILES_AT_LEVEL_PREFIX: &str = "mini-lsm.num-files-at-level";
+
+/// The total size in bytes of the SST files of the DB.
+///
+/// The memtable is not flushed to SST files yet, so it is not available.
+pub const TOTAL_SST_FILES_SIZE: &str = "mini-lsm.total-sst-files-size";
+
+/// The size in bytes of the live WAL, including the data not flushed yet.
+pub const TOTAL_WAL_SIZE: &str = "mini-lsm.total-wal-size";
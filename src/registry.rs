@@ -0,0 +1,43 @@
+//! This module keeps track of the DB directories open in this process.
+//!
+//! The LOCK file already keeps other processes out of an open DB, but whether a
+//! second lock taken by the same process conflicts depends on the platform and
+//! the locking primitive. The registry makes opening the same directory twice
+//! within a process fail deterministically, with a distinct error.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+use crate::error::{Error, Result};
+
+static OPEN_DIRS: LazyLock<Mutex<HashSet<PathBuf>>> = LazyLock::new(Default::default);
+
+/// Keeps a directory registered as open until dropped.
+pub struct Registration {
+    dir: PathBuf,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        OPEN_DIRS.lock().unwrap().remove(&self.dir);
+    }
+}
+
+/// Registers `dir` as open.
+///
+/// Paths are canonicalized, so different paths leading to the same directory
+/// are recognized.
+///
+/// # Returns
+///
+/// Returns `Err(Error::AlreadyOpen)` if the directory is already open in this process.
+pub fn register(dir: &Path) -> Result<Registration> {
+    let dir = dir.canonicalize()?;
+    if !OPEN_DIRS.lock().unwrap().insert(dir.clone()) {
+        return Err(Error::AlreadyOpen(dir.display().to_string()));
+    }
+    Ok(Registration { dir })
+}
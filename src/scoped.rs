@@ -0,0 +1,200 @@
+//! This module provides `ScopedDB`, a logical sub-store of a `DB`.
+//!
+//! All the keys read or written through a `ScopedDB` are transparently prefixed
+//! with the scope's prefix, and the prefix is stripped again from the keys
+//! returned by scans. This gives cheap isolation between tenants sharing a DB.
+//!
+//! The prefix is the 4-byte big-endian length of the scope name followed by
+//! the name, so that no scope is a key prefix of another one: without the
+//! length, the scope `t1` would see the keys of the scope `t10`.
+
+use std::ops::RangeBounds;
+
+use crate::{
+    error,
+    write_batch::{WriteBatch, WriteOp},
+    Iter, DB,
+};
+
+/// A handle to a `DB` that namespaces all operations under a key prefix.
+pub struct ScopedDB<'a> {
+    db: &'a DB,
+    prefix: Vec<u8>,
+}
+
+/// An iterator over the entries of a `ScopedDB`, yielding keys without the prefix.
+pub struct ScopedIter<'a> {
+    it: Iter<'a>,
+    prefix_len: usize,
+}
+
+impl Iterator for ScopedIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it.next().map(|(mut key, value)| {
+            key.drain(..self.prefix_len);
+            (key, value)
+        })
+    }
+}
+
+impl<'a> ScopedDB<'a> {
+    /// Creates a new `ScopedDB` over `db` using `name` as the namespace.
+    pub(crate) fn new(db: &'a DB, name: &[u8]) -> ScopedDB<'a> {
+        let len = u32::try_from(name.len()).expect("scope name too long");
+        ScopedDB {
+            db,
+            prefix: [&len.to_be_bytes()[..], name].concat(),
+        }
+    }
+
+    /// Returns the prefix the keys of this scope are stored under.
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    fn scoped_key(&self, key: &[u8]) -> Vec<u8> {
+        [self.prefix.as_slice(), key].concat()
+    }
+
+    pub fn insert_or_update(&self, key: &[u8], value: &[u8]) -> error::Result<()> {
+        let key = self.scoped_key(key);
+        self.db.insert_or_update(&key, value)
+    }
+
+    /// Applies a write batch with every key rewritten into this scope.
+    pub fn write(&self, wb: &WriteBatch) -> error::Result<()> {
+        let mut scoped_wb = WriteBatch::new();
+        if let Some(metadata) = wb.metadata() {
+            scoped_wb.set_metadata(metadata);
+        }
+        for op in wb.ops() {
+            match op {
+                WriteOp::Put(key, value) => {
+                    scoped_wb.insert_or_update(&self.scoped_key(key), value)
+                }
+                WriteOp::PutWithExpiry(key, value, expires_at) => {
+                    scoped_wb.insert_or_update_with_expiry(&self.scoped_key(key), value, expires_at)
+                }
+                WriteOp::Delete(key) => scoped_wb.delete(&self.scoped_key(key)),
+                WriteOp::SingleDelete(key) => scoped_wb.single_delete(&self.scoped_key(key)),
+                WriteOp::DeleteRange(start, end) => {
+                    scoped_wb.delete_range(&self.scoped_key(start), &self.scoped_key(end))
+                }
+            }
+        }
+        self.db.write(&scoped_wb)
+    }
+
+    /// Returns the value of `key` in this scope, like `DB::get`.
+    pub fn get(&self, key: &[u8]) -> error::Result<Option<Vec<u8>>> {
+        self.db.get(&self.scoped_key(key))
+    }
+
+    pub fn delete(&self, key: &[u8]) -> error::Result<()> {
+        let key = self.scoped_key(key);
+        self.db.delete(&key)
+    }
+
+    /// Deletes a key of this scope written at most once, like `DB::single_delete`.
+    pub fn single_delete(&self, key: &[u8]) -> error::Result<()> {
+        self.db.single_delete(&self.scoped_key(key))
+    }
+
+    /// Deletes the keys of this scope in `[start, end)`, like `DB::delete_range`.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> error::Result<()> {
+        self.db
+            .delete_range(&self.scoped_key(start), &self.scoped_key(end))
+    }
+
+    /// Returns an iterator over the keys of this scope within `range`, e.g.
+    /// `scope.scan(&b"a"[..]..)`.
+    pub fn scan<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> error::Result<ScopedIter<'_>> {
+        Ok(ScopedIter {
+            it: self.db.scan_prefix_range(&self.prefix, range)?,
+            prefix_len: self.prefix.len(),
+        })
+    }
+
+    /// Returns an iterator over all the keys of this scope.
+    pub fn iter(&self) -> error::Result<ScopedIter<'_>> {
+        Ok(ScopedIter {
+            it: self.db.scan_prefix(&self.prefix)?,
+            prefix_len: self.prefix.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::{write_batch::WriteBatch, DB};
+
+    #[test]
+    fn scopes_are_isolated() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+
+        for tenant in [&b"t1/"[..], b"t2/"] {
+            let scope = kvstore.scoped(tenant);
+            scope.insert_or_update(b"a", tenant).unwrap();
+            scope.insert_or_update(b"b", tenant).unwrap();
+
+            let mut wb = WriteBatch::new();
+            wb.insert_or_update(b"c", tenant);
+            wb.delete(b"b");
+            scope.write(&wb).unwrap();
+        }
+
+        kvstore.scoped(b"t1/").delete(b"a").unwrap();
+
+        let scope = kvstore.scoped(b"t1/");
+        assert_eq!(scope.get(b"a").unwrap(), None);
+        assert_eq!(scope.get(b"c").unwrap(), Some(b"t1/".to_vec()));
+        let entries: Vec<_> = scope.iter().unwrap().collect();
+        assert_eq!(entries, vec![(b"c".to_vec(), b"t1/".to_vec())]);
+
+        let scope = kvstore.scoped(b"t2/");
+        let entries: Vec<_> = scope.scan(&b"a"[..]..b"c").unwrap().collect();
+        assert_eq!(entries, vec![(b"a".to_vec(), b"t2/".to_vec())]);
+        let entries: Vec<_> = scope.scan(&b"b"[..]..).unwrap().collect();
+        assert_eq!(entries, vec![(b"c".to_vec(), b"t2/".to_vec())]);
+
+        // The keys are stored with the prefix in the underlying DB
+        assert_eq!(
+            kvstore.get(b"\0\0\0\x03t2/c").unwrap(),
+            Some(b"t2/".to_vec())
+        );
+    }
+
+    #[test]
+    fn scope_names_sharing_a_prefix_are_isolated() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path();
+        let kvstore = DB::new(db_path).expect("Failed to create a new DB");
+
+        kvstore.scoped(b"t1").insert_or_update(b"a", b"1").unwrap();
+        kvstore
+            .scoped(b"t10")
+            .insert_or_update(b"a", b"10")
+            .unwrap();
+        kvstore
+            .scoped(b"t10")
+            .insert_or_update(b"b", b"10")
+            .unwrap();
+
+        let scope = kvstore.scoped(b"t1");
+        let entries: Vec<_> = scope.iter().unwrap().collect();
+        assert_eq!(entries, vec![(b"a".to_vec(), b"1".to_vec())]);
+        let entries: Vec<_> = scope.scan(..).unwrap().collect();
+        assert_eq!(entries, vec![(b"a".to_vec(), b"1".to_vec())]);
+        assert_eq!(scope.get(b"0a").unwrap(), None);
+
+        scope.delete_range(b"a", b"z").unwrap();
+        let entries: Vec<_> = kvstore.scoped(b"t10").iter().unwrap().collect();
+        assert_eq!(entries.len(), 2);
+    }
+}
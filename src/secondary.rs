@@ -0,0 +1,268 @@
+//! This module provides `SecondaryDB`, a read-only view of a DB that another
+//! process, the primary, has open.
+//!
+//! The secondary finds the live WAL of the primary through its CURRENT and
+//! MANIFEST files, replays it into a memtable of its own, and then follows it:
+//! every call to `try_catch_up_with_primary` applies the batches appended since
+//! the previous one. Values expire with the TTL the primary records in its
+//! OPTIONS file. The primary's files are only ever read.
+
+use std::{
+    fs::File,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    error::{Error, Result},
+    iterator_tracker::IteratorTracker,
+    keys, lock_dir, manifest,
+    memtable::Memtable,
+    options_file, registry,
+    wal_recovery::{self, wal_file_path},
+    wal_tail::WalTail,
+    Iter,
+};
+
+/// Returns the log number and the path of the live WAL of the DB in `dir`.
+fn live_wal(dir: &Path) -> Result<(u64, PathBuf)> {
+    let number = manifest::read_current(dir)?
+        .ok_or_else(|| Error::ValueError(format!("no database at `{}`", dir.display())))?;
+    let manifest_path = manifest::manifest_file_path(dir, number);
+    let state = manifest::replay(manifest::path_str(&manifest_path)?)?;
+    Ok((state.log_number, wal_file_path(dir, state.log_number)))
+}
+
+/// Returns the TTL the DB in `dir` is open with.
+fn primary_ttl(dir: &Path) -> Result<Option<Duration>> {
+    Ok(options_file::load(&dir.join(options_file::OPTIONS_FILE_NAME))?.ttl)
+}
+
+/// Follows the live WAL of the primary.
+struct Follower {
+    log_number: u64,
+    /// The TTL the batches are applied with.
+    ttl: Option<Duration>,
+    tail: WalTail,
+    last_sequence: u64,
+}
+
+impl Follower {
+    /// Starts following the WAL with the given log number from its start.
+    fn start(log_number: u64, ttl: Option<Duration>, wal_path: &Path) -> Result<Follower> {
+        let mut tail = WalTail::new(manifest::path_str(wal_path)?, 0, Duration::ZERO)?;
+        tail.set_timeout(Some(Duration::ZERO));
+        Ok(Follower {
+            log_number,
+            ttl,
+            tail,
+            last_sequence: 0,
+        })
+    }
+
+    /// Applies the batches appended to the WAL since the last call to `memtable`.
+    ///
+    /// A batch the primary is still appending is left for the next call.
+    fn apply_new_batches(&mut self, memtable: &RwLock<Memtable>) -> Result<()> {
+        while let Some(wb) = self.tail.next_batch() {
+            let wb = wb?;
+            wal_recovery::consume_write_batch(
+                &mut memtable.write().unwrap(),
+                &wb,
+                wb.sequence(),
+                self.ttl,
+            );
+            self.last_sequence = wb.sequence() + u64::from(wb.count()) - 1;
+        }
+        Ok(())
+    }
+}
+
+/// A read-only view of a DB open in another process, caught up on demand.
+pub struct SecondaryDB {
+    primary_dir: PathBuf,
+    memtable: RwLock<Memtable>,
+    follower: Mutex<Follower>,
+    iterators: IteratorTracker,
+    _lock: (File, registry::Registration),
+}
+
+impl SecondaryDB {
+    /// Opens a secondary following the DB in `primary_dir`, see `DB::open_as_secondary`.
+    pub(crate) fn open(primary_dir: &Path, dir: &Path) -> Result<SecondaryDB> {
+        std::fs::create_dir_all(dir)?;
+        let lock = lock_dir(dir)?;
+        let (log_number, wal_path) = live_wal(primary_dir)?;
+        let mut follower = Follower::start(log_number, primary_ttl(primary_dir)?, &wal_path)?;
+        let memtable = RwLock::new(Memtable::new());
+        follower.apply_new_batches(&memtable)?;
+        Ok(SecondaryDB {
+            primary_dir: primary_dir.to_path_buf(),
+            memtable,
+            follower: Mutex::new(follower),
+            iterators: IteratorTracker::default(),
+            _lock: lock,
+        })
+    }
+
+    /// Applies the writes the primary made since the last catch-up.
+    ///
+    /// If the primary moved on to a new WAL, discarded the end of its WAL
+    /// while recovering, or was reopened with another TTL, the secondary
+    /// replays the live WAL from scratch.
+    /// Reads are served from the previous state until it is ready.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the primary's files cannot be read or are corrupt.
+    /// The secondary keeps its state, and a later call can try again.
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        let mut follower = self.follower.lock().unwrap();
+        let (log_number, wal_path) = live_wal(&self.primary_dir)?;
+        let ttl = primary_ttl(&self.primary_dir)?;
+        let wal_len = std::fs::metadata(&wal_path)?.len();
+        if log_number == follower.log_number
+            && ttl == follower.ttl
+            && wal_len >= follower.tail.offset()
+        {
+            return follower.apply_new_batches(&self.memtable);
+        }
+        tracing::info!(log_number, "replaying the WAL of the primary");
+        let mut replayed = Follower::start(log_number, ttl, &wal_path)?;
+        let memtable = RwLock::new(Memtable::new());
+        replayed.apply_new_batches(&memtable)?;
+        *self.memtable.write().unwrap() = memtable.into_inner().unwrap();
+        *follower = replayed;
+        Ok(())
+    }
+
+    /// Returns the directory of the primary.
+    pub fn primary_path(&self) -> &Path {
+        &self.primary_dir
+    }
+
+    /// Returns the sequence number of the most recent write applied, or 0 if none was.
+    pub fn latest_sequence(&self) -> u64 {
+        self.follower.lock().unwrap().last_sequence
+    }
+
+    /// Returns the value of `key`, or `None` if it does not exist, like `DB::get`.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.memtable.read().unwrap().get(key).map(<[u8]>::to_vec))
+    }
+
+    /// Returns an iterator over the keys within `range`, like `DB::scan`.
+    pub fn scan<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> Result<Iter<'_>> {
+        let guard = self.iterators.register()?;
+        Ok(Iter::new(
+            &self.memtable,
+            guard,
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            u64::MAX,
+            // A replay swaps the memtable for a new one, which a pin would not be registered in
+            false,
+        ))
+    }
+
+    /// Returns an iterator over all the keys starting with `prefix`, like `DB::scan_prefix`.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Iter<'_>> {
+        let successor = keys::prefix_successor(prefix);
+        let end = match &successor {
+            Some(successor) => Bound::Excluded(successor.as_slice()),
+            None => Bound::Unbounded,
+        };
+        self.scan((Bound::Included(prefix), end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use crate::{
+        write_batch::{now_micros, BatchMetadata, WriteBatch},
+        Error, Options, DB,
+    };
+
+    #[test]
+    fn catch_up_with_primary() {
+        let temp_dir = TempDir::new().unwrap();
+        let primary_path = temp_dir.path().join("primary");
+        let secondary_path = temp_dir.path().join("secondary");
+
+        let primary = DB::new(&primary_path).expect("Failed to create a new DB");
+        primary.insert_or_update(b"a", b"1").unwrap();
+        primary.insert_or_update(b"b", b"2").unwrap();
+
+        let secondary = DB::open_as_secondary(&primary_path, &secondary_path).unwrap();
+        assert_eq!(secondary.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(secondary.latest_sequence(), 2);
+        assert!(matches!(
+            DB::open_as_secondary(&primary_path, &secondary_path),
+            Err(Error::AlreadyOpen(_))
+        ));
+
+        // Writes only show up once the secondary catches up
+        let mut wb = WriteBatch::new();
+        wb.delete(b"a");
+        wb.insert_or_update(b"c", &[3; 100_000]);
+        primary.write(&wb).unwrap();
+        assert_eq!(secondary.get(b"c").unwrap(), None);
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(secondary.get(b"a").unwrap(), None);
+        assert_eq!(secondary.get(b"c").unwrap(), Some(vec![3; 100_000]));
+        assert_eq!(secondary.latest_sequence(), primary.latest_sequence());
+        let keys: Vec<_> = secondary.scan(..).unwrap().map(|(key, _)| key).collect();
+        assert_eq!(keys, [b"b".to_vec(), b"c".to_vec()]);
+
+        // Catching up without new writes changes nothing
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(secondary.scan_prefix(b"").unwrap().count(), 2);
+
+        // A new DB in the primary's place is replayed from scratch
+        drop(primary);
+        DB::destroy(&primary_path, &Options::default()).unwrap();
+        let primary = DB::new(&primary_path).expect("Failed to create a new DB");
+        primary.insert_or_update(b"d", b"4").unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        let keys: Vec<_> = secondary.scan(..).unwrap().map(|(key, _)| key).collect();
+        assert_eq!(keys, [b"d".to_vec()]);
+        assert_eq!(secondary.latest_sequence(), 1);
+    }
+
+    #[test]
+    fn values_expire_with_the_ttl_of_the_primary() {
+        let temp_dir = TempDir::new().unwrap();
+        let primary_path = temp_dir.path().join("primary");
+        let secondary_path = temp_dir.path().join("secondary");
+        let hour = Duration::from_secs(3600);
+
+        // A batch written two hours ago, which the TTL of the primary expired
+        let primary = DB::open_with_ttl(&primary_path, hour).expect("Failed to create a new DB");
+        primary.insert_or_update(b"a", b"1").unwrap();
+        let mut wb = WriteBatch::new();
+        wb.set_metadata(BatchMetadata {
+            write_time_micros: now_micros() - 2 * hour.as_micros() as u64,
+            ..Default::default()
+        });
+        wb.insert_or_update(b"b", b"2");
+        primary.write(&wb).unwrap();
+        assert_eq!(primary.get(b"b").unwrap(), None);
+
+        let secondary = DB::open_as_secondary(&primary_path, &secondary_path).unwrap();
+        assert_eq!(secondary.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(secondary.get(b"b").unwrap(), None);
+
+        // Reopening the primary without a TTL brings the value back
+        drop(primary);
+        let primary = DB::new(&primary_path).expect("Recovery failed");
+        assert_eq!(primary.get(b"b").unwrap(), Some(b"2".to_vec()));
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(secondary.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
+}
@@ -0,0 +1,115 @@
+//! This module provides `Snapshot`, a consistent point-in-time view of a DB.
+//!
+//! A snapshot is pinned to the sequence number of the last write when it was
+//! taken. Reads through it see the newest version of each key written at or
+//! before that sequence number, no matter what is written afterwards.
+
+use std::{
+    fmt,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{db_iterator::DBIterator, error::Result, keys, Iter, DB};
+
+/// A read-only view of a DB as of the moment it was taken, see `DB::snapshot`.
+pub struct Snapshot<'a> {
+    db: &'a DB,
+    sequence: u64,
+}
+
+impl<'a> Snapshot<'a> {
+    /// Creates a snapshot of `db` at `sequence`, which must already be registered with its memtable.
+    pub(crate) fn new(db: &'a DB, sequence: u64) -> Snapshot<'a> {
+        Snapshot { db, sequence }
+    }
+
+    /// Returns whether the snapshot was taken of `db`.
+    pub(crate) fn is_of(&self, db: &DB) -> bool {
+        std::ptr::eq(self.db, db)
+    }
+
+    /// Returns the sequence number of the last write the snapshot sees.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the value `key` had when the snapshot was taken, like `DB::get`.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.db.get_at(key, self.sequence)
+    }
+
+    /// Returns an iterator over the keys within `range` as of the snapshot, like `DB::scan`.
+    ///
+    /// The iterator borrows the snapshot, as releasing the snapshot would drop
+    /// the versions it reads:
+    ///
+    /// ```compile_fail
+    /// # let temp_dir = tempfile::TempDir::new().unwrap();
+    /// # let db = mini_lsm::DB::new(temp_dir.path()).unwrap();
+    /// let snapshot = db.snapshot().unwrap();
+    /// let iter = snapshot.scan(..).unwrap();
+    /// drop(snapshot);
+    /// iter.count();
+    /// ```
+    pub fn scan<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> Result<Iter<'_>> {
+        self.db.iter(
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            self.sequence,
+        )
+    }
+
+    /// Returns a cursor over the keys within `range` as of the snapshot, like `DB::iterator`.
+    ///
+    /// Like with `scan`, the cursor borrows the snapshot:
+    ///
+    /// ```compile_fail
+    /// # let temp_dir = tempfile::TempDir::new().unwrap();
+    /// # let db = mini_lsm::DB::new(temp_dir.path()).unwrap();
+    /// let snapshot = db.snapshot().unwrap();
+    /// let mut it = snapshot.iterator(..).unwrap();
+    /// drop(snapshot);
+    /// it.seek_to_first();
+    /// ```
+    pub fn iterator<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> Result<DBIterator<'_>> {
+        self.db.check_readable()?;
+        let guard = self.db.iterators.register()?;
+        Ok(DBIterator::new(
+            &self.db.memtable,
+            guard,
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            self.sequence,
+            true,
+        ))
+    }
+
+    /// Returns an iterator over the keys starting with `prefix` as of the
+    /// snapshot, like `DB::scan_prefix`.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Iter<'_>> {
+        let successor = keys::prefix_successor(prefix);
+        let end = match &successor {
+            Some(successor) => Bound::Excluded(successor.as_slice()),
+            None => Bound::Unbounded,
+        };
+        self.scan((Bound::Included(prefix), end))
+    }
+}
+
+impl fmt::Debug for Snapshot<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("sequence", &self.sequence)
+            .finish()
+    }
+}
+
+impl Drop for Snapshot<'_> {
+    fn drop(&mut self) {
+        self.db
+            .memtable
+            .write()
+            .unwrap()
+            .release_snapshot(self.sequence);
+    }
+}
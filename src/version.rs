@@ -0,0 +1,150 @@
+//! This module provides reference-counted versions of the LSM tree's file set.
+//!
+//! A `Version` is an immutable snapshot of the files in every level. Reads pin
+//! the current version by holding an `Arc<Version>` for their whole duration,
+//! while flushes and compactions install new versions through the `VersionSet`.
+//! A file is only obsolete, and may be deleted, once no live version refers to it.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Weak},
+};
+
+use crate::{
+    error::Result,
+    manifest::{FileMetaData, Manifest, VersionEdit, VersionState, NUM_LEVELS},
+};
+
+/// An immutable snapshot of the files in the LSM tree.
+#[derive(Debug, Default)]
+pub struct Version {
+    levels: [Vec<FileMetaData>; NUM_LEVELS],
+}
+
+impl Version {
+    fn from_state(state: &VersionState) -> Version {
+        Version {
+            levels: state.levels.clone(),
+        }
+    }
+
+    /// Returns the files in `level`.
+    #[allow(dead_code)]
+    pub fn files(&self, level: usize) -> &[FileMetaData] {
+        &self.levels[level]
+    }
+
+    /// Returns the numbers of all the files in the version.
+    fn file_numbers(&self) -> impl Iterator<Item = u64> + '_ {
+        self.levels.iter().flatten().map(|file| file.number)
+    }
+}
+
+/// The set of versions of the LSM tree that are still referenced.
+pub struct VersionSet {
+    manifest: Manifest,
+    current: Arc<Version>,
+    versions: Vec<Weak<Version>>,
+}
+
+impl VersionSet {
+    /// Creates a `VersionSet` whose current version is the state of `manifest`.
+    pub fn new(manifest: Manifest) -> VersionSet {
+        let current = Arc::new(Version::from_state(manifest.state()));
+        VersionSet {
+            versions: vec![Arc::downgrade(&current)],
+            manifest,
+            current,
+        }
+    }
+
+    /// Returns the current version, pinning its files until the returned reference is dropped.
+    /// Nothing reads from SST files until flushes write them.
+    #[allow(dead_code)]
+    pub fn current(&self) -> Arc<Version> {
+        Arc::clone(&self.current)
+    }
+
+    /// Returns the MANIFEST backing the version set.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Persists `edit` in the MANIFEST and installs the resulting version as current.
+    ///
+    /// Versions pinned by in-flight reads are unaffected.
+    pub fn log_and_apply(&mut self, edit: &VersionEdit) -> Result<()> {
+        self.manifest.log_and_apply(edit)?;
+        self.current = Arc::new(Version::from_state(self.manifest.state()));
+        self.versions.push(Arc::downgrade(&self.current));
+        Ok(())
+    }
+
+    /// Returns the numbers of the files referenced by any live version.
+    ///
+    /// Files that are not part of this set are obsolete and can be safely deleted.
+    /// Nothing is deleted until flushes write SST files.
+    #[allow(dead_code)]
+    pub fn live_files(&mut self) -> HashSet<u64> {
+        self.versions.retain(|version| version.strong_count() > 0);
+        self.versions
+            .iter()
+            .filter_map(Weak::upgrade)
+            .flat_map(|version| version.file_numbers().collect::<Vec<_>>())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::manifest::DEFAULT_MAX_MANIFEST_SIZE;
+
+    fn file(number: u64, level: u32) -> FileMetaData {
+        FileMetaData {
+            number,
+            level,
+            size: 1024,
+            smallest: vec![number as u8],
+            largest: vec![number as u8],
+        }
+    }
+
+    #[test]
+    fn pinned_versions_keep_files_alive() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest = Manifest::create(
+            temp_dir.path(),
+            VersionState::default(),
+            DEFAULT_MAX_MANIFEST_SIZE,
+        )
+        .unwrap();
+        let mut versions = VersionSet::new(manifest);
+
+        let mut edit = VersionEdit::default();
+        edit.add_file(file(1, 0));
+        edit.add_file(file(2, 0));
+        versions.log_and_apply(&edit).unwrap();
+
+        // A read pins the version containing files 1 and 2
+        let pinned = versions.current();
+        assert_eq!(pinned.files(0).len(), 2);
+
+        // Compact files 1 and 2 into file 3
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, 1);
+        edit.delete_file(0, 2);
+        edit.add_file(file(3, 1));
+        versions.log_and_apply(&edit).unwrap();
+
+        assert_eq!(versions.current().files(1), &[file(3, 1)]);
+        assert_eq!(pinned.files(0).len(), 2);
+        assert_eq!(versions.live_files(), HashSet::from([1, 2, 3]));
+
+        // Once the read completes, files 1 and 2 become obsolete
+        drop(pinned);
+        assert_eq!(versions.live_files(), HashSet::from([3]));
+    }
+}
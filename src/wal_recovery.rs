@@ -2,21 +2,159 @@
 ///
 /// WAL recovery is responsible for loading the WAL file into the memtable.
 ///
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
 use crate::{
-    error,
+    error::{self, Error},
     lending_iterator::LendingIterator,
     log_reader::LogReader,
     memtable::Memtable,
-    write_batch::{WriteBatch, WriteBatchBuilder},
+    options::WalRecoveryMode,
+    write_batch::{Checkpoint, WriteBatch, WriteBatchBuilder, WriteOp},
 };
 
-pub fn consume_write_batch(memtable: &mut Memtable, wb: &WriteBatch) {
-    for (key, value) in wb.iter() {
-        match value {
-            Some(value) => memtable.insert_or_update(key, value),
-            None => {
-                memtable.delete(key);
+/// Returns the path of the WAL segment with the given file number in `dir`.
+pub fn wal_file_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.log", number))
+}
+
+/// The number of batches written between two consistency checkpoints.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// The state of the WAL that is tracked by the writer and reconstructed on recovery.
+#[derive(Default, Debug)]
+pub struct WalState {
+    /// The sequence number of the last entry in the WAL.
+    pub last_sequence: u64,
+    /// The CRC32C over the bytes of every batch in the WAL, in order.
+    pub rolling_crc: u32,
+    /// The number of batches written since the last checkpoint.
+    pub batches_since_checkpoint: u64,
+    /// The offset in the WAL right after the last batch replayed by recovery.
+    pub replayed_len: u64,
+    /// The byte ranges of the WAL that recovery skipped because they were corrupt.
+    pub corrupted: Vec<Range<u64>>,
+}
+
+impl WalState {
+    /// Accounts for a batch that was appended to the WAL and applied to the memtable.
+    pub fn record_batch(&mut self, wb: &WriteBatch) {
+        self.last_sequence = self.first_sequence(wb) + u64::from(wb.count()) - 1;
+        self.rolling_crc = crc32c::crc32c_append(self.rolling_crc, wb.as_bytes());
+        self.batches_since_checkpoint += 1;
+    }
+
+    /// Accounts for a batch that was applied to the memtable without being
+    /// appended to the WAL, which only consumes sequence numbers.
+    pub fn record_unlogged_batch(&mut self, wb: &WriteBatch) {
+        self.last_sequence = self.first_sequence(wb) + u64::from(wb.count()) - 1;
+    }
+
+    /// Returns the sequence number of the first entry of `wb`. Batches written
+    /// before sequence numbers were recorded in the WAL follow the previous one.
+    pub fn first_sequence(&self, wb: &WriteBatch) -> u64 {
+        match wb.sequence() {
+            0 => self.last_sequence + 1,
+            sequence => sequence,
+        }
+    }
+
+    /// Checks that `wb` comes after the batches accounted for so far.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::Corruption)` if the sequence number of `wb` goes backwards.
+    fn check_sequence(&self, wb: &WriteBatch) -> error::Result<()> {
+        if self.first_sequence(wb) <= self.last_sequence {
+            return Err(Error::Corruption(format!(
+                "batch with sequence {} follows sequence {}",
+                wb.sequence(),
+                self.last_sequence
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns a checkpoint describing the current state, and resets the batch counter.
+    pub fn checkpoint(&mut self, memtable: &Memtable) -> Checkpoint {
+        self.batches_since_checkpoint = 0;
+        Checkpoint {
+            sequence: self.last_sequence,
+            rolling_crc: self.rolling_crc,
+            key_count: memtable.len() as u64,
+        }
+    }
+
+    /// Reconciles the replayed state against a checkpoint read from the WAL.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::Corruption)` if the replayed state diverges from the checkpoint.
+    fn verify(&mut self, checkpoint: &Checkpoint, memtable: &Memtable) -> error::Result<()> {
+        let replayed = self.checkpoint(memtable);
+        if replayed != *checkpoint {
+            return Err(Error::Corruption(format!(
+                "replayed WAL state {:?} does not match the checkpoint {:?}",
+                replayed, checkpoint
+            )));
+        }
+        Ok(())
+    }
+
+    /// Adopts the state of a checkpoint after corrupt batches were skipped,
+    /// since the replayed state cannot match it anymore.
+    fn resync(&mut self, checkpoint: &Checkpoint) {
+        self.last_sequence = self.last_sequence.max(checkpoint.sequence);
+        self.rolling_crc = checkpoint.rolling_crc;
+        self.batches_since_checkpoint = 0;
+    }
+}
+
+/// Returns the time the values of `wb` without an expiry of their own expire
+/// at, in microseconds since the Unix epoch, or 0 if they never do.
+///
+/// The TTL in the metadata of the batch takes precedence over `ttl`, the TTL of
+/// the DB. Both count from the write time in the metadata, so batches without
+/// metadata never expire.
+fn batch_expiry(wb: &WriteBatch, ttl: Option<Duration>) -> u64 {
+    let Some(metadata) = wb.metadata() else {
+        return 0;
+    };
+    let ttl_micros = match (metadata.ttl_secs, ttl) {
+        (0, None) => return 0,
+        (0, Some(ttl)) => u64::try_from(ttl.as_micros()).unwrap_or(u64::MAX),
+        (ttl_secs, _) => ttl_secs.saturating_mul(1_000_000),
+    };
+    metadata.write_time_micros.saturating_add(ttl_micros)
+}
+
+/// Applies the entries of `wb` to the memtable, assigning them consecutive
+/// sequence numbers from `first_sequence`.
+///
+/// Values expire at their own expiry time, or else at the one given by the
+/// metadata of the batch and `ttl`, the TTL of the DB.
+pub fn consume_write_batch(
+    memtable: &mut Memtable,
+    wb: &WriteBatch,
+    first_sequence: u64,
+    ttl: Option<Duration>,
+) {
+    let expires_at = batch_expiry(wb, ttl);
+    for (op, sequence) in wb.ops().zip(first_sequence..) {
+        match op {
+            WriteOp::Put(key, value) => {
+                memtable.put_with_expiry(key, sequence, Some(value), expires_at)
             }
+            WriteOp::PutWithExpiry(key, value, expires_at) => {
+                memtable.put_with_expiry(key, sequence, Some(value), expires_at)
+            }
+            WriteOp::Delete(key) => memtable.put(key, sequence, None),
+            WriteOp::SingleDelete(key) => memtable.single_delete(key, sequence),
+            WriteOp::DeleteRange(start, end) => memtable.delete_range(start, end, sequence),
         }
     }
 }
@@ -25,47 +163,162 @@ pub fn consume_write_batch(memtable: &mut Memtable, wb: &WriteBatch) {
 ///
 /// This function reads the WAL file specified by `log_file` and loads its contents
 /// into the `memtable`. It iterates over the records in the WAL file, validates them,
-/// and handles the payload based on the record type. The payload is processed by the
-/// `handle_payload` function, which inserts or updates key-value pairs in the memtable.
+/// and assembles them into write batches that are applied to the memtable.
 /// Partial records are buffered until a complete record is received.
 ///
 /// # Arguments
 ///
 /// * `log_file` - The path to the WAL file.
 /// * `memtable` - A mutable reference to the memtable.
+/// * `mode` - How to handle corrupt records and batches.
+/// * `ttl` - The TTL of the DB, see `consume_write_batch`.
+///
+/// Every batch is validated before it is applied, and the replayed state is
+/// reconciled against the consistency checkpoints found in the WAL, so replay
+/// bugs surface as errors instead of silently diverging data.
+///
+/// # Returns
+///
+/// The `WalState` after replaying the WAL. Every entry of a write batch consumes
+/// one sequence number, starting from 1, and the last sequence number is
+/// restored from the sequence recorded in the header of the last batch.
 ///
 /// # Errors
 ///
-/// This function returns an error if there is an issue reading the WAL file or if the
-/// records in the WAL file are invalid.
+/// This function returns an error if there is an issue reading the WAL file, or if
+/// the WAL is corrupt in a way `mode` does not tolerate. A batch written in a newer
+/// format fails with `Error::IncompatibleVersion` in every mode.
 ///
 /// # Example
 ///
 /// ```ignore
 /// use mini_lsm::wal_recovery::load;
 /// use mini_lsm::memtable::Memtable;
+/// use mini_lsm::options::WalRecoveryMode;
 ///
 /// let mut memtable = Memtable::new();
 /// let log_file = "/path/to/wal.log";
 ///
-/// if let Err(err) = load(log_file, &mut memtable) {
+/// if let Err(err) = load(log_file, &mut memtable, WalRecoveryMode::AbsoluteConsistency, None) {
 ///     println!("Failed to load WAL file: {}", err);
 /// }
 /// ```
 ///
-pub fn load(log_file: &str, memtable: &mut Memtable) -> error::Result<()> {
+pub fn load(
+    log_file: &str,
+    memtable: &mut Memtable,
+    mode: WalRecoveryMode,
+    ttl: Option<Duration>,
+) -> error::Result<WalState> {
     let log_reader = LogReader::new(log_file)?;
     let mut wb_builder = WriteBatchBuilder::new();
+    let mut state = WalState::default();
+    // The first corruption that was skipped
+    let mut corruption: Option<Error> = None;
 
     let mut iter = log_reader.to_iter()?;
-    while let Some(record_or_error) = iter.next() {
-        let record = record_or_error?;
-        record.validate_crc()?;
-        wb_builder.accumulate_record(&record)?;
-        if wb_builder.is_ready() {
-            consume_write_batch(memtable, wb_builder.get_write_batch());
-            wb_builder.consume();
+    state.replayed_len = iter.offset();
+    // The offset of the first record of the batch being assembled
+    let mut batch_start = iter.offset();
+    let mut corrupt_batches = Vec::new();
+    loop {
+        if !wb_builder.in_progress() {
+            batch_start = iter.offset();
+        }
+        let skipped_records = iter.skipped().len();
+        let Some(record_or_error) = iter.next() else {
+            break;
+        };
+        let batch_ready = record_or_error
+            .and_then(|record| wb_builder.accumulate_record(&record))
+            .and_then(|()| match wb_builder.is_ready() {
+                true => {
+                    let wb = wb_builder.get_write_batch();
+                    wb.validate()?;
+                    if wb.checkpoint().is_none() {
+                        state.check_sequence(wb)?;
+                    }
+                    Ok(true)
+                }
+                false => Ok(false),
+            });
+        let batch_ready = match batch_ready {
+            Ok(batch_ready) => batch_ready,
+            // Data written by a newer version is not corrupt, and must not be dropped
+            Err(err @ Error::IncompatibleVersion { .. }) => return Err(err),
+            Err(err) => {
+                wb_builder.consume();
+                // Corrupt records are skipped by the reader, corrupt batches here
+                if iter.skipped().len() == skipped_records {
+                    corrupt_batches.push(batch_start..iter.offset());
+                }
+                match mode {
+                    WalRecoveryMode::AbsoluteConsistency => return Err(err),
+                    WalRecoveryMode::PointInTime => break,
+                    WalRecoveryMode::TolerateCorruptedTail | WalRecoveryMode::SkipAnyCorruption => {
+                        corruption.get_or_insert(err);
+                        continue;
+                    }
+                }
+            }
+        };
+        // Valid records after a corruption mean it is not at the tail
+        if mode == WalRecoveryMode::TolerateCorruptedTail {
+            if let Some(err) = corruption.take() {
+                return Err(err);
+            }
+        }
+        if !batch_ready {
+            continue;
+        }
+
+        let wb = wb_builder.get_write_batch();
+        match wb.checkpoint() {
+            Some(checkpoint) if corruption.is_some() => state.resync(&checkpoint),
+            Some(checkpoint) => state.verify(&checkpoint, memtable)?,
+            None => {
+                consume_write_batch(memtable, wb, state.first_sequence(wb), ttl);
+                state.record_batch(wb);
+            }
         }
+        wb_builder.consume();
+        state.replayed_len = iter.offset();
+    }
+
+    // A crash in the middle of an append leaves a torn record or batch at the
+    // end of the WAL. Replay stops right before it.
+    if iter.torn_tail().is_some() || wb_builder.in_progress() {
+        if mode == WalRecoveryMode::AbsoluteConsistency {
+            return Err(Error::Corruption(format!(
+                "the WAL ends with a torn batch at offset {}",
+                state.replayed_len
+            )));
+        }
+        tracing::warn!(
+            offset = state.replayed_len,
+            "dropping the torn batch at the end of the WAL"
+        );
+    }
+    let mut corrupted: Vec<Range<u64>> = iter
+        .skipped()
+        .iter()
+        .cloned()
+        .chain(corrupt_batches)
+        .collect();
+    corrupted.sort_by_key(|range| range.start);
+    // A corrupt block is usually followed by the rest of the batch it cut short
+    for range in corrupted {
+        match state.corrupted.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => state.corrupted.push(range),
+        }
+    }
+    for range in &state.corrupted {
+        tracing::warn!(
+            start = range.start,
+            end = range.end,
+            "skipped corrupt bytes of the WAL"
+        );
     }
-    Ok(())
+    Ok(state)
 }
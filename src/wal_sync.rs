@@ -0,0 +1,107 @@
+//! This module provides periodic background fsyncs of the WAL.
+//!
+//! Writes that don't ask to be synced only reach the OS page cache, and are lost
+//! if the machine crashes before the OS writes them back. Syncing the WAL in the
+//! background every few milliseconds bounds that window without making every
+//! write wait for an fsync.
+
+use std::{
+    fs::File,
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event_listener::{EventListener, WalSyncInfo},
+    metrics::Metrics,
+};
+
+#[derive(Default)]
+struct Shared {
+    stopped: bool,
+    error: Option<io::Error>,
+}
+
+/// A background thread fsyncing a file at a fixed interval until dropped.
+pub struct WalSyncer {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl WalSyncer {
+    /// Starts syncing `file` every `interval`.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - A handle to the WAL, typically cloned from the handle it is written through.
+    /// * `interval` - The time between two fsyncs.
+    /// * `metrics` - The metrics the fsyncs are reported to.
+    /// * `log_number` - The number of the WAL, reported to the listeners.
+    /// * `listeners` - The listeners notified of each fsync.
+    pub fn start(
+        file: File,
+        interval: Duration,
+        metrics: Arc<Metrics>,
+        log_number: u64,
+        listeners: Vec<Arc<dyn EventListener>>,
+    ) -> WalSyncer {
+        let shared = Arc::new((Mutex::new(Shared::default()), Condvar::new()));
+        let thread_shared = Arc::clone(&shared);
+        let handle = thread::Builder::new()
+            .name("wal-sync".to_string())
+            .spawn(move || {
+                let (lock, stop) = &*thread_shared;
+                let mut state = lock.lock().unwrap();
+                loop {
+                    state = stop
+                        .wait_timeout_while(state, interval, |state| !state.stopped)
+                        .unwrap()
+                        .0;
+                    if state.stopped {
+                        return;
+                    }
+                    let start = Instant::now();
+                    match file.sync_data() {
+                        Ok(()) => {
+                            let duration = start.elapsed();
+                            metrics.record_fsync(duration);
+                            let info = WalSyncInfo {
+                                log_number,
+                                duration,
+                                background: true,
+                            };
+                            for listener in &listeners {
+                                listener.on_wal_sync(&info);
+                            }
+                        }
+                        Err(err) => {
+                            state.error.get_or_insert(err);
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn the WAL sync thread");
+        WalSyncer {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    /// Returns the first error hit by a background fsync since the last call, if any.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.shared.0.lock().unwrap().error.take()
+    }
+}
+
+impl Drop for WalSyncer {
+    fn drop(&mut self) {
+        let (lock, stop) = &*self.shared;
+        lock.lock().unwrap().stopped = true;
+        stop.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! This module lets consumers such as replicators follow the WAL of a DB as it
+//! is written.
+//!
+//! A `WalTail` reads the WAL from the start and then waits for new batches to be
+//! appended, returning every committed write batch in order.
+
+use std::time::Duration;
+
+use crate::{
+    error::Result,
+    lending_iterator::LendingIterator,
+    log_reader::{LogReader, TailingIter},
+    write_batch::{WriteBatch, WriteBatchBuilder},
+};
+
+/// Follows a WAL, returning the write batches appended to it.
+pub struct WalTail {
+    iter: TailingIter,
+    builder: WriteBatchBuilder,
+    /// The offset right after the last batch consumed, which the records of a
+    /// partial batch in the builder are not part of.
+    offset: u64,
+}
+
+impl WalTail {
+    /// Starts following the WAL at `wal_path` from `offset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `wal_path` - The path of the WAL file.
+    /// * `offset` - The offset to resume at, as reported by `offset`, or 0 to
+    ///   start from the beginning.
+    /// * `poll_interval` - How long to wait before checking the WAL for new batches again.
+    pub(crate) fn new(wal_path: &str, offset: u64, poll_interval: Duration) -> Result<WalTail> {
+        let mut iter = LogReader::new(wal_path)?.to_tailing_iter(0, poll_interval)?;
+        iter.seek(offset)?;
+        Ok(WalTail {
+            offset: iter.offset(),
+            iter,
+            builder: WriteBatchBuilder::new(),
+        })
+    }
+
+    /// Sets how long `next_batch` waits for a new batch before returning `None`.
+    /// Without a timeout, `next_batch` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.iter.set_timeout(timeout);
+    }
+
+    /// Returns the WAL offset up to which batches have been returned. A
+    /// consumer can persist it to resume following the WAL after a restart.
+    ///
+    /// A batch that was only partially appended when `next_batch` timed out
+    /// starts after the offset, so that resuming reads it in full.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Waits for the next batch appended to the WAL and returns it.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(Ok(batch))`: The next batch, with its sequence number set.
+    /// - `Some(Err(err))`: The WAL is corrupt. The partial batch is dropped and
+    ///   following resumes at the next block.
+    /// - `None`: No batch was appended before the timeout.
+    pub fn next_batch(&mut self) -> Option<Result<WriteBatch>> {
+        loop {
+            let result = match self.iter.next()? {
+                Ok(record) => self.builder.accumulate_record(&record),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                self.builder.consume();
+                self.offset = self.iter.offset();
+                return Some(Err(err));
+            }
+            if self.builder.is_ready() {
+                let wb = self.builder.get_write_batch().clone();
+                self.builder.consume();
+                self.offset = self.iter.offset();
+                // Checkpoints are bookkeeping of the WAL, not writes
+                if wb.checkpoint().is_none() {
+                    return Some(Ok(wb));
+                }
+            }
+        }
+    }
+}
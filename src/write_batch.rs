@@ -1,27 +1,264 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::{Error, Result};
 use crate::log_record::{LogRecord, RecordType};
 
-const HEADER_SIZE: usize = 16;
+// Header Format (version 1):
+//
+// +------------+------------+--------------+---------------+---------------+---------------+
+// | Count (4B) | Flags (1B) | Version (1B) | Reserved (2B) | Sequence (8B) | Checksum (4B) |
+// +------------+------------+--------------+---------------+---------------+---------------+
+//
+// Version 0 headers are 16 bytes long and end after the sequence field, which
+// is always zero since version 0 reserved those bytes. They carry no checksum.
+// Version 2 has the same header as version 1 and adds range deletions,
+// version 3 adds expiry times to entries, and version 4 adds single deletions.
+// Batches are never rewritten, so every version is read; only the newest is written.
+const HEADER_SIZE: usize = 20;
+const HEADER_SIZE_V0: usize = 16;
 const COUNT_OFFSET: usize = 0;
+const FLAGS_OFFSET: usize = 4;
+const VERSION_OFFSET: usize = 5;
+const SEQUENCE_OFFSET: usize = 8;
+const CHECKSUM_OFFSET: usize = 16;
+
+/// The newest write batch format version this build can read and the version it writes.
+/// Batches written by a newer version are rejected rather than mis-parsed.
+pub const FORMAT_VERSION: u8 = 4;
+
+/// Set in the header flags when the entries following the header are snappy compressed.
+const FLAG_COMPRESSED: u8 = 0x1;
+/// Set in the header flags when a metadata section follows the header.
+const FLAG_HAS_METADATA: u8 = 0x2;
+/// Set in the header flags of checkpoint batches, which carry a `Checkpoint` instead of entries.
+const FLAG_CHECKPOINT: u8 = 0x4;
+
+// Checkpoint Format:
+//
+// +---------------+------------------+----------------+
+// | Sequence (8B) | Rolling CRC (4B) | Key Count (8B) |
+// +---------------+------------------+----------------+
+const CHECKPOINT_SIZE: usize = 20;
+
+// Metadata Format (present only if FLAG_HAS_METADATA is set):
+//
+// +-----------------+----------------+---------------+
+// | Write Time (8B) | Origin Id (8B) | TTL Secs (8B) |
+// +-----------------+----------------+---------------+
+const METADATA_SIZE: usize = 24;
+
+// Entry Format:
+//
+// +----------------+-----+------------------+-------+
+// | Key Length (4B) | Key | Value Length (4B) | Value |
+// +----------------+-----+------------------+-------+
+//
+// A value length of 0 marks a delete. Range deletions set the top bit of the key
+// length, and store the start of the range as the key and its end as the value.
+// Entries with an expiry time set the next bit, and are followed by the time
+// they expire at, in microseconds since the Unix epoch (8B). Single deletions
+// set the third bit, and have an empty value.
+
+/// Set in the key length of range deletion entries.
+const RANGE_DELETION_FLAG: u32 = 1 << 31;
+/// Set in the key length of entries followed by an expiry time.
+const EXPIRY_FLAG: u32 = 1 << 30;
+/// Set in the key length of single deletion entries.
+const SINGLE_DELETION_FLAG: u32 = 1 << 29;
+/// The bits of the key length that are flags rather than part of the length.
+const LENGTH_FLAGS: u32 = RANGE_DELETION_FLAG | EXPIRY_FLAG | SINGLE_DELETION_FLAG;
+
+/// Returns the current time, in microseconds since the Unix epoch.
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+/// Batches smaller than this are never compressed, since the savings would not
+/// outweigh the cost of compression.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// The compression applied to write batch payloads before they are written to the WAL.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CompressionType {
+    #[default]
+    None,
+    Snappy,
+}
+
+/// Metadata attached to an entire `WriteBatch`.
+///
+/// The metadata is persisted with the batch in the WAL, so consumers reading the
+/// log (e.g. replication or CDC) can filter batches by their origin or age.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct BatchMetadata {
+    /// The time the batch was written, in microseconds since the Unix epoch.
+    pub write_time_micros: u64,
+    /// An application-defined identifier of the writer that produced the batch.
+    pub origin_id: u64,
+    /// The number of seconds after `write_time_micros` the batch expires, or 0 if it never expires.
+    pub ttl_secs: u64,
+}
+
+impl BatchMetadata {
+    /// Returns true if the batch has a TTL that elapsed before `now_micros`.
+    pub fn is_expired(&self, now_micros: u64) -> bool {
+        self.ttl_secs != 0
+            && self
+                .write_time_micros
+                .saturating_add(self.ttl_secs.saturating_mul(1_000_000))
+                <= now_micros
+    }
+
+    fn to_bytes(self) -> [u8; METADATA_SIZE] {
+        let mut bytes = [0; METADATA_SIZE];
+        bytes[0..8].copy_from_slice(&self.write_time_micros.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.origin_id.to_be_bytes());
+        bytes[16..24].copy_from_slice(&self.ttl_secs.to_be_bytes());
+        bytes
+    }
 
+    fn from_bytes(bytes: &[u8]) -> BatchMetadata {
+        BatchMetadata {
+            write_time_micros: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            origin_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+            ttl_secs: u64::from_be_bytes(bytes[16..24].try_into().unwrap()),
+        }
+    }
+}
+
+/// A consistency checkpoint periodically written to the WAL.
+///
+/// It records the state the writer had after applying all the batches before
+/// it, so that recovery can reconcile the replayed state against it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    /// The sequence number of the last entry written before the checkpoint.
+    pub sequence: u64,
+    /// The CRC32C over the bytes of every batch written before the checkpoint.
+    pub rolling_crc: u32,
+    /// The number of live keys in the memtable at the checkpoint.
+    pub key_count: u64,
+}
+
+/// Returns the size of the header of a serialized batch, which depends on its format version.
+fn header_size(bytes: &[u8]) -> usize {
+    if bytes[VERSION_OFFSET] == 0 {
+        HEADER_SIZE_V0
+    } else {
+        HEADER_SIZE
+    }
+}
+
+/// Returns the offset of the first entry in a serialized batch.
+fn entries_offset(bytes: &[u8]) -> usize {
+    if bytes[FLAGS_OFFSET] & FLAG_CHECKPOINT != 0 {
+        // Checkpoints do not contain any entries.
+        bytes.len()
+    } else if bytes[FLAGS_OFFSET] & FLAG_HAS_METADATA != 0 {
+        header_size(bytes) + METADATA_SIZE
+    } else {
+        header_size(bytes)
+    }
+}
+
+/// Computes the checksum of a serialized batch, covering every byte except the checksum itself.
+fn compute_checksum(bytes: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(&bytes[..CHECKSUM_OFFSET]);
+    crc32c::crc32c_append(crc, &bytes[CHECKSUM_OFFSET + 4..])
+}
+
+#[derive(Clone)]
 pub struct WriteBatch {
     entries: Vec<u8>,
 }
 
-pub struct WriteBatchIterator<'a> {
+/// A write operation in a `WriteBatch`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WriteOp<'a> {
+    /// Sets a key to a value.
+    Put(&'a [u8], &'a [u8]),
+    /// Sets a key to a value expiring at a time, in microseconds since the Unix epoch.
+    PutWithExpiry(&'a [u8], &'a [u8], u64),
+    /// Deletes a key.
+    Delete(&'a [u8]),
+    /// Deletes a key that was written at most once, see `WriteBatch::single_delete`.
+    SingleDelete(&'a [u8]),
+    /// Deletes the keys from the start of the range, inclusive, to its end, exclusive.
+    DeleteRange(&'a [u8], &'a [u8]),
+}
+
+/// An iterator over all the operations in a `WriteBatch`, in order.
+pub struct WriteOpIterator<'a> {
     payload: &'a [u8],
     pos: usize,
 }
 
+impl<'a> WriteOpIterator<'a> {
+    pub fn from_payload(bytes: &'a [u8]) -> WriteOpIterator<'a> {
+        WriteOpIterator {
+            payload: bytes,
+            pos: entries_offset(bytes),
+        }
+    }
+
+    fn read_len(&mut self) -> u32 {
+        let len = u32::from_be_bytes(self.payload[self.pos..self.pos + 4].try_into().unwrap());
+        self.pos += 4;
+        len
+    }
+
+    fn read_bytes(&mut self, len: usize) -> &'a [u8] {
+        let bytes = &self.payload[self.pos..self.pos + len];
+        self.pos += len;
+        bytes
+    }
+}
+
+impl<'a> Iterator for WriteOpIterator<'a> {
+    type Item = WriteOp<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.payload.len() {
+            return None;
+        }
+
+        let key_len = self.read_len();
+        let key = self.read_bytes((key_len & !LENGTH_FLAGS) as usize);
+        let value_len = self.read_len() as usize;
+        let value = self.read_bytes(value_len);
+        Some(if key_len & RANGE_DELETION_FLAG != 0 {
+            WriteOp::DeleteRange(key, value)
+        } else if key_len & SINGLE_DELETION_FLAG != 0 {
+            WriteOp::SingleDelete(key)
+        } else if key_len & EXPIRY_FLAG != 0 {
+            let expires_at = u64::from_be_bytes(self.read_bytes(8).try_into().unwrap());
+            WriteOp::PutWithExpiry(key, value, expires_at)
+        } else if value.is_empty() {
+            WriteOp::Delete(key)
+        } else {
+            WriteOp::Put(key, value)
+        })
+    }
+}
+
+pub struct WriteBatchIterator<'a> {
+    ops: WriteOpIterator<'a>,
+}
+
 impl<'a> WriteBatchIterator<'a> {
     pub fn from_payload(bytes: &'a [u8]) -> WriteBatchIterator<'a> {
         WriteBatchIterator {
-            payload: bytes,
-            pos: HEADER_SIZE,
+            ops: WriteOpIterator::from_payload(bytes),
         }
     }
 }
 
-/// An iterator over the entries in a `WriteBatch`.
+/// An iterator over the point entries in a `WriteBatch`, skipping range deletions.
 ///
 /// This iterator yields key-value pairs, where the key is a byte slice and the value is an optional byte slice.
 /// If the value is `None`, it indicates a deletion entry.
@@ -52,27 +289,14 @@ impl<'a> Iterator for WriteBatchIterator<'a> {
     ///
     /// Returns `None` if there are no more entries in the `WriteBatch`.
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pos == self.payload.len() {
-            return None;
-        }
-
-        let key_len =
-            u32::from_be_bytes(self.payload[self.pos..self.pos + 4].try_into().unwrap()) as usize;
-        self.pos += 4;
-
-        let key = &self.payload[self.pos..self.pos + key_len];
-        self.pos += key_len;
-
-        let value_len =
-            u32::from_be_bytes(self.payload[self.pos..self.pos + 4].try_into().unwrap()) as usize;
-        self.pos += 4;
-
-        if value_len == 0 {
-            return Some((key, None));
-        } else {
-            let value = &self.payload[self.pos..self.pos + value_len];
-            self.pos += value_len;
-            return Some((key, Some(value)));
+        loop {
+            match self.ops.next()? {
+                WriteOp::Put(key, value) | WriteOp::PutWithExpiry(key, value, _) => {
+                    return Some((key, Some(value)))
+                }
+                WriteOp::Delete(key) | WriteOp::SingleDelete(key) => return Some((key, None)),
+                WriteOp::DeleteRange(..) => continue,
+            }
         }
     }
 }
@@ -92,11 +316,139 @@ impl Default for WriteBatch {
 impl WriteBatch {
     /// Creates a new empty write batch.
     pub fn new() -> WriteBatch {
-        WriteBatch {
-            entries: vec![0; HEADER_SIZE],
+        let mut entries = vec![0; HEADER_SIZE];
+        entries[VERSION_OFFSET] = FORMAT_VERSION;
+        WriteBatch { entries }
+    }
+
+    /// Attaches metadata to the batch, replacing any metadata set before.
+    pub fn set_metadata(&mut self, metadata: BatchMetadata) {
+        let header_size = header_size(&self.entries);
+        if self.entries[FLAGS_OFFSET] & FLAG_HAS_METADATA != 0 {
+            self.entries[header_size..header_size + METADATA_SIZE]
+                .copy_from_slice(&metadata.to_bytes());
+        } else {
+            self.entries
+                .splice(header_size..header_size, metadata.to_bytes());
+            self.entries[FLAGS_OFFSET] |= FLAG_HAS_METADATA;
         }
     }
 
+    /// Returns the metadata attached to the batch, if any.
+    pub fn metadata(&self) -> Option<BatchMetadata> {
+        if self.entries[FLAGS_OFFSET] & FLAG_HAS_METADATA == 0 {
+            return None;
+        }
+        let header_size = header_size(&self.entries);
+        Some(BatchMetadata::from_bytes(
+            &self.entries[header_size..header_size + METADATA_SIZE],
+        ))
+    }
+
+    /// Creates a checkpoint batch carrying `checkpoint`.
+    pub fn new_checkpoint(checkpoint: Checkpoint) -> WriteBatch {
+        let mut wb = WriteBatch::new();
+        wb.entries[FLAGS_OFFSET] |= FLAG_CHECKPOINT;
+        wb.entries
+            .extend_from_slice(&checkpoint.sequence.to_be_bytes());
+        wb.entries
+            .extend_from_slice(&checkpoint.rolling_crc.to_be_bytes());
+        wb.entries
+            .extend_from_slice(&checkpoint.key_count.to_be_bytes());
+        wb
+    }
+
+    /// Returns the checkpoint carried by the batch, if it is a checkpoint batch.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        if self.entries[FLAGS_OFFSET] & FLAG_CHECKPOINT == 0 {
+            return None;
+        }
+        let header_size = header_size(&self.entries);
+        let body = &self.entries[header_size..header_size + CHECKPOINT_SIZE];
+        Some(Checkpoint {
+            sequence: u64::from_be_bytes(body[0..8].try_into().unwrap()),
+            rolling_crc: u32::from_be_bytes(body[8..12].try_into().unwrap()),
+            key_count: u64::from_be_bytes(body[12..20].try_into().unwrap()),
+        })
+    }
+
+    /// Validates the structure of the batch.
+    ///
+    /// Checks that every entry lies within the batch and that the number of
+    /// entries matches the count recorded in the header.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::Corruption)` if the batch is malformed.
+    pub fn validate(&self) -> Result<()> {
+        let corruption = |reason: &str| Err(Error::Corruption(format!("write batch {}", reason)));
+        if self.entries.len() < HEADER_SIZE_V0 || self.entries.len() < header_size(&self.entries) {
+            return corruption("is smaller than its header");
+        }
+        if self.entries[FLAGS_OFFSET] & FLAG_CHECKPOINT != 0 {
+            if self.entries.len() != header_size(&self.entries) + CHECKPOINT_SIZE
+                || self.count() != 0
+            {
+                return corruption("has a malformed checkpoint");
+            }
+            return Ok(());
+        }
+
+        let read_len = |pos: usize| -> Option<usize> {
+            let bytes = self.entries.get(pos..pos + 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let mut pos = entries_offset(&self.entries);
+        if pos > self.entries.len() {
+            return corruption("is smaller than its metadata");
+        }
+        let mut entry_count: u64 = 0;
+        while pos < self.entries.len() {
+            let Some(key_len) = read_len(pos) else {
+                return corruption("has a truncated key length");
+            };
+            pos += 4 + (key_len & !(LENGTH_FLAGS as usize));
+            let Some(value_len) = read_len(pos) else {
+                return corruption("has a truncated key or value length");
+            };
+            pos += 4 + value_len;
+            if key_len & EXPIRY_FLAG as usize != 0 {
+                pos += 8;
+            }
+            if pos > self.entries.len() {
+                return corruption("has a truncated value");
+            }
+            entry_count += 1;
+        }
+        if entry_count != u64::from(self.count()) {
+            return corruption(&format!(
+                "contains {} entries but its header records {}",
+                entry_count,
+                self.count()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the format version the batch was written with.
+    pub fn version(&self) -> u8 {
+        self.entries[VERSION_OFFSET]
+    }
+
+    /// Returns the sequence number recorded in the header, or 0 if none was assigned.
+    pub fn sequence(&self) -> u64 {
+        u64::from_be_bytes(
+            self.entries[SEQUENCE_OFFSET..SEQUENCE_OFFSET + 8]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// Records `sequence` in the header.
+    pub fn set_sequence(&mut self, sequence: u64) {
+        self.entries[SEQUENCE_OFFSET..SEQUENCE_OFFSET + 8].copy_from_slice(&sequence.to_be_bytes());
+    }
+
     /// Returns the number of write operations in the batch.
     pub fn count(&self) -> u32 {
         u32::from_be_bytes(
@@ -121,6 +473,21 @@ impl WriteBatch {
         self.insert_or_update(key, &[]);
     }
 
+    /// Adds a delete operation to the batch for a key that was written at most
+    /// once since it was last deleted.
+    ///
+    /// Reads see it as a delete. A compaction can then drop the tombstone
+    /// together with the value it deletes, instead of carrying the tombstone
+    /// down to the last level. If the key was written more than once, older
+    /// values may reappear.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to delete.
+    pub fn single_delete(&mut self, key: &[u8]) {
+        self.push_entry(key, &[], SINGLE_DELETION_FLAG);
+    }
+
     /// Adds an insert or update operation to the batch for the given key-value pair.
     ///
     /// # Arguments
@@ -128,17 +495,129 @@ impl WriteBatch {
     /// * `key` - The key to insert or update.
     /// * `value` - The value to associate with the key.
     pub fn insert_or_update(&mut self, key: &[u8], value: &[u8]) {
+        self.push_entry(key, value, 0);
+    }
+
+    /// Adds an insert or update operation to the batch for the given key-value
+    /// pair, which expires at the given time. Reads stop returning the key
+    /// once it expires, as if it was deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to insert or update.
+    /// * `value` - The value to associate with the key. An empty value deletes
+    ///   the key, which never expires.
+    /// * `expires_at_micros` - The time the value expires at, in microseconds
+    ///   since the Unix epoch.
+    pub fn insert_or_update_with_expiry(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        expires_at_micros: u64,
+    ) {
+        if value.is_empty() {
+            return self.delete(key);
+        }
+        self.push_entry(key, value, EXPIRY_FLAG);
+        self.entries
+            .extend_from_slice(&expires_at_micros.to_be_bytes());
+    }
+
+    /// Adds a delete operation to the batch for every key from `start`,
+    /// inclusive, to `end`, exclusive. The range is recorded as a single
+    /// entry, no matter how many keys it covers.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The first key to delete.
+    /// * `end` - The key right after the last one to delete.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.push_entry(start, end, RANGE_DELETION_FLAG);
+    }
+
+    /// Appends an entry, with `flags` set in its key length.
+    fn push_entry(&mut self, key: &[u8], value: &[u8], flags: u32) {
+        let key_len = u32::try_from(key.len()).unwrap();
+        assert!(key_len & LENGTH_FLAGS == 0, "key is too long");
         self.entries
-            .extend_from_slice(&u32::try_from(key.len()).unwrap().to_be_bytes());
+            .extend_from_slice(&(key_len | flags).to_be_bytes());
         self.entries.extend_from_slice(key);
         self.entries
             .extend_from_slice(&u32::try_from(value.len()).unwrap().to_be_bytes());
-        if value.len() > 0 {
+        if !value.is_empty() {
             self.entries.extend_from_slice(value);
         }
         self.increment_count();
     }
 
+    /// Returns the number of point operations in the batch that are
+    /// overwritten by a later operation on the same key.
+    pub fn duplicate_count(&self) -> usize {
+        let unique_keys: HashSet<&[u8]> = self.iter().map(|(key, _)| key).collect();
+        self.iter().count() - unique_keys.len()
+    }
+
+    /// Collapses the batch so that it holds a single operation per key.
+    ///
+    /// Since operations on the same key are applied in order, only the last one
+    /// is kept (last write wins). Range deletions are all kept. The relative
+    /// order of the remaining operations is preserved, and so is the batch metadata.
+    ///
+    /// # Returns
+    ///
+    /// The number of operations that were dropped.
+    pub fn dedup(&mut self) -> usize {
+        let mut last_index: HashMap<&[u8], usize> = HashMap::new();
+        let mut point_count = 0;
+        for (index, op) in self.ops().enumerate() {
+            if let WriteOp::Put(key, _)
+            | WriteOp::PutWithExpiry(key, ..)
+            | WriteOp::Delete(key)
+            | WriteOp::SingleDelete(key) = op
+            {
+                last_index.insert(key, index);
+                point_count += 1;
+            }
+        }
+        let duplicates = point_count - last_index.len();
+        if duplicates == 0 {
+            return 0;
+        }
+
+        let mut deduped = WriteBatch::new();
+        if let Some(metadata) = self.metadata() {
+            deduped.set_metadata(metadata);
+        }
+        for (index, op) in self.ops().enumerate() {
+            match op {
+                WriteOp::Put(key, value) if last_index[key] == index => {
+                    deduped.insert_or_update(key, value)
+                }
+                WriteOp::PutWithExpiry(key, value, expires_at) if last_index[key] == index => {
+                    deduped.insert_or_update_with_expiry(key, value, expires_at)
+                }
+                WriteOp::Delete(key) if last_index[key] == index => deduped.delete(key),
+                WriteOp::SingleDelete(key) if last_index[key] == index => {
+                    deduped.single_delete(key)
+                }
+                WriteOp::DeleteRange(start, end) => deduped.delete_range(start, end),
+                _ => {}
+            }
+        }
+        *self = deduped;
+        duplicates
+    }
+
+    /// Appends the operations of `other` to the batch, after its own.
+    ///
+    /// The metadata of `other` is not carried over: the batch keeps its own.
+    pub fn append(&mut self, other: &WriteBatch) {
+        self.entries
+            .extend_from_slice(&other.entries[entries_offset(&other.entries)..]);
+        let count = self.count() + other.count();
+        self.entries[COUNT_OFFSET..COUNT_OFFSET + 4].copy_from_slice(&count.to_be_bytes());
+    }
+
     /// Returns the total length of the write batch in bytes.
     pub fn len(&self) -> usize {
         self.entries.len()
@@ -154,6 +633,7 @@ impl WriteBatch {
         // Clear the entries vector and reset the count to 0.
         self.entries.resize(HEADER_SIZE, 0);
         self.entries.copy_from_slice(&[0; HEADER_SIZE]);
+        self.entries[VERSION_OFFSET] = FORMAT_VERSION;
     }
 
     /// Returns the write batch as a byte slice.
@@ -161,12 +641,120 @@ impl WriteBatch {
         &self.entries
     }
 
-    /// Returns an iterator over the write operations in the batch.
-    pub fn iter(&self) -> WriteBatchIterator {
-        WriteBatchIterator {
-            payload: &self.entries,
-            pos: HEADER_SIZE,
+    /// Returns the payload to be written to the WAL for this batch.
+    ///
+    /// Batches of at least `threshold` bytes are compressed with the given
+    /// `compression` and flagged as such in the header, unless compression
+    /// does not make the payload any smaller. The checksum in the header of
+    /// the payload covers the payload as written, after compression.
+    ///
+    /// # Arguments
+    ///
+    /// * `compression` - The compression to apply.
+    /// * `threshold` - The minimum batch size in bytes eligible for compression.
+    pub fn to_wal_payload(
+        &self,
+        compression: CompressionType,
+        threshold: usize,
+    ) -> Result<Vec<u8>> {
+        if self.version() == 0 {
+            // Batches recovered from old logs are rewritten in the current format.
+            let mut upgraded = self.clone();
+            upgraded.entries.splice(
+                HEADER_SIZE_V0..HEADER_SIZE_V0,
+                [0; HEADER_SIZE - HEADER_SIZE_V0],
+            );
+            upgraded.entries[VERSION_OFFSET] = FORMAT_VERSION;
+            return upgraded.to_wal_payload(compression, threshold);
+        }
+
+        let mut payload = self.compressed_payload(compression, threshold)?;
+        let checksum = compute_checksum(&payload);
+        payload[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_be_bytes());
+        Ok(payload)
+    }
+
+    fn compressed_payload(
+        &self,
+        compression: CompressionType,
+        threshold: usize,
+    ) -> Result<Vec<u8>> {
+        if compression == CompressionType::None || self.len() < threshold {
+            return Ok(self.entries.clone());
+        }
+
+        let body = &self.entries[HEADER_SIZE..];
+        let mut payload = vec![0; HEADER_SIZE + snap::raw::max_compress_len(body.len())];
+        let compressed_len =
+            snap::raw::Encoder::new().compress(body, &mut payload[HEADER_SIZE..])?;
+        if compressed_len >= body.len() {
+            return Ok(self.entries.clone());
+        }
+
+        payload.truncate(HEADER_SIZE + compressed_len);
+        payload[..HEADER_SIZE].copy_from_slice(&self.entries[..HEADER_SIZE]);
+        payload[FLAGS_OFFSET] |= FLAG_COMPRESSED;
+        Ok(payload)
+    }
+
+    /// Checks that the batch was written with a format version this build understands.
+    fn check_version(&self) -> Result<()> {
+        if self.version() > FORMAT_VERSION {
+            return Err(Error::IncompatibleVersion {
+                found: self.version(),
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Verifies the checksum of a batch read from the WAL, then clears it.
+    ///
+    /// The checksum only protects the serialized payload, so it is cleared to make
+    /// the recovered batch identical to the one that was originally written.
+    /// Version 0 batches have no checksum.
+    fn verify_checksum(&mut self) -> Result<()> {
+        if self.version() == 0 {
+            return Ok(());
+        }
+        if self.entries.len() < HEADER_SIZE {
+            return Err(Error::Corruption(
+                "write batch is smaller than its header".to_string(),
+            ));
+        }
+        let checksum = &mut self.entries[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4];
+        let expected = u32::from_be_bytes((&*checksum).try_into().unwrap());
+        checksum.fill(0);
+        if compute_checksum(&self.entries) != expected {
+            return Err(Error::Corruption(
+                "write batch checksum mismatch".to_string(),
+            ));
         }
+        Ok(())
+    }
+
+    /// Decompresses the entries in place if the header marks them as compressed.
+    fn decompress(&mut self) -> Result<()> {
+        if self.entries[FLAGS_OFFSET] & FLAG_COMPRESSED == 0 {
+            return Ok(());
+        }
+
+        let header_size = header_size(&self.entries);
+        let body = snap::raw::Decoder::new().decompress_vec(&self.entries[header_size..])?;
+        self.entries.truncate(header_size);
+        self.entries[FLAGS_OFFSET] &= !FLAG_COMPRESSED;
+        self.entries.extend_from_slice(&body);
+        Ok(())
+    }
+
+    /// Returns an iterator over the point operations in the batch, skipping range deletions.
+    pub fn iter(&self) -> WriteBatchIterator<'_> {
+        WriteBatchIterator::from_payload(&self.entries)
+    }
+
+    /// Returns an iterator over all the operations in the batch, in order.
+    pub fn ops(&self) -> WriteOpIterator<'_> {
+        WriteOpIterator::from_payload(&self.entries)
     }
 }
 
@@ -175,6 +763,12 @@ pub struct WriteBatchBuilder {
     ready: bool,
 }
 
+impl Default for WriteBatchBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WriteBatchBuilder {
     pub fn new() -> WriteBatchBuilder {
         let mut wb = WriteBatch::new();
@@ -182,18 +776,45 @@ impl WriteBatchBuilder {
         WriteBatchBuilder { wb, ready: false }
     }
 
-    pub fn accumulate_record(&mut self, record: &LogRecord) -> crate::error::Result<()> {
-        record.validate_crc()?;
+    /// Adds a record to the batch being assembled. The checksum of the record
+    /// is validated by the log reader it was read with.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Err(Error::Corruption)` if the record does not continue the
+    /// batch being assembled, e.g. because records were skipped after a
+    /// corruption. The caller should then `consume` the partial batch.
+    pub fn accumulate_record(&mut self, record: &LogRecord) -> Result<()> {
+        let starts_batch = matches!(record.rtype, RecordType::First | RecordType::Full);
+        if starts_batch != self.wb.entries.is_empty() {
+            return Err(Error::Corruption(format!(
+                "unexpected {:?} record after {} bytes of a batch",
+                record.rtype,
+                self.wb.entries.len()
+            )));
+        }
         match record.rtype {
             RecordType::First | RecordType::Middle => {
                 self.wb.entries.extend_from_slice(record.payload);
             }
             RecordType::Full | RecordType::Last => {
                 self.wb.entries.extend_from_slice(record.payload);
+                // The header is read below, starting with the version
+                if self.wb.entries.len() < HEADER_SIZE_V0 {
+                    return Err(Error::Corruption(format!(
+                        "write batch of {} bytes is smaller than its header",
+                        self.wb.entries.len()
+                    )));
+                }
+                self.wb.check_version()?;
+                self.wb.verify_checksum()?;
+                self.wb.decompress()?;
                 self.ready = true
             }
             RecordType::None => {
-                assert!(false, "unexpected record type");
+                return Err(Error::Corruption(
+                    "record of an unknown type in a batch".to_string(),
+                ));
             }
         }
         Ok(())
@@ -208,13 +829,21 @@ impl WriteBatchBuilder {
         self.ready
     }
 
+    /// Returns whether records of a batch have been accumulated but the batch
+    /// is not complete yet.
+    pub fn in_progress(&self) -> bool {
+        !self.ready && !self.wb.entries.is_empty()
+    }
+
     pub fn get_write_batch(&self) -> &WriteBatch {
         assert!(self.is_ready());
         &self.wb
     }
 }
 
+#[cfg(test)]
 mod tests {
+    use crate::log_record::{LogRecord, RecordType};
     use crate::write_batch::COUNT_OFFSET;
 
     #[test]
@@ -292,9 +921,11 @@ mod tests {
             super::HEADER_SIZE + 4 + key.len() + 4 + value.len()
         );
         assert_eq!(&bytes[COUNT_OFFSET..COUNT_OFFSET + 4], 1u32.to_be_bytes());
+        assert_eq!(bytes[super::FLAGS_OFFSET], 0);
+        assert_eq!(bytes[super::VERSION_OFFSET], super::FORMAT_VERSION);
         assert_eq!(
-            &bytes[COUNT_OFFSET + 4..super::HEADER_SIZE],
-            &[0; super::HEADER_SIZE - 4]
+            &bytes[super::VERSION_OFFSET + 1..super::HEADER_SIZE],
+            &[0; super::HEADER_SIZE - super::VERSION_OFFSET - 1]
         );
         assert_eq!(
             &bytes[super::HEADER_SIZE..super::HEADER_SIZE + 4],
@@ -310,4 +941,398 @@ mod tests {
         );
         assert_eq!(&bytes[super::HEADER_SIZE + 4 + key.len() + 4..], value);
     }
+
+    #[test]
+    fn compressed_wal_payload() {
+        let mut wb = super::WriteBatch::new();
+        let value = vec![b'x'; 1024];
+        for i in 0..64i32 {
+            wb.insert_or_update(&i.to_be_bytes(), &value);
+        }
+
+        // Batches below the threshold are left uncompressed.
+        let payload = wb
+            .to_wal_payload(super::CompressionType::Snappy, wb.len() + 1)
+            .unwrap();
+        assert_eq!(
+            payload[super::HEADER_SIZE..],
+            wb.as_bytes()[super::HEADER_SIZE..]
+        );
+
+        let payload = wb
+            .to_wal_payload(
+                super::CompressionType::Snappy,
+                super::DEFAULT_COMPRESSION_THRESHOLD,
+            )
+            .unwrap();
+        assert!(payload.len() < wb.len() / 10);
+
+        // The builder transparently decompresses the payload.
+        let mut builder = super::WriteBatchBuilder::new();
+        let record = LogRecord::new(RecordType::Full, &payload);
+        builder.accumulate_record(&record).unwrap();
+        let recovered = builder.get_write_batch();
+        assert_eq!(recovered.as_bytes(), wb.as_bytes());
+        assert_eq!(recovered.count(), 64);
+    }
+
+    #[test]
+    fn header_sequence_and_checksum() {
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update(b"key", b"value");
+        assert_eq!(wb.version(), super::FORMAT_VERSION);
+        assert_eq!(wb.sequence(), 0);
+        wb.set_sequence(42);
+        assert_eq!(wb.sequence(), 42);
+        assert_eq!(wb.count(), 1);
+
+        let payload = wb.to_wal_payload(super::CompressionType::None, 0).unwrap();
+        let mut builder = super::WriteBatchBuilder::new();
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::Full, &payload))
+            .unwrap();
+        assert_eq!(builder.get_write_batch().as_bytes(), wb.as_bytes());
+
+        // Any change to the payload is caught by the checksum
+        let mut corrupted = payload.clone();
+        corrupted[super::SEQUENCE_OFFSET] ^= 1;
+        let mut builder = super::WriteBatchBuilder::new();
+        assert!(matches!(
+            builder.accumulate_record(&LogRecord::new(RecordType::Full, &corrupted)),
+            Err(crate::error::Error::Corruption(_))
+        ));
+    }
+
+    #[test]
+    fn read_version_0_batches() {
+        // Version 0 headers are 16 bytes long and have no sequence or checksum
+        let mut payload = vec![0; super::HEADER_SIZE_V0];
+        payload[COUNT_OFFSET..COUNT_OFFSET + 4].copy_from_slice(&1u32.to_be_bytes());
+        payload[super::FLAGS_OFFSET] = super::FLAG_HAS_METADATA;
+        let metadata = super::BatchMetadata {
+            write_time_micros: 1,
+            origin_id: 2,
+            ttl_secs: 3,
+        };
+        payload.extend_from_slice(&metadata.to_bytes());
+        for field in [&b"key"[..], b"value"] {
+            payload.extend_from_slice(&u32::try_from(field.len()).unwrap().to_be_bytes());
+            payload.extend_from_slice(field);
+        }
+
+        let mut builder = super::WriteBatchBuilder::new();
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::Full, &payload))
+            .unwrap();
+        let wb = builder.get_write_batch();
+        wb.validate().unwrap();
+        assert_eq!(wb.version(), 0);
+        assert_eq!(wb.sequence(), 0);
+        assert_eq!(wb.metadata(), Some(metadata));
+        let entries: Vec<_> = wb.iter().collect();
+        assert_eq!(entries, vec![(&b"key"[..], Some(&b"value"[..]))]);
+
+        // Rewriting a version 0 batch upgrades it to the current format
+        let payload = wb.to_wal_payload(super::CompressionType::None, 0).unwrap();
+        let mut builder = super::WriteBatchBuilder::new();
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::Full, &payload))
+            .unwrap();
+        let upgraded = builder.get_write_batch();
+        assert_eq!(upgraded.version(), super::FORMAT_VERSION);
+        assert_eq!(upgraded.metadata(), Some(metadata));
+        assert_eq!(upgraded.iter().collect::<Vec<_>>(), entries);
+    }
+
+    #[test]
+    fn reject_newer_format_version() {
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update(b"key", b"value");
+        let mut payload = wb.as_bytes().to_vec();
+        payload[super::VERSION_OFFSET] = super::FORMAT_VERSION + 1;
+
+        let mut builder = super::WriteBatchBuilder::new();
+        let record = LogRecord::new(RecordType::Full, &payload);
+        match builder.accumulate_record(&record) {
+            Err(crate::error::Error::IncompatibleVersion { found, supported }) => {
+                assert_eq!(found, super::FORMAT_VERSION + 1);
+                assert_eq!(supported, super::FORMAT_VERSION);
+            }
+            _ => panic!("Expected IncompatibleVersion error"),
+        }
+        assert!(!builder.is_ready());
+    }
+
+    #[test]
+    fn reject_malformed_records() {
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update(b"key", b"value");
+        let payload = wb.as_bytes().to_vec();
+        let mut builder = super::WriteBatchBuilder::new();
+
+        // Too short to hold the format version
+        for len in [0, 3, super::HEADER_SIZE_V0 - 1] {
+            let record = LogRecord::new(RecordType::Full, &payload[..len]);
+            assert!(matches!(
+                builder.accumulate_record(&record),
+                Err(crate::error::Error::Corruption(_))
+            ));
+            builder.consume();
+        }
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::First, &payload[..2]))
+            .unwrap();
+        let record = LogRecord::new(RecordType::Last, &payload[2..4]);
+        assert!(matches!(
+            builder.accumulate_record(&record),
+            Err(crate::error::Error::Corruption(_))
+        ));
+        builder.consume();
+
+        // A record without a type does not belong in a batch
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::First, &payload[..4]))
+            .unwrap();
+        let record = LogRecord::new(RecordType::None, &payload[4..]);
+        assert!(matches!(
+            builder.accumulate_record(&record),
+            Err(crate::error::Error::Corruption(_))
+        ));
+        assert!(!builder.is_ready());
+    }
+
+    #[test]
+    fn metadata() {
+        let mut wb = super::WriteBatch::new();
+        assert_eq!(wb.metadata(), None);
+        wb.insert_or_update(b"key", b"value");
+
+        let metadata = super::BatchMetadata {
+            write_time_micros: 1_000_000,
+            origin_id: 42,
+            ttl_secs: 10,
+        };
+        wb.set_metadata(metadata);
+        wb.delete(b"key");
+        assert_eq!(wb.metadata(), Some(metadata));
+        assert!(!metadata.is_expired(10_999_999));
+        assert!(metadata.is_expired(11_000_000));
+
+        // The metadata survives the round trip through the log and doesn't
+        // show up as an entry.
+        let payload = wb.to_wal_payload(super::CompressionType::None, 0).unwrap();
+        let mut builder = super::WriteBatchBuilder::new();
+        let record = LogRecord::new(RecordType::Full, &payload);
+        builder.accumulate_record(&record).unwrap();
+        let recovered = builder.get_write_batch();
+        assert_eq!(recovered.metadata(), Some(metadata));
+        let entries: Vec<_> = recovered.iter().collect();
+        assert_eq!(
+            entries,
+            vec![(&b"key"[..], Some(&b"value"[..])), (&b"key"[..], None)]
+        );
+
+        wb.clear();
+        assert_eq!(wb.metadata(), None);
+        assert_eq!(wb.iter().count(), 0);
+    }
+
+    #[test]
+    fn checkpoint() {
+        let checkpoint = super::Checkpoint {
+            sequence: 7,
+            rolling_crc: 0xdeadbeef,
+            key_count: 3,
+        };
+        let wb = super::WriteBatch::new_checkpoint(checkpoint);
+        assert_eq!(wb.checkpoint(), Some(checkpoint));
+        assert_eq!(wb.count(), 0);
+        assert_eq!(wb.iter().count(), 0);
+        wb.validate().unwrap();
+
+        assert_eq!(super::WriteBatch::new().checkpoint(), None);
+    }
+
+    #[test]
+    fn validate() {
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update(b"key", b"value");
+        wb.delete(b"key");
+        wb.validate().unwrap();
+
+        // A count that doesn't match the entries
+        let mut bytes = wb.as_bytes().to_vec();
+        bytes[COUNT_OFFSET..COUNT_OFFSET + 4].copy_from_slice(&3u32.to_be_bytes());
+        let corrupted = super::WriteBatch { entries: bytes };
+        corrupted.validate().expect_err("Expected a count mismatch");
+
+        // A truncated value
+        let mut bytes = wb.as_bytes().to_vec();
+        bytes.truncate(bytes.len() - 5);
+        let corrupted = super::WriteBatch { entries: bytes };
+        corrupted
+            .validate()
+            .expect_err("Expected a truncated batch");
+    }
+
+    #[test]
+    fn dedup() {
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update(b"a", b"1");
+        wb.insert_or_update(b"b", b"1");
+        wb.insert_or_update(b"a", b"2");
+        wb.delete(b"b");
+        wb.insert_or_update(b"c", b"1");
+        wb.delete(b"a");
+        wb.insert_or_update(b"a", b"3");
+        assert_eq!(wb.duplicate_count(), 4);
+
+        assert_eq!(wb.dedup(), 4);
+        assert_eq!(wb.count(), 3);
+        assert_eq!(wb.duplicate_count(), 0);
+        let entries: Vec<_> = wb.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                (&b"b"[..], None),
+                (&b"c"[..], Some(&b"1"[..])),
+                (&b"a"[..], Some(&b"3"[..]))
+            ]
+        );
+
+        assert_eq!(wb.dedup(), 0);
+    }
+
+    #[test]
+    fn append() {
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update(b"a", b"1");
+        let mut other = super::WriteBatch::new();
+        other.set_metadata(super::BatchMetadata {
+            origin_id: 7,
+            ..Default::default()
+        });
+        other.delete(b"a");
+        other.delete_range(b"b", b"c");
+
+        wb.append(&other);
+        wb.validate().unwrap();
+        assert_eq!(wb.count(), 3);
+        assert_eq!(wb.metadata(), None);
+        assert_eq!(
+            wb.iter().collect::<Vec<_>>(),
+            [(&b"a"[..], Some(&b"1"[..])), (&b"a"[..], None)]
+        );
+    }
+
+    #[test]
+    fn single_delete() {
+        use super::WriteOp;
+
+        let mut wb = super::WriteBatch::new();
+        wb.single_delete(b"a");
+        wb.insert_or_update(b"b", b"2");
+        wb.single_delete(b"b");
+        wb.validate().unwrap();
+
+        let payload = wb.to_wal_payload(super::CompressionType::None, 0).unwrap();
+        let mut builder = super::WriteBatchBuilder::new();
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::Full, &payload))
+            .unwrap();
+        let recovered = builder.get_write_batch();
+        recovered.validate().unwrap();
+        assert_eq!(
+            recovered.ops().collect::<Vec<_>>(),
+            vec![
+                WriteOp::SingleDelete(b"a"),
+                WriteOp::Put(b"b", b"2"),
+                WriteOp::SingleDelete(b"b"),
+            ]
+        );
+        // Point iteration sees single deletions as deletes
+        assert_eq!(recovered.iter().next(), Some((&b"a"[..], None)));
+
+        assert_eq!(wb.dedup(), 1);
+        assert_eq!(
+            wb.ops().collect::<Vec<_>>(),
+            vec![WriteOp::SingleDelete(b"a"), WriteOp::SingleDelete(b"b")]
+        );
+    }
+
+    #[test]
+    fn delete_range() {
+        use super::WriteOp;
+
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update(b"a", b"1");
+        wb.delete_range(b"a", b"c");
+        wb.insert_or_update(b"b", b"2");
+        wb.delete_range(b"x", b"");
+        wb.insert_or_update(b"a", b"3");
+        assert_eq!(wb.count(), 5);
+        wb.validate().unwrap();
+
+        let payload = wb.to_wal_payload(super::CompressionType::None, 0).unwrap();
+        let mut builder = super::WriteBatchBuilder::new();
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::Full, &payload))
+            .unwrap();
+        let recovered = builder.get_write_batch();
+        recovered.validate().unwrap();
+        assert_eq!(
+            recovered.ops().collect::<Vec<_>>(),
+            vec![
+                WriteOp::Put(b"a", b"1"),
+                WriteOp::DeleteRange(b"a", b"c"),
+                WriteOp::Put(b"b", b"2"),
+                WriteOp::DeleteRange(b"x", b""),
+                WriteOp::Put(b"a", b"3"),
+            ]
+        );
+        // Point iteration skips the range deletions
+        assert_eq!(recovered.iter().count(), 3);
+
+        // Deduplication keeps every range deletion in place
+        assert_eq!(wb.duplicate_count(), 1);
+        assert_eq!(wb.dedup(), 1);
+        assert_eq!(
+            wb.ops().collect::<Vec<_>>(),
+            vec![
+                WriteOp::DeleteRange(b"a", b"c"),
+                WriteOp::Put(b"b", b"2"),
+                WriteOp::DeleteRange(b"x", b""),
+                WriteOp::Put(b"a", b"3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn insert_or_update_with_expiry() {
+        use super::WriteOp;
+
+        let mut wb = super::WriteBatch::new();
+        wb.insert_or_update_with_expiry(b"a", b"1", 42);
+        wb.insert_or_update_with_expiry(b"b", b"", 42);
+        wb.insert_or_update(b"c", b"3");
+        wb.validate().unwrap();
+
+        let payload = wb.to_wal_payload(super::CompressionType::None, 0).unwrap();
+        let mut builder = super::WriteBatchBuilder::new();
+        builder
+            .accumulate_record(&LogRecord::new(RecordType::Full, &payload))
+            .unwrap();
+        let recovered = builder.get_write_batch();
+        recovered.validate().unwrap();
+        // An empty value is a delete, which never expires
+        assert_eq!(
+            recovered.ops().collect::<Vec<_>>(),
+            vec![
+                WriteOp::PutWithExpiry(b"a", b"1", 42),
+                WriteOp::Delete(b"b"),
+                WriteOp::Put(b"c", b"3"),
+            ]
+        );
+        assert_eq!(recovered.iter().next(), Some((&b"a"[..], Some(&b"1"[..]))));
+    }
 }